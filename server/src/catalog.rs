@@ -19,7 +19,9 @@
 use std::sync::Arc;
 
 use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use once_cell::sync::Lazy;
 use relative_path::RelativePathBuf;
+use tokio::sync::Mutex;
 
 use crate::{
     catalog::manifest::Manifest,
@@ -35,6 +37,10 @@ pub mod snapshot;
 
 pub use manifest::create_from_parquet_file;
 
+// snapshots and manifests are read, changed and written back as a whole, changes
+// from the object store sync and from imports are applied one at a time
+static SNAPSHOT_LOCK: Lazy<Mutex<()>> = Lazy::new(Mutex::default);
+
 pub trait Snapshot {
     fn manifests(&self, time_predicates: &[PartialTimeFilter]) -> Vec<ManifestItem>;
 }
@@ -88,7 +94,7 @@ pub async fn update_snapshot(
                 NaiveDateTime::from_timestamp_millis(stats.min)
                     .unwrap()
                     .and_utc(),
-                NaiveDateTime::from_timestamp_millis(stats.min)
+                NaiveDateTime::from_timestamp_millis(stats.max)
                     .unwrap()
                     .and_utc(),
             ),
@@ -96,6 +102,7 @@ pub async fn update_snapshot(
         }
     }
 
+    let _guard = SNAPSHOT_LOCK.lock().await;
     // get current snapshot
     let mut meta = storage.get_snapshot(stream_name).await?;
     let manifests = &mut meta.manifest_list;
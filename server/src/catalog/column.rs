@@ -58,8 +58,10 @@ pub enum TypedStatistics {
 }
 
 impl TypedStatistics {
-    pub fn update(self, other: Self) -> Self {
-        match (self, other) {
+    // statistics of files written before a field was widened have the narrower type,
+    // integers are compared as floats and other mixed types have no statistics
+    pub fn update(self, other: Self) -> Option<Self> {
+        let stats = match (self, other) {
             (TypedStatistics::Bool(this), TypedStatistics::Bool(other)) => {
                 TypedStatistics::Bool(BoolType {
                     min: min(this.min, other.min),
@@ -84,8 +86,16 @@ impl TypedStatistics {
                     max: max(this.max, other.max),
                 })
             }
-            _ => panic!("Cannot update wrong types"),
-        }
+            (TypedStatistics::Int(this), TypedStatistics::Float(other))
+            | (TypedStatistics::Float(other), TypedStatistics::Int(this)) => {
+                TypedStatistics::Float(Float64Type {
+                    min: other.min.min(this.min as f64),
+                    max: other.max.max(this.max as f64),
+                })
+            }
+            _ => return None,
+        };
+        Some(stats)
     }
 
     pub fn min_max_as_scalar(self, datatype: &DataType) -> Option<(ScalarValue, ScalarValue)> {
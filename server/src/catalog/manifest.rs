@@ -130,7 +130,11 @@ fn sort_order(
 ) -> Vec<Vec<(String, SortOrder)>> {
     let mut sort_orders = Vec::new();
     for row_group in row_groups {
-        let sort_order = row_group.sorting_columns().unwrap();
+        // files that are not sorted, such as imported ones, have no sorting columns
+        let sort_order = row_group
+            .sorting_columns()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
         let sort_order = sort_order
             .iter()
             .map(|sort_order| {
@@ -171,7 +175,7 @@ fn column_statistics(
                 entry.compressed_size += col.compressed_size() as u64;
                 entry.uncompressed_size += col.uncompressed_size() as u64;
                 if let Some(other) = col.statistics().and_then(|stats| stats.try_into().ok()) {
-                    entry.stats = entry.stats.clone().map(|this| this.update(other));
+                    entry.stats = entry.stats.clone().and_then(|this| this.update(other));
                 }
             } else {
                 columns.insert(
@@ -17,16 +17,30 @@
 *
 */
 
+pub mod dead_letter;
+pub mod dedup;
+pub mod durability;
+pub mod evolution;
 pub mod format;
+pub mod nested;
+pub mod pattern;
+pub mod processor;
+pub mod static_schema;
+pub mod text_parser;
+pub mod time_partition;
 mod writer;
 
 use arrow_array::RecordBatch;
 use arrow_schema::{Field, Fields, Schema};
+use chrono::NaiveDateTime;
 use itertools::Itertools;
 
+use std::collections::HashSet;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::metadata;
+use crate::storage::watermark;
 
 use self::error::EventError;
 pub use self::writer::STREAM_WRITERS;
@@ -46,15 +60,66 @@ pub struct Event {
 
 // Events holds the schema related to a each event for a single log stream
 impl Event {
-    pub async fn process(self) -> Result<(), EventError> {
+    pub async fn process(mut self) -> Result<(), EventError> {
+        watermark::check().map_err(EventError::StagingFull)?;
+
+        // the values of the rows kept are released again if they fail to be written
+        let mut dedup_guard = None;
+        if let Some(dedup) = metadata::STREAM_INFO.dedup(&self.stream_name)? {
+            let (dropped, rb, guard) = loop {
+                match dedup::DEDUP.filter_batch(&self.stream_name, &dedup.field, self.rb)? {
+                    dedup::Rows::Filtered(dropped, rb, guard) => break (dropped, rb, guard),
+                    dedup::Rows::InProgress(rb) => {
+                        self.rb = rb;
+                        tokio::time::sleep(dedup::IN_PROGRESS_RETRY_INTERVAL).await;
+                    }
+                }
+            };
+            self.rb = rb;
+            dedup_guard = Some(guard);
+            if dropped > 0 {
+                log::debug!(
+                    "dropped {} duplicate events of stream {}",
+                    dropped,
+                    self.stream_name
+                );
+            }
+            if self.rb.num_rows() == 0 {
+                return Ok(());
+            }
+        }
+
         let key = get_schema_key(&self.rb.schema().fields);
         let num_rows = self.rb.num_rows() as u64;
 
+        // batches to write along with the time they are partitioned by,
+        // no time means that the batch is partitioned by the time of arrival
+        let batches = match metadata::STREAM_INFO.time_partition(&self.stream_name)? {
+            Some(time_partition) => {
+                let (rb, batches) = time_partition.partition_batch(self.rb)?;
+                self.rb = rb;
+                batches
+                    .into_iter()
+                    .map(|(time, rb)| (Some(time), rb))
+                    .collect()
+            }
+            None => vec![(None, self.rb.clone())],
+        };
+
         if self.is_first_event {
             commit_schema(&self.stream_name, self.rb.schema())?;
         }
 
-        Self::process_event(&self.stream_name, &key, self.rb.clone())?;
+        let mut files = HashSet::new();
+        for (time, rb) in batches {
+            files.insert(Self::process_event(&self.stream_name, &key, rb, time)?);
+        }
+
+        let durability = durability::of_stream(&self.stream_name)?;
+        durability::persist(&self.stream_name, durability, files).await?;
+        if let Some(guard) = dedup_guard {
+            guard.accept();
+        }
 
         metadata::STREAM_INFO.update_stats(
             &self.stream_name,
@@ -76,22 +141,23 @@ impl Event {
     }
 
     // event process all events after the 1st event. Concatenates record batches
-    // and puts them in memory store for each event.
+    // and puts them in memory store for each event. Returns the staging file written to
     fn process_event(
         stream_name: &str,
         schema_key: &str,
         rb: RecordBatch,
-    ) -> Result<(), EventError> {
-        STREAM_WRITERS.append_to_local(stream_name, schema_key, rb)?;
-        Ok(())
+        time: Option<NaiveDateTime>,
+    ) -> Result<PathBuf, EventError> {
+        Ok(STREAM_WRITERS.append_to_local(stream_name, schema_key, rb, time)?)
     }
 }
 
 pub fn get_schema_key(fields: &[Arc<Field>]) -> String {
-    // Fields must be sorted
+    // Fields must be sorted. Types are part of the key as fields can be widened
     let mut hasher = xxhash_rust::xxh3::Xxh3::new();
     for field in fields.iter().sorted_by_key(|v| v.name()) {
-        hasher.update(field.name().as_bytes())
+        hasher.update(field.name().as_bytes());
+        hasher.update(field.data_type().to_string().as_bytes());
     }
     let hash = hasher.digest();
     format!("{hash:x}")
@@ -105,7 +171,7 @@ pub fn commit_schema(stream_name: &str, schema: Arc<Schema>) -> Result<(), Event
         .expect("map has entry for this stream name")
         .schema;
     let current_schema = Schema::new(map.values().cloned().collect::<Fields>());
-    let schema = Schema::try_merge(vec![current_schema, schema.as_ref().clone()])?;
+    let schema = evolution::merge_schemas(vec![current_schema, schema.as_ref().clone()])?;
     map.clear();
     map.extend(schema.fields.iter().map(|f| (f.name().clone(), f.clone())));
     Ok(())
@@ -129,5 +195,11 @@ pub mod error {
         Arrow(#[from] ArrowError),
         #[error("ObjectStorage Error: {0}")]
         ObjectStorage(#[from] ObjectStorageError),
+        #[error("Invalid time partition field {0}: {1}")]
+        TimePartition(String, String),
+        #[error("Failed to persist events: {0}")]
+        Persist(String),
+        #[error("{0}")]
+        StagingFull(String),
     }
 }
@@ -0,0 +1,227 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::json::flatten_json_body_nested;
+use crate::validator;
+
+use super::nested::NestedObjects;
+use super::processor::{self, Processor};
+
+// stored in place of payloads that the processors of the stream could not be applied to
+const WITHHELD: &str =
+    "[WITHHELD] the processors of the stream could not be applied to this payload";
+
+// Stream that the rejected events of a stream are kept in, set with PUT /logstream/{name}/deadletter
+// {
+//     "stream": "apprejected"
+// }
+// Every request (or record of a partial ingest) that is rejected as invalid is
+// added to the dead letter stream as a record with the fields below, the
+// payload can be posted to the stream again once the cause is fixed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeadLetter {
+    pub stream: String,
+}
+
+impl DeadLetter {
+    pub fn validate(&self, stream_name: &str) -> Result<(), String> {
+        if self.stream == stream_name {
+            return Err("dead letter stream can not be the stream itself".to_owned());
+        }
+        validator::stream_name(&self.stream)
+            .map_err(|err| format!("invalid dead letter stream, {}", err))
+    }
+}
+
+// headers of a letter for events that do not come in an HTTP request, such as kafka messages
+pub fn source_headers(source: String) -> BTreeMap<String, String> {
+    BTreeMap::from([("source".to_owned(), source)])
+}
+
+// record of the dead letter stream for a rejected event
+pub struct Letter<'a> {
+    // stream the event was sent to
+    pub stream: &'a str,
+    pub reason: String,
+    // body of the request, or the record for records rejected on their own.
+    // Kept as a string, or base64 encoded if it is not valid UTF-8
+    pub payload: Vec<u8>,
+    pub headers: &'a BTreeMap<String, String>,
+    pub received_at: DateTime<Utc>,
+}
+
+impl Letter<'_> {
+    pub fn into_record(self) -> BTreeMap<String, Value> {
+        let mut record = BTreeMap::from([
+            ("stream".to_owned(), Value::String(self.stream.to_owned())),
+            ("reason".to_owned(), Value::String(self.reason)),
+            (
+                "headers".to_owned(),
+                Value::String(serde_json::to_string(self.headers).unwrap_or_default()),
+            ),
+            (
+                "received_at".to_owned(),
+                Value::String(
+                    self.received_at
+                        .to_rfc3339_opts(SecondsFormat::Millis, true),
+                ),
+            ),
+        ]);
+        let payload = match String::from_utf8(self.payload) {
+            Ok(payload) => payload,
+            Err(err) => {
+                record.insert(
+                    "payload_encoding".to_owned(),
+                    Value::String("base64".to_owned()),
+                );
+                STANDARD.encode(err.into_bytes())
+            }
+        };
+        record.insert("payload".to_owned(), Value::String(payload));
+        record
+    }
+}
+
+// The payload of a rejected event as processed by the processors of its stream, so that
+// fields the stream drops or redacts are not kept in the dead letter stream either.
+// The processors run again when the payload is replayed into the stream.
+// Payloads that are not json records can not be processed and are withheld
+pub fn process_payload(
+    processors: &[Processor],
+    nested: &NestedObjects,
+    payload: Vec<u8>,
+) -> Vec<u8> {
+    if processors.is_empty() {
+        return payload;
+    }
+    let process = |record: Value| match flatten_json_body_nested(record, nested) {
+        Ok(Value::Object(mut record)) => {
+            processor::apply(processors, &mut record);
+            Some(Value::Object(record))
+        }
+        _ => None,
+    };
+    let processed = match serde_json::from_slice(&payload) {
+        Ok(Value::Array(records)) => records
+            .into_iter()
+            .map(process)
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array),
+        Ok(record) => process(record),
+        Err(_) => None,
+    };
+    match processed.and_then(|value| serde_json::to_vec(&value).ok()) {
+        Some(payload) => payload,
+        None => WITHHELD.as_bytes().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{TimeZone, Utc};
+    use serde_json::{json, Value};
+
+    use super::{process_payload, DeadLetter, Letter};
+    use crate::event::nested::NestedObjects;
+    use crate::event::processor::Processor;
+
+    #[test]
+    fn dead_letter_streams() {
+        let dead_letter: DeadLetter =
+            serde_json::from_value(json!({ "stream": "apprejected" })).unwrap();
+        assert!(dead_letter.validate("app").is_ok());
+        assert!(dead_letter.validate("apprejected").is_err());
+
+        let dead_letter = DeadLetter {
+            stream: "1app".to_owned(),
+        };
+        assert!(dead_letter.validate("app").is_err());
+        assert!(serde_json::from_value::<DeadLetter>(json!({ "name": "app" })).is_err());
+    }
+
+    #[test]
+    fn letter_into_record() {
+        let headers = BTreeMap::from([("x-p-tag-env".to_owned(), "prod".to_owned())]);
+        let record = Letter {
+            stream: "app",
+            reason: "bad event".to_owned(),
+            payload: r#"{"a":"#.as_bytes().to_vec(),
+            headers: &headers,
+            received_at: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+        }
+        .into_record();
+
+        assert_eq!(record["stream"], "app");
+        assert_eq!(record["reason"], "bad event");
+        assert_eq!(record["payload"], r#"{"a":"#);
+        assert_eq!(record["received_at"], "2024-01-02T03:04:05.000Z");
+        let Value::String(stored) = &record["headers"] else {
+            panic!("headers are kept as a string")
+        };
+        assert_eq!(
+            serde_json::from_str::<Value>(stored).unwrap(),
+            json!({ "x-p-tag-env": "prod" })
+        );
+
+        let record = Letter {
+            stream: "app",
+            reason: "bad event".to_owned(),
+            payload: vec![0xff, 0x00],
+            headers: &headers,
+            received_at: Utc::now(),
+        }
+        .into_record();
+        assert_eq!(record["payload"], "/wA=");
+        assert_eq!(record["payload_encoding"], "base64");
+    }
+
+    #[test]
+    fn payloads_are_processed() {
+        let processors: Vec<Processor> = serde_json::from_value(json!([
+            { "type": "drop", "fields": ["password"] },
+            { "type": "redact", "fields": ["user_email"] }
+        ]))
+        .unwrap();
+        let nested = NestedObjects::default();
+
+        let payload = process_payload(
+            &processors,
+            &nested,
+            br#"[{"user":{"email":"a@b.io"},"password":"x","n":1}]"#.to_vec(),
+        );
+        assert_eq!(
+            serde_json::from_slice::<Value>(&payload).unwrap(),
+            json!([{ "user_email": "[REDACTED]", "n": 1 }])
+        );
+
+        let payload = process_payload(&processors, &nested, br#"{"password":"#.to_vec());
+        assert!(payload.starts_with(b"[WITHHELD]"));
+        let payload = process_payload(&[], &nested, br#"{"password":"#.to_vec());
+        assert_eq!(payload, br#"{"password":"#);
+    }
+}
@@ -0,0 +1,486 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use arrow_array::{Array, BooleanArray, RecordBatch};
+use arrow_schema::ArrowError;
+use arrow_select::filter::filter_record_batch;
+use bytes::Bytes;
+use datafusion::arrow::util::display::array_value_to_string;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::option::CONFIG;
+
+pub static DEDUP: Lazy<Deduplicator> = Lazy::new(|| {
+    Deduplicator::new(
+        Duration::from_secs(CONFIG.parseable.dedup_window),
+        CONFIG.parseable.dedup_capacity,
+    )
+});
+
+// how long a batch waits for the rows with the same values as its own to be written
+pub const IN_PROGRESS_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+// Deduplication of the records of a stream, set with PUT /logstream/{name}/dedup
+// {
+//     "field": "event_id"
+// }
+// A record is dropped when a record with the same value of the field was already
+// ingested into the stream within the dedup window. Records without the field are kept.
+// A record with the value of a record that is still being written waits for that write.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dedup {
+    // name of the column after the event is flattened
+    pub field: String,
+}
+
+impl Dedup {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.field.is_empty() {
+            return Err("dedup field can not be empty".to_owned());
+        }
+        Ok(())
+    }
+}
+
+// outcome of claiming the idempotency key of a request
+pub enum Claim<'a> {
+    // first request with the key, it is to be ingested
+    New(RequestGuard<'a>),
+    // a request with the key was already ingested, along with the body it was answered with
+    Duplicate(Option<Bytes>),
+    // a request with the key is being ingested right now
+    InProgress,
+}
+
+// Held while the request that claimed a key is ingested.
+// The key is released when the guard is dropped without being accepted,
+// so that a retry of a failed (or cancelled) request is ingested again.
+pub struct RequestGuard<'a> {
+    dedup: &'a Deduplicator,
+    stream_name: String,
+    hash: u64,
+    accepted: bool,
+}
+
+impl RequestGuard<'_> {
+    // reply is the body the request is answered with, for its duplicates to be answered the same
+    pub fn accept(mut self, reply: Option<Bytes>) {
+        self.accepted = true;
+        self.dedup.update(&self.stream_name, |window| {
+            if let Some(entry) = window.keys.get_mut(&self.hash) {
+                entry.pending = false;
+                entry.reply = reply;
+            }
+        });
+    }
+}
+
+impl Drop for RequestGuard<'_> {
+    fn drop(&mut self) {
+        if !self.accepted {
+            self.dedup.update(&self.stream_name, |window| {
+                window.keys.remove(&self.hash);
+            });
+        }
+    }
+}
+
+// outcome of filtering the rows of a batch
+pub enum Rows<'a> {
+    // the batch without its duplicate rows, along with the number of rows dropped
+    Filtered(usize, RecordBatch, RowsGuard<'a>),
+    // a row has the value of a row that is being written right now, which is not known
+    // to be a duplicate until that write is done. The batch is to be filtered again then
+    InProgress(RecordBatch),
+}
+
+// Held while the rows kept by filter_batch are written.
+// Their values are released when the guard is dropped without being accepted,
+// so that the rows of a failed write are not dropped as duplicates on retry.
+pub struct RowsGuard<'a> {
+    dedup: &'a Deduplicator,
+    stream_name: String,
+    seen: Instant,
+    hashes: Vec<u64>,
+}
+
+impl RowsGuard<'_> {
+    pub fn accept(mut self) {
+        let hashes = std::mem::take(&mut self.hashes);
+        self.dedup.update(&self.stream_name, |window| {
+            for hash in hashes {
+                if let Some(entry) = window
+                    .keys
+                    .get_mut(&hash)
+                    .filter(|entry| entry.seen == self.seen)
+                {
+                    entry.pending = false;
+                }
+            }
+        });
+    }
+}
+
+impl Drop for RowsGuard<'_> {
+    fn drop(&mut self) {
+        if self.hashes.is_empty() {
+            return;
+        }
+        self.dedup.update(&self.stream_name, |window| {
+            for hash in &self.hashes {
+                // the value may have been evicted and seen again since
+                if window
+                    .keys
+                    .get(hash)
+                    .is_some_and(|entry| entry.seen == self.seen)
+                {
+                    window.keys.remove(hash);
+                }
+            }
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    seen: Instant,
+    pending: bool,
+    reply: Option<Bytes>,
+}
+
+// Keys seen by a stream, bounded both by age and by count.
+// Only the hash of a key is kept, idempotency keys and record values
+// are hashed apart so that they never match each other.
+#[derive(Debug, Default)]
+struct Window {
+    keys: HashMap<u64, Entry>,
+    order: VecDeque<(u64, Instant)>,
+}
+
+impl Window {
+    // makes room for a key to be inserted
+    fn evict(&mut self, now: Instant, window: Duration, capacity: usize) {
+        while let Some(&(hash, seen)) = self.order.front() {
+            if now.saturating_duration_since(seen) < window && self.order.len() < capacity {
+                break;
+            }
+            self.order.pop_front();
+            // the key may have been released and seen again since
+            if self.keys.get(&hash).is_some_and(|entry| entry.seen == seen) {
+                self.keys.remove(&hash);
+            }
+        }
+    }
+
+    // returns the entry of a key that is already in the window, inserts it otherwise
+    fn insert(&mut self, hash: u64, now: Instant, pending: bool) -> Option<Entry> {
+        if let Some(entry) = self.keys.get(&hash) {
+            return Some(entry.clone());
+        }
+        self.keys.insert(
+            hash,
+            Entry {
+                seen: now,
+                pending,
+                reply: None,
+            },
+        );
+        self.order.push_back((hash, now));
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct Deduplicator {
+    window: Duration,
+    capacity: usize,
+    streams: Mutex<HashMap<String, Window>>,
+}
+
+impl Deduplicator {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            streams: Mutex::default(),
+        }
+    }
+
+    // claims the idempotency key of a request to a stream
+    pub fn claim(&self, stream_name: &str, key: &str) -> Claim<'_> {
+        let hash = hash_key(b'r', key);
+        let now = Instant::now();
+        let existing = self.update(stream_name, |window| window.insert(hash, now, true));
+        match existing {
+            None => Claim::New(RequestGuard {
+                dedup: self,
+                stream_name: stream_name.to_owned(),
+                hash,
+                accepted: false,
+            }),
+            Some(entry) if entry.pending => Claim::InProgress,
+            Some(entry) => Claim::Duplicate(entry.reply),
+        }
+    }
+
+    // drops the rows of the batch whose value of the field was already seen,
+    // in this batch or earlier. Returns the number of rows dropped along with the batch
+    // and a guard for the values of the rows kept, to be accepted once they are written
+    pub fn filter_batch(
+        &self,
+        stream_name: &str,
+        field: &str,
+        rb: RecordBatch,
+    ) -> Result<Rows<'_>, ArrowError> {
+        let now = Instant::now();
+        let mut guard = RowsGuard {
+            dedup: self,
+            stream_name: stream_name.to_owned(),
+            seen: now,
+            hashes: Vec::new(),
+        };
+        let Some(column) = rb.column_by_name(field).cloned() else {
+            return Ok(Rows::Filtered(0, rb, guard));
+        };
+
+        let hashes = (0..column.len())
+            .map(|row| {
+                if column.is_null(row) {
+                    return Ok(None);
+                }
+                Ok(Some(hash_key(b'v', &array_value_to_string(&column, row)?)))
+            })
+            .collect::<Result<Vec<_>, ArrowError>>()?;
+
+        // the values of the rows kept stay pending until the guard is accepted
+        let keep: Option<Vec<bool>> = self.update(stream_name, |window| {
+            let mut inserted = HashSet::new();
+            let mut keep = Vec::with_capacity(hashes.len());
+            for hash in hashes {
+                let Some(hash) = hash else {
+                    keep.push(true);
+                    continue;
+                };
+                window.evict(now, self.window, self.capacity);
+                match window.insert(hash, now, true) {
+                    None => {
+                        inserted.insert(hash);
+                        keep.push(true);
+                    }
+                    Some(entry) if entry.pending && !inserted.contains(&hash) => {
+                        // released so that the batch does not hold up the others while it waits
+                        for hash in inserted {
+                            window.keys.remove(&hash);
+                        }
+                        return None;
+                    }
+                    Some(_) => keep.push(false),
+                }
+            }
+            guard.hashes.extend(inserted);
+            Some(keep)
+        });
+        let Some(keep) = keep else {
+            return Ok(Rows::InProgress(rb));
+        };
+
+        let dropped = keep.iter().filter(|keep| !**keep).count();
+        if dropped == 0 {
+            return Ok(Rows::Filtered(0, rb, guard));
+        }
+        let rb = filter_record_batch(&rb, &BooleanArray::from(keep))?;
+        Ok(Rows::Filtered(dropped, rb, guard))
+    }
+
+    // drops the keys of a stream, for when its dedup field changes or it is deleted
+    pub fn reset(&self, stream_name: &str) {
+        self.streams.lock().unwrap().remove(stream_name);
+    }
+
+    fn update<T>(&self, stream_name: &str, f: impl FnOnce(&mut Window) -> T) -> T {
+        let mut streams = self.streams.lock().unwrap();
+        let window = streams.entry(stream_name.to_owned()).or_default();
+        window.evict(Instant::now(), self.window, self.capacity);
+        f(window)
+    }
+}
+
+fn hash_key(kind: u8, key: &str) -> u64 {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    hasher.update(&[kind]);
+    hasher.update(key.as_bytes());
+    hasher.digest()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use arrow_array::{Array, ArrayRef, Int64Array, RecordBatch, StringArray};
+    use bytes::Bytes;
+
+    use super::{Claim, Deduplicator, Rows, RowsGuard};
+
+    fn filter<'a>(
+        dedup: &'a Deduplicator,
+        field: &str,
+        rb: RecordBatch,
+    ) -> (usize, RecordBatch, RowsGuard<'a>) {
+        match dedup.filter_batch("app", field, rb).unwrap() {
+            Rows::Filtered(dropped, rb, guard) => (dropped, rb, guard),
+            Rows::InProgress(_) => panic!("rows are not in progress"),
+        }
+    }
+
+    fn batch(ids: Vec<Option<&str>>) -> RecordBatch {
+        let values: Vec<i64> = (0..ids.len() as i64).collect();
+        RecordBatch::try_from_iter([
+            ("id", Arc::new(StringArray::from(ids)) as ArrayRef),
+            ("value", Arc::new(Int64Array::from(values)) as ArrayRef),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn request_keys_are_claimed_once() {
+        let dedup = Deduplicator::new(Duration::from_secs(60), 100);
+
+        let Claim::New(guard) = dedup.claim("app", "abc") else {
+            panic!("first claim is new")
+        };
+        assert!(matches!(dedup.claim("app", "abc"), Claim::InProgress));
+        assert!(matches!(dedup.claim("other", "abc"), Claim::New(_)));
+        guard.accept(None);
+        assert!(matches!(dedup.claim("app", "abc"), Claim::Duplicate(None)));
+
+        // duplicates are answered the same as the request
+        let Claim::New(guard) = dedup.claim("app", "partial") else {
+            panic!("first claim is new")
+        };
+        guard.accept(Some(Bytes::from_static(b"{\"ingested\":1}")));
+        let Claim::Duplicate(Some(reply)) = dedup.claim("app", "partial") else {
+            panic!("reply is kept")
+        };
+        assert_eq!(reply, Bytes::from_static(b"{\"ingested\":1}"));
+
+        // a request that was not accepted releases its key
+        let Claim::New(guard) = dedup.claim("app", "def") else {
+            panic!("first claim is new")
+        };
+        drop(guard);
+        assert!(matches!(dedup.claim("app", "def"), Claim::New(_)));
+    }
+
+    #[test]
+    fn duplicate_rows_are_dropped() {
+        let dedup = Deduplicator::new(Duration::from_secs(60), 100);
+
+        let (dropped, rb, guard) = filter(
+            &dedup,
+            "id",
+            batch(vec![Some("a"), Some("b"), Some("a"), None]),
+        );
+        assert_eq!(dropped, 1);
+        assert_eq!(rb.num_rows(), 3);
+        guard.accept();
+
+        let (dropped, rb, guard) = filter(&dedup, "id", batch(vec![Some("b"), Some("c"), None]));
+        assert_eq!(dropped, 1);
+        guard.accept();
+        let values = rb
+            .column_by_name("value")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(values.values(), &[1, 2]);
+
+        // batches without the field are kept as is
+        let (dropped, _, _) = filter(&dedup, "missing", batch(vec![Some("a")]));
+        assert_eq!(dropped, 0);
+    }
+
+    #[test]
+    fn window_is_bounded() {
+        let dedup = Deduplicator::new(Duration::from_secs(60), 2);
+        for id in ["a", "b", "c"] {
+            let (_, _, guard) = filter(&dedup, "id", batch(vec![Some(id)]));
+            guard.accept();
+        }
+        // a is evicted by c, b and c are still in the window
+        let (dropped, _, _) = filter(&dedup, "id", batch(vec![Some("c")]));
+        assert_eq!(dropped, 1);
+        let (dropped, _, _) = filter(&dedup, "id", batch(vec![Some("a")]));
+        assert_eq!(dropped, 0);
+
+        let dedup = Deduplicator::new(Duration::ZERO, 100);
+        let (_, _, guard) = filter(&dedup, "id", batch(vec![Some("a")]));
+        guard.accept();
+        let (dropped, _, _) = filter(&dedup, "id", batch(vec![Some("a")]));
+        assert_eq!(dropped, 0);
+    }
+
+    #[test]
+    fn rows_not_written_are_released() {
+        let dedup = Deduplicator::new(Duration::from_secs(60), 100);
+
+        let (_, _, guard) = filter(&dedup, "id", batch(vec![Some("a"), Some("b")]));
+        drop(guard);
+        let (dropped, rb, guard) = filter(&dedup, "id", batch(vec![Some("a"), Some("b")]));
+        assert_eq!(dropped, 0);
+        assert_eq!(rb.num_rows(), 2);
+        guard.accept();
+
+        let (dropped, _, _) = filter(&dedup, "id", batch(vec![Some("a")]));
+        assert_eq!(dropped, 1);
+    }
+
+    #[test]
+    fn rows_being_written_are_waited_for() {
+        let dedup = Deduplicator::new(Duration::from_secs(60), 100);
+
+        let (_, _, writing) = filter(&dedup, "id", batch(vec![Some("a")]));
+        let Rows::InProgress(rb) = dedup
+            .filter_batch("app", "id", batch(vec![Some("b"), Some("a")]))
+            .unwrap()
+        else {
+            panic!("a is being written")
+        };
+        assert_eq!(rb.num_rows(), 2);
+        // the values of a batch that waits are not held
+        let (dropped, _, guard) = filter(&dedup, "id", batch(vec![Some("b")]));
+        assert_eq!(dropped, 0);
+        drop(guard);
+
+        // the write of a failed, so its row is not a duplicate
+        drop(writing);
+        let (dropped, rb, guard) = filter(&dedup, "id", rb);
+        assert_eq!(dropped, 0);
+        assert_eq!(rb.num_rows(), 2);
+        guard.accept();
+        let (dropped, _, _) = filter(&dedup, "id", batch(vec![Some("a")]));
+        assert_eq!(dropped, 1);
+    }
+}
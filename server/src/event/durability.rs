@@ -0,0 +1,233 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::num::NonZeroU64;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::{BoxFuture, Shared};
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::{error::stream_info::MetadataError, STREAM_INFO};
+use crate::option::CONFIG;
+
+use super::error::EventError;
+
+// requests of a stream waiting on the same group commit
+static GROUP_COMMITS: Lazy<Mutex<HashMap<String, PendingCommit>>> = Lazy::new(Mutex::default);
+
+type GroupCommit = Shared<BoxFuture<'static, Result<(), String>>>;
+
+struct PendingCommit {
+    // staging files written by the requests in the commit
+    files: Arc<Mutex<HashSet<PathBuf>>>,
+    commit: GroupCommit,
+}
+
+// When the events of a request count as persisted, and so when the request is answered.
+// Set for the server with --durability and for a stream with PUT /logstream/{name}/durability
+// { "level": "none" }
+// { "level": "fsync-per-request" }
+// { "level": "group-commit", "interval_ms": 20 }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "level", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Durability {
+    // events are acknowledged once they are handed to the OS, a crash of the
+    // machine can lose the latest events
+    #[default]
+    None,
+    // staging files of the stream are synced to disk before every request is acknowledged
+    FsyncPerRequest,
+    // requests wait for a sync of the staging files that runs every interval,
+    // shared by all the requests to the stream in that interval
+    GroupCommit {
+        // --group-commit-interval when not set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        interval_ms: Option<NonZeroU64>,
+    },
+}
+
+impl Durability {
+    pub fn from_level(level: &str) -> Option<Self> {
+        match level {
+            "none" => Some(Durability::None),
+            "fsync-per-request" => Some(Durability::FsyncPerRequest),
+            "group-commit" => Some(Durability::GroupCommit { interval_ms: None }),
+            _ => None,
+        }
+    }
+}
+
+// durability of the stream, the one set for the server unless the stream has its own
+pub fn of_stream(stream_name: &str) -> Result<Durability, MetadataError> {
+    Ok(STREAM_INFO
+        .durability(stream_name)?
+        .unwrap_or(CONFIG.parseable.durability))
+}
+
+// whether the staging files of the stream are synced to disk
+pub fn is_durable(stream_name: &str) -> bool {
+    of_stream(stream_name).is_ok_and(|durability| durability != Durability::None)
+}
+
+// waits until the events a request wrote to the given staging files are as durable as asked
+pub async fn persist(
+    stream_name: &str,
+    durability: Durability,
+    files: HashSet<PathBuf>,
+) -> Result<(), EventError> {
+    match durability {
+        Durability::None => Ok(()),
+        Durability::FsyncPerRequest => sync(files).await.map_err(EventError::Persist),
+        Durability::GroupCommit { interval_ms } => {
+            let interval = interval_ms.map_or(CONFIG.parseable.group_commit_interval, |ms| {
+                Duration::from_millis(ms.get())
+            });
+            group_commit(stream_name, interval, files)
+                .await
+                .map_err(EventError::Persist)
+        }
+    }
+}
+
+// joins the next group commit of the stream with the files of the request, starting
+// one if there is none pending. The commit is spawned so that it completes even if
+// all of its requests go away
+fn group_commit(stream_name: &str, interval: Duration, files: HashSet<PathBuf>) -> GroupCommit {
+    let mut commits = GROUP_COMMITS.lock().unwrap();
+    if let Some(pending) = commits.get(stream_name) {
+        pending.files.lock().unwrap().extend(files);
+        return pending.commit.clone();
+    }
+
+    let name = stream_name.to_owned();
+    let files = Arc::new(Mutex::new(files));
+    let commit_files = Arc::clone(&files);
+    let handle = tokio::spawn(async move {
+        tokio::time::sleep(interval).await;
+        // requests from here on are left for the next commit, the ones
+        // already in this commit have written their events before joining it
+        GROUP_COMMITS.lock().unwrap().remove(&name);
+        let files = std::mem::take(&mut *commit_files.lock().unwrap());
+        sync(files).await
+    });
+    let commit = handle
+        .map(|result| result.unwrap_or_else(|err| Err(err.to_string())))
+        .boxed()
+        .shared();
+    commits.insert(
+        stream_name.to_owned(),
+        PendingCommit {
+            files,
+            commit: commit.clone(),
+        },
+    );
+    commit
+}
+
+async fn sync(files: HashSet<PathBuf>) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || sync_files(files))
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| err.to_string())
+}
+
+// Files are synced through handles of their own so that writes to the stream are
+// not held up by the sync. A file that is gone has been converted to parquet, which
+// is synced before the staging files it is made of are removed
+fn sync_files(files: HashSet<PathBuf>) -> io::Result<()> {
+    for path in files {
+        match File::open(&path) {
+            Ok(file) => file.sync_data()?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::{group_commit, Durability, GROUP_COMMITS};
+
+    #[test]
+    fn durability_levels() {
+        let durability: Durability = serde_json::from_value(json!({ "level": "none" })).unwrap();
+        assert_eq!(durability, Durability::None);
+
+        let durability: Durability =
+            serde_json::from_value(json!({ "level": "group-commit", "interval_ms": 20 })).unwrap();
+        assert_eq!(
+            durability,
+            Durability::GroupCommit {
+                interval_ms: Some(20.try_into().unwrap())
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(Durability::FsyncPerRequest).unwrap(),
+            json!({ "level": "fsync-per-request" })
+        );
+        assert_eq!(
+            Durability::from_level("group-commit"),
+            Some(Durability::GroupCommit { interval_ms: None })
+        );
+
+        for value in [
+            json!({ "level": "fsync" }),
+            json!({ "level": "group-commit", "interval_ms": 0 }),
+            json!({ "level": "group-commit", "interval": 20 }),
+        ] {
+            assert!(serde_json::from_value::<Durability>(value).is_err());
+        }
+    }
+
+    #[actix_web::test]
+    async fn group_commits_sync_the_files_of_their_requests() {
+        let dir = std::env::temp_dir().join(format!("parseable-commit-{}", ulid::Ulid::new()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let written = dir.join("written.data.arrows");
+        std::fs::write(&written, b"events").unwrap();
+
+        let interval = Duration::from_millis(10);
+        let first = group_commit("commits", interval, HashSet::from([written]));
+        // files converted to parquet and removed since are skipped
+        let second = group_commit(
+            "commits",
+            interval,
+            HashSet::from([dir.join("converted.data.arrows")]),
+        );
+        let pending = GROUP_COMMITS.lock().unwrap()["commits"].files.clone();
+        assert_eq!(pending.lock().unwrap().len(), 2);
+        assert!(first.await.is_ok());
+        assert!(second.await.is_ok());
+        assert!(!GROUP_COMMITS.lock().unwrap().contains_key("commits"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,504 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_json::reader::infer_json_schema_from_iterator;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// Schema evolution of streams. A field whose values no longer fit its type is
+// widened instead of rejecting the event, integers are widened to floats and
+// integers, floats and booleans are widened to strings.
+// Int64 -> Float64 -> Utf8
+// Data written with the narrower type is cast to the wider one when it is read.
+pub fn widen(existing: &DataType, other: &DataType) -> Option<DataType> {
+    match (existing, other) {
+        (existing, other) if existing == other => Some(existing.clone()),
+        (DataType::Null, other) => Some(other.clone()),
+        (existing, DataType::Null) => Some(existing.clone()),
+        (DataType::Int64, DataType::Float64) | (DataType::Float64, DataType::Int64) => {
+            Some(DataType::Float64)
+        }
+        (existing, other) if is_scalar(existing) && is_scalar(other) => Some(DataType::Utf8),
+        _ => None,
+    }
+}
+
+fn is_scalar(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Boolean | DataType::Int64 | DataType::Float64 | DataType::Utf8
+    )
+}
+
+// the type a JSON value is inferred as, None for values that are never widened
+fn value_type(value: &Value) -> Option<DataType> {
+    match value {
+        Value::Bool(_) => Some(DataType::Boolean),
+        Value::Number(n) if n.is_i64() => Some(DataType::Int64),
+        Value::Number(_) => Some(DataType::Float64),
+        Value::String(_) => Some(DataType::Utf8),
+        _ => None,
+    }
+}
+
+// Widens the fields of the stream schema that the values of a flattened record do not fit.
+// Returns true if any field was widened
+pub fn widen_fields(schema: &mut HashMap<String, Arc<Field>>, record: &Value) -> bool {
+    let Value::Object(record) = record else {
+        return false;
+    };
+
+    let mut widened = false;
+    for (name, value) in record {
+        let (Some(field), Some(value_type)) = (schema.get(name), value_type(value)) else {
+            continue;
+        };
+        match widen(field.data_type(), &value_type) {
+            Some(data_type) if &data_type != field.data_type() => {
+                schema.insert(name.to_owned(), Arc::new(Field::new(name, data_type, true)));
+                widened = true;
+            }
+            _ => (),
+        }
+    }
+    widened
+}
+
+// Adds the keys that the objects of a flattened record have on top of the children of the
+// struct fields of the stream schema, and of the structs of list fields, merged in with
+// merge_field. Rows written before have null for the new children. Returns true if any
+// struct was widened
+pub fn widen_structs(schema: &mut HashMap<String, Arc<Field>>, record: &Value) -> bool {
+    let Value::Object(record) = record else {
+        return false;
+    };
+
+    let mut widened = false;
+    for (name, value) in record {
+        let Some(field) = schema.get(name) else {
+            continue;
+        };
+        if !has_new_children(field.data_type(), value) {
+            continue;
+        }
+        // null values do not fix the type of a child
+        let Some(value) = strip_nulls(value.clone()) else {
+            continue;
+        };
+        let object = Value::Object(Map::from_iter([(name.to_owned(), value)]));
+        let Ok(inferred) = infer_json_schema_from_iterator(std::iter::once(Ok(&object))) else {
+            continue;
+        };
+        let Ok(inferred) = inferred.field_with_name(name) else {
+            continue;
+        };
+        if let Ok(merged) = merge_field(field, inferred) {
+            schema.insert(name.to_owned(), Arc::new(merged));
+            widened = true;
+        }
+    }
+    widened
+}
+
+// true if an object of the value, or an object nested in it, has a key with a
+// value that is not a child of the struct it is stored as
+fn has_new_children(data_type: &DataType, value: &Value) -> bool {
+    match (data_type, value) {
+        (DataType::Struct(children), Value::Object(object)) => {
+            object.iter().any(|(key, value)| match children.find(key) {
+                None => !value.is_null(),
+                Some((_, child)) => has_new_children(child.data_type(), value),
+            })
+        }
+        (DataType::List(item), Value::Array(values)) => values
+            .iter()
+            .any(|value| has_new_children(item.data_type(), value)),
+        _ => false,
+    }
+}
+
+fn strip_nulls(value: Value) -> Option<Value> {
+    match value {
+        Value::Null => None,
+        Value::Object(object) => Some(Value::Object(
+            object
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, strip_nulls(value)?)))
+                .collect(),
+        )),
+        Value::Array(values) => Some(Value::Array(
+            values.into_iter().filter_map(strip_nulls).collect(),
+        )),
+        value => Some(value),
+    }
+}
+
+// Merges two versions of a field like Field::try_merge, which only merges the children of
+// structs. The children of structs in lists are merged too, List<Struct> columns get the
+// keys of all versions
+pub fn merge_field(field: &Field, from: &Field) -> Result<Field, ArrowError> {
+    let nullable = field.is_nullable() || from.is_nullable();
+    match (field.data_type(), from.data_type()) {
+        (DataType::List(item), DataType::List(from_item)) => {
+            let item = merge_field(item, from_item)?;
+            Ok(field
+                .clone()
+                .with_data_type(DataType::List(Arc::new(item)))
+                .with_nullable(nullable))
+        }
+        (DataType::Struct(children), DataType::Struct(from_children)) => {
+            let mut merged: Vec<Field> = children
+                .iter()
+                .map(|child| child.as_ref().clone())
+                .collect();
+            for from_child in from_children {
+                match merged
+                    .iter_mut()
+                    .find(|child| child.name() == from_child.name())
+                {
+                    Some(child) => *child = merge_field(child, from_child)?,
+                    None => merged.push(from_child.as_ref().clone()),
+                }
+            }
+            Ok(field
+                .clone()
+                .with_data_type(DataType::Struct(merged.into()))
+                .with_nullable(nullable))
+        }
+        _ => {
+            let mut merged = field.clone();
+            merged.try_merge(from)?;
+            Ok(merged)
+        }
+    }
+}
+
+fn is_nested(data_type: &DataType) -> bool {
+    matches!(data_type, DataType::Struct(_) | DataType::List(_))
+}
+
+// Merges schemas like Schema::try_merge, fields with different types are widened first
+// and structs, also those in lists, get the children of every schema
+pub fn merge_schemas(schemas: impl IntoIterator<Item = Schema>) -> Result<Schema, ArrowError> {
+    let schemas: Vec<Schema> = schemas.into_iter().collect();
+
+    let mut nested: HashMap<&str, Field> = HashMap::new();
+    for field in schemas.iter().flat_map(|schema| schema.fields().iter()) {
+        if !is_nested(field.data_type()) {
+            continue;
+        }
+        let merged = match nested.get(field.name().as_str()) {
+            Some(merged) => merge_field(merged, field)?,
+            None => field.as_ref().clone(),
+        };
+        nested.insert(field.name(), merged);
+    }
+
+    let mut types: HashMap<&str, DataType> = HashMap::new();
+    for field in schemas.iter().flat_map(|schema| schema.fields().iter()) {
+        match types.get(field.name().as_str()) {
+            Some(data_type) => {
+                if let Some(widened) = widen(data_type, field.data_type()) {
+                    types.insert(field.name(), widened);
+                }
+            }
+            None => {
+                types.insert(field.name(), field.data_type().clone());
+            }
+        }
+    }
+
+    let schemas: Vec<Schema> = schemas
+        .iter()
+        .map(|schema| {
+            let fields: Vec<Arc<Field>> = schema
+                .fields()
+                .iter()
+                .map(|field| match types.get(field.name().as_str()) {
+                    _ if is_nested(field.data_type()) => {
+                        Arc::new(nested[field.name().as_str()].clone())
+                    }
+                    Some(data_type)
+                        if data_type != field.data_type()
+                            && widen(field.data_type(), data_type).as_ref() == Some(data_type) =>
+                    {
+                        Arc::new(
+                            field
+                                .as_ref()
+                                .clone()
+                                .with_data_type(data_type.clone())
+                                .with_nullable(true),
+                        )
+                    }
+                    _ => field.clone(),
+                })
+                .collect();
+            Schema::new_with_metadata(fields, schema.metadata().clone())
+        })
+        .collect();
+
+    Schema::try_merge(schemas)
+}
+
+// A version of the schema of a stream. A new version is kept in the object store
+// every time a field of the stream is widened, see ObjectStorage::get_schema_history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchemaVersion {
+    pub version: u32,
+    // time the schema took effect, unknown for the first version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    // fields widened by this version
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub widened: Vec<WidenedField>,
+    pub schema: Schema,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WidenedField {
+    pub name: String,
+    pub from: DataType,
+    pub to: DataType,
+}
+
+// fields of the old schema that have a wider type in the new one
+pub fn widened_fields(old: &Schema, new: &Schema) -> Vec<WidenedField> {
+    old.fields()
+        .iter()
+        .filter_map(|field| {
+            let (_, new_field) = new.column_with_name(field.name())?;
+            (field.data_type() != new_field.data_type()
+                && widen(field.data_type(), new_field.data_type()).as_ref()
+                    == Some(new_field.data_type()))
+            .then(|| WidenedField {
+                name: field.name().to_owned(),
+                from: field.data_type().clone(),
+                to: new_field.data_type().clone(),
+            })
+        })
+        .collect()
+}
+
+// Adds a version to the history for a new schema, if it widens fields of the last one.
+// The first version of a history is the schema from before the first widening
+pub fn add_version(history: &mut Vec<SchemaVersion>, old: &Schema, new: &Schema) -> bool {
+    let widened = widened_fields(old, new);
+    if widened.is_empty() {
+        return false;
+    }
+    if history.is_empty() {
+        history.push(SchemaVersion {
+            version: 1,
+            since: None,
+            widened: Vec::new(),
+            schema: old.clone(),
+        });
+    }
+    let version = history.last().map_or(1, |last| last.version + 1);
+    history.push(SchemaVersion {
+        version,
+        since: Some(Utc::now()),
+        widened,
+        schema: new.clone(),
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field, Schema};
+    use serde_json::json;
+
+    use super::{add_version, merge_schemas, widen, widen_fields, widen_structs};
+
+    #[test]
+    fn types_are_widened() {
+        assert_eq!(
+            widen(&DataType::Int64, &DataType::Float64),
+            Some(DataType::Float64)
+        );
+        assert_eq!(
+            widen(&DataType::Float64, &DataType::Int64),
+            Some(DataType::Float64)
+        );
+        assert_eq!(
+            widen(&DataType::Float64, &DataType::Utf8),
+            Some(DataType::Utf8)
+        );
+        assert_eq!(
+            widen(&DataType::Utf8, &DataType::Boolean),
+            Some(DataType::Utf8)
+        );
+        assert_eq!(
+            widen(&DataType::Int64, &DataType::Int64),
+            Some(DataType::Int64)
+        );
+        let list = DataType::List(Arc::new(Field::new("item", DataType::Int64, true)));
+        assert_eq!(widen(&DataType::Int64, &list), None);
+    }
+
+    #[test]
+    fn fields_are_widened_for_records() {
+        let mut schema = HashMap::from([
+            (
+                "a".to_owned(),
+                Arc::new(Field::new("a", DataType::Int64, true)),
+            ),
+            (
+                "b".to_owned(),
+                Arc::new(Field::new("b", DataType::Utf8, true)),
+            ),
+        ]);
+        assert!(!widen_fields(&mut schema, &json!({ "a": 1, "b": 2.5 })));
+        assert!(widen_fields(&mut schema, &json!({ "a": 1.5, "c": 1 })));
+        assert_eq!(schema["a"].data_type(), &DataType::Float64);
+        assert!(widen_fields(&mut schema, &json!({ "a": "x" })));
+        assert_eq!(schema["a"].data_type(), &DataType::Utf8);
+        assert!(!schema.contains_key("c"));
+    }
+
+    #[test]
+    fn structs_are_widened_for_records() {
+        let request = DataType::Struct(
+            vec![
+                Field::new("method", DataType::Utf8, true),
+                Field::new(
+                    "client",
+                    DataType::Struct(vec![Field::new("ip", DataType::Utf8, true)].into()),
+                    true,
+                ),
+            ]
+            .into(),
+        );
+        let mut schema = HashMap::from([(
+            "request".to_owned(),
+            Arc::new(Field::new("request", request, true)),
+        )]);
+
+        let record =
+            json!({ "request": { "method": "GET", "client": { "ip": "::1" }, "path": null } });
+        assert!(!widen_structs(&mut schema, &record));
+
+        let record = json!({ "request": { "path": "/", "client": { "port": 80, "tls": null } } });
+        assert!(widen_structs(&mut schema, &record));
+        let DataType::Struct(children) = schema["request"].data_type() else {
+            panic!("request is a struct")
+        };
+        let names: Vec<&str> = children.iter().map(|child| child.name().as_str()).collect();
+        assert_eq!(names, ["method", "client", "path"]);
+        let DataType::Struct(client) = children[1].data_type() else {
+            panic!("client is a struct")
+        };
+        assert_eq!(client.len(), 2);
+        assert_eq!(client[1].name(), "port");
+        assert_eq!(client[1].data_type(), &DataType::Int64);
+
+        // structs in lists are widened the same
+        let item = Field::new(
+            "item",
+            DataType::Struct(vec![Field::new("k", DataType::Int64, true)].into()),
+            true,
+        );
+        schema.insert(
+            "items".to_owned(),
+            Arc::new(Field::new("items", DataType::List(Arc::new(item)), true)),
+        );
+        assert!(!widen_structs(
+            &mut schema,
+            &json!({ "items": [{ "k": 1 }, null] })
+        ));
+        assert!(widen_structs(
+            &mut schema,
+            &json!({ "items": [{ "k": 1 }, { "v": "x" }] })
+        ));
+        let DataType::List(item) = schema["items"].data_type() else {
+            panic!("items is a list")
+        };
+        assert!(matches!(item.data_type(), DataType::Struct(children) if children.len() == 2));
+
+        // children keep their type
+        let record = json!({ "request": { "method": 1, "client": { "port": "x", "ua": "curl" } } });
+        assert!(!widen_structs(&mut schema, &record));
+    }
+
+    #[test]
+    fn schemas_are_merged_and_versioned() {
+        let old = Schema::new(vec![
+            Field::new("a", DataType::Int64, true),
+            Field::new("b", DataType::Utf8, true),
+        ]);
+        let new = Schema::new(vec![
+            Field::new("a", DataType::Float64, true),
+            Field::new("c", DataType::Boolean, true),
+        ]);
+        let merged = merge_schemas([old.clone(), new]).unwrap();
+        assert_eq!(
+            merged.field_with_name("a").unwrap().data_type(),
+            &DataType::Float64
+        );
+        assert_eq!(merged.fields().len(), 3);
+
+        // lists of structs written before keys were added get the keys of the stream
+        let list = |children: Vec<Field>| {
+            let item = Field::new("item", DataType::Struct(children.into()), true);
+            Schema::new(vec![Field::new(
+                "items",
+                DataType::List(Arc::new(item)),
+                true,
+            )])
+        };
+        let lists = merge_schemas([
+            list(vec![Field::new("k", DataType::Int64, true)]),
+            list(vec![
+                Field::new("k", DataType::Int64, true),
+                Field::new("v", DataType::Utf8, true),
+            ]),
+        ])
+        .unwrap();
+        assert_eq!(
+            lists,
+            list(vec![
+                Field::new("k", DataType::Int64, true),
+                Field::new("v", DataType::Utf8, true),
+            ])
+        );
+
+        let list = Schema::new(vec![Field::new(
+            "a",
+            DataType::List(Arc::new(Field::new("item", DataType::Int64, true))),
+            true,
+        )]);
+        assert!(merge_schemas([old.clone(), list]).is_err());
+
+        let mut history = Vec::new();
+        assert!(!add_version(&mut history, &old, &old));
+        assert!(add_version(&mut history, &old, &merged));
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].version, 2);
+        assert_eq!(history[1].widened[0].name, "a");
+        assert_eq!(history[1].widened[0].to, DataType::Float64);
+    }
+}
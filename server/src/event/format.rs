@@ -17,20 +17,29 @@
  *
  */
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
 use anyhow::{anyhow, Error as AnyError};
-use arrow_array::{RecordBatch, StringArray};
+use arrow_array::builder::{MapBuilder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch, StringArray};
 use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use itertools::Itertools;
 
+use crate::handlers::SEPARATOR;
 use crate::utils::{self, arrow::get_field};
 
+use super::nested::map_type;
 use super::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY};
 
+pub mod arrow;
+pub mod csv;
 pub mod json;
 
-type Tags = String;
-type Metadata = String;
+pub type Tags = BTreeMap<String, String>;
+pub type Metadata = BTreeMap<String, String>;
 type EventSchema = Vec<Arc<Field>>;
 
 // Global Trait for event format
@@ -47,20 +56,22 @@ pub trait EventFormat: Sized {
         self,
         schema: HashMap<String, Arc<Field>>,
     ) -> Result<(RecordBatch, bool), AnyError> {
+        let tags_type = labels_type(&schema, DEFAULT_TAGS_KEY);
+        let metadata_type = labels_type(&schema, DEFAULT_METADATA_KEY);
         let (data, mut schema, is_first, tags, metadata) = self.to_data(schema)?;
 
         if get_field(&schema, DEFAULT_TAGS_KEY).is_some() {
             return Err(anyhow!("field {} is a reserved field", DEFAULT_TAGS_KEY));
         };
 
-        if get_field(&schema, DEFAULT_TAGS_KEY).is_some() {
+        if get_field(&schema, DEFAULT_METADATA_KEY).is_some() {
             return Err(anyhow!(
                 "field {} is a reserved field",
                 DEFAULT_METADATA_KEY
             ));
         };
 
-        if get_field(&schema, DEFAULT_TAGS_KEY).is_some() {
+        if get_field(&schema, DEFAULT_TIMESTAMP_KEY).is_some() {
             return Err(anyhow!(
                 "field {} is a reserved field",
                 DEFAULT_TIMESTAMP_KEY
@@ -80,27 +91,65 @@ pub trait EventFormat: Sized {
         // p_tags and p_metadata are added to the end of the schema
         let tags_index = schema.len();
         let metadata_index = tags_index + 1;
-        schema.push(Arc::new(Field::new(DEFAULT_TAGS_KEY, DataType::Utf8, true)));
+        schema.push(Arc::new(Field::new(DEFAULT_TAGS_KEY, tags_type, true)));
         schema.push(Arc::new(Field::new(
             DEFAULT_METADATA_KEY,
-            DataType::Utf8,
+            metadata_type,
             true,
         )));
 
         // prepare the record batch and new fields to be added
         let schema = Arc::new(Schema::new(schema));
         let rb = Self::decode(data, schema.clone())?;
-        let tags_arr = StringArray::from_iter_values(std::iter::repeat(&tags).take(rb.num_rows()));
-        let metadata_arr =
-            StringArray::from_iter_values(std::iter::repeat(&metadata).take(rb.num_rows()));
+        let tags_arr = labels_array(&tags, schema.field(tags_index).data_type(), rb.num_rows())?;
+        let metadata_arr = labels_array(
+            &metadata,
+            schema.field(metadata_index).data_type(),
+            rb.num_rows(),
+        )?;
         // modify the record batch to add fields to respective indexes
         let rb = utils::arrow::replace_columns(
             Arc::clone(&schema),
             &rb,
             &[tags_index, metadata_index],
-            &[Arc::new(tags_arr), Arc::new(metadata_arr)],
+            &[tags_arr, metadata_arr],
         );
 
         Ok((rb, is_first))
     }
 }
+
+// Tags and metadata are Map<Utf8, Utf8> columns. Streams created before that
+// keep them as key=value strings joined with the separator
+fn labels_type(schema: &HashMap<String, Arc<Field>>, key: &str) -> DataType {
+    match schema.get(key) {
+        Some(field) if field.data_type() == &DataType::Utf8 => DataType::Utf8,
+        _ => map_type(),
+    }
+}
+
+fn labels_array(
+    labels: &BTreeMap<String, String>,
+    data_type: &DataType,
+    num_rows: usize,
+) -> Result<ArrayRef, AnyError> {
+    if data_type == &DataType::Utf8 {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .join(&SEPARATOR.to_string());
+        return Ok(Arc::new(StringArray::from_iter_values(
+            std::iter::repeat(labels).take(num_rows),
+        )));
+    }
+
+    let mut builder = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    for _ in 0..num_rows {
+        for (key, value) in labels {
+            builder.keys().append_value(key);
+            builder.values().append_value(value);
+        }
+        builder.append(true)?;
+    }
+    Ok(Arc::new(builder.finish()))
+}
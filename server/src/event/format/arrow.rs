@@ -0,0 +1,87 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use anyhow::anyhow;
+use arrow_array::{new_null_array, RecordBatch};
+use arrow_schema::{Field, Schema};
+use std::{collections::HashMap, sync::Arc};
+
+use super::{EventFormat, Metadata, Tags};
+
+// Event that already arrives as an arrow record batch, for example over Arrow Flight.
+// Columns are used as is, there is no flattening or type inference involved.
+pub struct Event {
+    pub rb: RecordBatch,
+    pub tags: Tags,
+    pub metadata: Metadata,
+}
+
+impl EventFormat for Event {
+    type Data = RecordBatch;
+
+    // check the fields of the incoming batch against the stream schema.
+    // fields already known to the stream must have the same type,
+    // new fields are added as nullable fields
+    fn to_data(
+        self,
+        schema: HashMap<String, Arc<Field>>,
+    ) -> Result<(Self::Data, Vec<Arc<Field>>, bool, Tags, Metadata), anyhow::Error> {
+        let mut is_first = false;
+        let mut fields = Vec::with_capacity(self.rb.num_columns());
+
+        for field in self.rb.schema().fields() {
+            match schema.get(field.name()) {
+                Some(existing) if existing.data_type() != field.data_type() => {
+                    return Err(anyhow!(
+                        "Could not process this event, field {} of type {} does not match type {} of the stream",
+                        field.name(),
+                        field.data_type(),
+                        existing.data_type()
+                    ))
+                }
+                Some(existing) => fields.push(existing.clone()),
+                None => {
+                    is_first = true;
+                    fields.push(Arc::new(Field::new(
+                        field.name(),
+                        field.data_type().clone(),
+                        true,
+                    )));
+                }
+            }
+        }
+        fields.sort_by(|a, b| a.name().cmp(b.name()));
+
+        Ok((self.rb, fields, is_first, self.tags, self.metadata))
+    }
+
+    // Arrange the columns of the batch as per the event schema,
+    // columns that are not part of the batch are filled with nulls
+    fn decode(data: Self::Data, schema: Arc<Schema>) -> Result<RecordBatch, anyhow::Error> {
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|field| match data.column_by_name(field.name()) {
+                Some(column) => column.clone(),
+                None => new_null_array(field.data_type(), data.num_rows()),
+            })
+            .collect();
+
+        Ok(RecordBatch::try_new(schema, columns)?)
+    }
+}
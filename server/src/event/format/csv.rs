@@ -0,0 +1,267 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use anyhow::anyhow;
+use arrow_array::RecordBatch;
+use arrow_schema::{DataType, Field, Schema};
+use arrow_select::concat::concat_batches;
+use bytes::Bytes;
+use datafusion::arrow::csv::reader::{Format, ReaderBuilder};
+use datafusion::arrow::json::writer::record_batches_to_json_rows;
+use serde_json::Value;
+use std::{collections::HashMap, collections::HashSet, io::Cursor, sync::Arc};
+
+use super::{EventFormat, Metadata, Tags};
+
+// Event made of delimiter separated values, such as CSV or TSV exports.
+// Columns are named after the header row, or column_1, column_2 ... without one.
+pub struct Event {
+    pub data: Bytes,
+    pub delimiter: u8,
+    // None detects whether the first row is a header
+    pub has_header: Option<bool>,
+    pub tags: Tags,
+    pub metadata: Metadata,
+}
+
+impl EventFormat for Event {
+    type Data = RecordBatch;
+
+    // infer the type of every column, columns already known to the stream
+    // keep the type of the stream and are parsed as such
+    fn to_data(
+        self,
+        schema: HashMap<String, Arc<Field>>,
+    ) -> Result<(Self::Data, Vec<Arc<Field>>, bool, Tags, Metadata), anyhow::Error> {
+        let has_header = match self.has_header {
+            Some(has_header) => has_header,
+            None => detect_header(&self.data, self.delimiter)?,
+        };
+        let format = Format::default()
+            .with_header(has_header)
+            .with_delimiter(self.delimiter);
+        let (inferred, num_rows) = format.infer_schema(Cursor::new(&self.data), None)?;
+
+        let mut names = HashSet::new();
+        let mut is_first = false;
+        let mut fields = Vec::with_capacity(inferred.fields().len());
+        for field in inferred.fields() {
+            if field.name().is_empty() || !names.insert(field.name()) {
+                return Err(anyhow!(
+                    "Header row has an empty or duplicate column name {:?}",
+                    field.name()
+                ));
+            }
+            match schema.get(field.name()) {
+                Some(existing) => fields.push(existing.clone()),
+                None => {
+                    is_first = true;
+                    fields.push(Arc::new(Field::new(
+                        field.name(),
+                        json_compatible(field.data_type()),
+                        true,
+                    )));
+                }
+            }
+        }
+
+        // columns are read in the order of the file, the event schema is sorted by name
+        let file_schema = Arc::new(Schema::new(fields.clone()));
+        let reader = ReaderBuilder::new(file_schema.clone())
+            .with_format(format)
+            .with_batch_size(num_rows.max(1))
+            .build(Cursor::new(self.data))?;
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+        let rb = concat_batches(&file_schema, &batches)?;
+
+        fields.sort_by(|a, b| a.name().cmp(b.name()));
+        Ok((rb, fields, is_first, self.tags, self.metadata))
+    }
+
+    // Arrange the columns of the batch as per the event schema
+    fn decode(data: Self::Data, schema: Arc<Schema>) -> Result<RecordBatch, anyhow::Error> {
+        super::arrow::Event::decode(data, schema)
+    }
+}
+
+impl Event {
+    // The rows as json records, with the types the columns would have in the stream,
+    // for streams whose processors have to run on every record. Empty values are left out
+    pub fn into_records(
+        self,
+        schema: HashMap<String, Arc<Field>>,
+    ) -> Result<(Vec<Value>, Tags, Metadata), anyhow::Error> {
+        let (rb, _, _, tags, metadata) = self.to_data(schema)?;
+        let records = record_batches_to_json_rows(&[&rb])?
+            .into_iter()
+            .map(Value::Object)
+            .collect();
+        Ok((records, tags, metadata))
+    }
+}
+
+// New columns get the same types that the json format would infer for the values,
+// so that csv and json events of a stream agree with each other.
+// Dates and timestamps are kept as strings, just like in json.
+fn json_compatible(data_type: &DataType) -> DataType {
+    match data_type {
+        DataType::Boolean | DataType::Int64 | DataType::Float64 => data_type.clone(),
+        _ => DataType::Utf8,
+    }
+}
+
+// The first row is taken to be a header unless it holds non string values,
+// has empty or repeated names, or is the only row of the body.
+fn detect_header(data: &Bytes, delimiter: u8) -> Result<bool, anyhow::Error> {
+    let format = Format::default().with_delimiter(delimiter);
+    let (first_row, count) = format.infer_schema(Cursor::new(data), Some(2))?;
+    if count < 2 {
+        return Ok(false);
+    }
+    let all_strings = first_row
+        .fields()
+        .iter()
+        .all(|field| field.data_type() == &DataType::Utf8);
+    if !all_strings {
+        return Ok(false);
+    }
+
+    let (header, _) = format
+        .with_header(true)
+        .infer_schema(Cursor::new(data), Some(0))?;
+    let mut names = HashSet::new();
+    Ok(header
+        .fields()
+        .iter()
+        .all(|field| !field.name().trim().is_empty() && names.insert(field.name())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::Arc,
+    };
+
+    use arrow_array::{Array, Float64Array, Int64Array, StringArray};
+    use arrow_schema::{DataType, Field};
+    use bytes::Bytes;
+
+    use super::Event;
+    use crate::event::format::EventFormat;
+
+    fn event(data: &'static str, delimiter: u8, has_header: Option<bool>) -> Event {
+        Event {
+            data: Bytes::from_static(data.as_bytes()),
+            delimiter,
+            has_header,
+            tags: BTreeMap::default(),
+            metadata: BTreeMap::default(),
+        }
+    }
+
+    #[test]
+    fn csv_with_detected_header() {
+        let data = "id,amount,customer\n1,10.5,acme\n2,,globex\n";
+        let (rb, is_first) = event(data, b',', None)
+            .into_recordbatch(HashMap::default())
+            .unwrap();
+
+        assert!(is_first);
+        assert_eq!(rb.num_rows(), 2);
+        // p_timestamp, amount, customer, id, p_tags, p_metadata
+        assert_eq!(rb.num_columns(), 6);
+        let schema = rb.schema();
+        assert_eq!(schema.field(1).name(), "amount");
+        assert_eq!(schema.field(1).data_type(), &DataType::Float64);
+        assert_eq!(schema.field(3).data_type(), &DataType::Int64);
+
+        let amount = rb
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(amount.value(0), 10.5);
+        assert!(amount.is_null(1));
+        let customer = rb.column(2).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(customer.value(1), "globex");
+    }
+
+    #[test]
+    fn tsv_without_header_uses_stream_types() {
+        let schema = HashMap::from([(
+            "column_1".to_owned(),
+            Arc::new(Field::new("column_1", DataType::Utf8, true)),
+        )]);
+        let data = "100\tok\n200\tfailed\n";
+        let (rb, is_first) = event(data, b'\t', None).into_recordbatch(schema).unwrap();
+
+        assert!(is_first);
+        assert_eq!(rb.num_rows(), 2);
+        let schema = rb.schema();
+        assert_eq!(schema.field(1).name(), "column_1");
+        assert_eq!(schema.field(1).data_type(), &DataType::Utf8);
+        assert_eq!(schema.field(2).name(), "column_2");
+        let column_1 = rb.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(column_1.value(0), "100");
+    }
+
+    #[test]
+    fn explicit_header_and_type_mismatch() {
+        let (rb, _) = event("a,b\nx,y\n", b',', Some(true))
+            .into_recordbatch(HashMap::default())
+            .unwrap();
+        assert_eq!(rb.num_rows(), 1);
+
+        let (rb, _) = event("a,b\n1,2\n", b',', Some(false))
+            .into_recordbatch(HashMap::default())
+            .unwrap();
+        assert_eq!(rb.num_rows(), 2);
+
+        let schema = HashMap::from([(
+            "a".to_owned(),
+            Arc::new(Field::new("a", DataType::Int64, true)),
+        )]);
+        assert!(event("a\nnot a number\n", b',', Some(true))
+            .into_recordbatch(schema)
+            .is_err());
+
+        let (rb, _) = event("a,b\n7,2\n", b',', Some(true))
+            .into_recordbatch(HashMap::from([(
+                "a".to_owned(),
+                Arc::new(Field::new("a", DataType::Int64, true)),
+            )]))
+            .unwrap();
+        let a = rb.column(1).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(a.value(0), 7);
+    }
+
+    #[test]
+    fn rows_into_records() {
+        let (records, _, _) = event("id,amount,customer\n1,10.5,acme\n2,,globex\n", b',', None)
+            .into_records(HashMap::default())
+            .unwrap();
+        assert_eq!(
+            records,
+            vec![
+                serde_json::json!({ "id": 1, "amount": 10.5, "customer": "acme" }),
+                serde_json::json!({ "id": 2, "customer": "globex" }),
+            ]
+        );
+    }
+}
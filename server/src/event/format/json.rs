@@ -25,16 +25,22 @@ use arrow_json::reader::{infer_json_schema_from_iterator, ReaderBuilder};
 use arrow_schema::{DataType, Field, Fields, Schema};
 use datafusion::arrow::util::bit_util::round_upto_multiple_of_64;
 use itertools::Itertools;
+use serde::Serialize;
 use serde_json::Value;
 use std::{collections::HashMap, sync::Arc};
 
 use super::{EventFormat, Metadata, Tags};
-use crate::utils::{arrow::get_field, json::flatten_json_body};
+use crate::event::evolution::{merge_schemas, widen_fields, widen_structs};
+use crate::event::nested::NestedObjects;
+use crate::event::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY};
+use crate::utils::{arrow::get_field, json::flatten_json_body_nested};
 
 pub struct Event {
     pub data: Value,
     pub tags: Tags,
     pub metadata: Metadata,
+    // objects of the stream that are not flattened
+    pub nested: NestedObjects,
 }
 
 impl EventFormat for Event {
@@ -46,8 +52,8 @@ impl EventFormat for Event {
         self,
         schema: HashMap<String, Arc<Field>>,
     ) -> Result<(Self::Data, Vec<Arc<Field>>, bool, Tags, Metadata), anyhow::Error> {
-        let data = flatten_json_body(self.data)?;
-        let stream_schema = schema;
+        let data = flatten_json_body_nested(self.data, &self.nested)?;
+        let mut stream_schema = schema;
 
         // incoming event may be a single json or a json array
         // but Data (type defined above) is a vector of json values
@@ -62,22 +68,28 @@ impl EventFormat for Event {
         let fields =
             collect_keys(value_arr.iter()).expect("fields can be collected from array of objects");
 
-        let mut is_first = false;
+        // fields that the values of this event do not fit are widened and structs get the
+        // children this event adds to them, which changes the schema of the stream like new fields do
+        let mut is_first = value_arr.iter().fold(false, |widened, value| {
+            let structs = widen_structs(&mut stream_schema, value);
+            widen_fields(&mut stream_schema, value) || structs || widened
+        });
         let schema = match derive_arrow_schema(&stream_schema, fields) {
             Ok(schema) => schema,
             Err(_) => match infer_json_schema_from_iterator(value_arr.iter().map(Ok)) {
                 Ok(infer_schema) => {
-                    if let Err(err) = Schema::try_merge(vec![
+                    let mut fields = infer_schema.fields.to_vec();
+                    self.nested.map_fields(&mut fields);
+                    if let Err(err) = merge_schemas(vec![
                         Schema::new(stream_schema.values().cloned().collect::<Fields>()),
-                        infer_schema.clone(),
+                        Schema::new(fields.clone()),
                     ]) {
                         return Err(anyhow!("Could not merge schema of this event with that of the existing stream. {:?}", err));
                     }
                     is_first = true;
-                    infer_schema
-                        .fields
-                        .iter()
-                        .cloned()
+                    fields
+                        .into_iter()
+                        .map(|field| existing_field(&stream_schema, field))
                         .sorted_by(|a, b| a.name().cmp(b.name()))
                         .collect()
                 }
@@ -107,7 +119,8 @@ impl EventFormat for Event {
         let array_capacity = round_upto_multiple_of_64(data.len());
         let mut reader = ReaderBuilder::new(schema)
             .with_batch_size(array_capacity)
-            .with_coerce_primitive(false)
+            // numbers and booleans of fields widened to strings
+            .with_coerce_primitive(true)
             .build_decoder()?;
 
         reader.serialize(&data)?;
@@ -119,6 +132,101 @@ impl EventFormat for Event {
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct RejectedRecord {
+    pub index: usize,
+    pub reason: String,
+}
+
+// Split records into the ones that can be ingested together into a stream with the given schema
+// and the ones that can not, along with their position in the request and why they were rejected.
+// Records are checked one by one, new fields introduced by an accepted record are
+// also enforced on the records that follow it.
+pub fn partition_records(
+    schema: &HashMap<String, Arc<Field>>,
+    nested: &NestedObjects,
+    records: Vec<Value>,
+) -> (Vec<Value>, Vec<RejectedRecord>) {
+    let mut schema = schema.clone();
+    let mut accepted = Vec::with_capacity(records.len());
+    let mut rejected = Vec::new();
+
+    for (index, record) in records.into_iter().enumerate() {
+        match check_record(&mut schema, nested, record) {
+            Ok(record) => accepted.push(record),
+            Err(reason) => rejected.push(RejectedRecord { index, reason }),
+        }
+    }
+
+    (accepted, rejected)
+}
+
+fn check_record(
+    schema: &mut HashMap<String, Arc<Field>>,
+    nested: &NestedObjects,
+    record: Value,
+) -> Result<Value, String> {
+    if !record.is_object() {
+        return Err("record is not a JSON object".to_owned());
+    }
+    let record = flatten_json_body_nested(record, nested).map_err(|err| err.to_string())?;
+    let obj = record.as_object().expect("flattened object is an object");
+
+    for reserved in [
+        DEFAULT_TIMESTAMP_KEY,
+        DEFAULT_TAGS_KEY,
+        DEFAULT_METADATA_KEY,
+    ] {
+        if obj.contains_key(reserved) {
+            return Err(format!("field {} is a reserved field", reserved));
+        }
+    }
+
+    widen_fields(schema, &record);
+    widen_structs(schema, &record);
+    let mut fields: Vec<Arc<Field>> = Vec::with_capacity(obj.len());
+    let mut new_fields = Vec::new();
+    if obj.keys().any(|key| !schema.contains_key(key)) {
+        let inferred = infer_json_schema_from_iterator(std::iter::once(Ok(&record)))
+            .map_err(|err| format!("could not infer schema of record, {}", err))?;
+        let mut inferred = inferred.fields.to_vec();
+        nested.map_fields(&mut inferred);
+        for field in inferred.iter() {
+            match schema.get(field.name()) {
+                Some(existing) => fields.push(existing.clone()),
+                // fields with only null values do not fix the type of a column
+                None if field.data_type() == &DataType::Null => (),
+                None => {
+                    fields.push(field.clone());
+                    new_fields.push(field.clone());
+                }
+            }
+        }
+    } else {
+        fields.extend(obj.keys().filter_map(|key| schema.get(key).cloned()));
+    }
+
+    if let Some(reason) = mismatch_reason(&fields, &record) {
+        return Err(reason);
+    }
+
+    for field in new_fields {
+        schema.insert(field.name().to_owned(), field);
+    }
+
+    Ok(record)
+}
+
+// The type inferred for a field of the stream is replaced by the one of the stream.
+// Structs already have the children of the event, see evolution::widen_structs, and
+// widened fields keep their wider type for events with values of the narrower one
+fn existing_field(schema: &HashMap<String, Arc<Field>>, field: Arc<Field>) -> Arc<Field> {
+    match schema.get(field.name()) {
+        Some(existing) => existing.clone(),
+        None => field,
+    }
+}
+
 // Returns arrow schema with the fields that are present in the request body
 // This schema is an input to convert the request body to arrow record batch
 fn derive_arrow_schema(
@@ -154,18 +262,26 @@ fn collect_keys<'a>(values: impl Iterator<Item = &'a Value>) -> Result<Vec<&'a s
 }
 
 fn fields_mismatch(schema: &[Arc<Field>], body: &Value) -> bool {
+    mismatch_reason(schema, body).is_some()
+}
+
+fn mismatch_reason(schema: &[Arc<Field>], body: &Value) -> Option<String> {
     for (name, val) in body.as_object().expect("body is of object variant") {
         if val.is_null() {
             continue;
         }
         let Some(field) = get_field(schema, name) else {
-            return true;
+            return Some(format!("field {} is not part of the schema", name));
         };
         if !valid_type(field.data_type(), val) {
-            return true;
+            return Some(format!(
+                "field {} has a value that does not match its type {}",
+                name,
+                field.data_type()
+            ));
         }
     }
-    false
+    None
 }
 
 fn valid_type(data_type: &DataType, value: &Value) -> bool {
@@ -173,8 +289,10 @@ fn valid_type(data_type: &DataType, value: &Value) -> bool {
         DataType::Boolean => value.is_boolean(),
         DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => value.is_i64(),
         DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => value.is_u64(),
-        DataType::Float16 | DataType::Float32 | DataType::Float64 => value.is_f64(),
-        DataType::Utf8 => value.is_string(),
+        DataType::Float16 | DataType::Float32 => value.is_f64(),
+        // integers fit fields widened to floats and any scalar fits strings, see evolution::widen
+        DataType::Float64 => value.is_number(),
+        DataType::Utf8 => value.is_string() || value.is_number() || value.is_boolean(),
         DataType::List(field) => {
             let data_type = field.data_type();
             if let Value::Array(arr) = value {
@@ -192,18 +310,14 @@ fn valid_type(data_type: &DataType, value: &Value) -> bool {
         DataType::Struct(fields) => {
             if let Value::Object(val) = value {
                 for (key, value) in val {
-                    let field = (0..fields.len())
-                        .find(|idx| fields[*idx].name() == key)
-                        .map(|idx| &fields[idx]);
-
-                    if let Some(field) = field {
-                        if value.is_null() {
-                            continue;
-                        }
-                        if !valid_type(field.data_type(), value) {
-                            return false;
-                        }
-                    } else {
+                    // keys with null values are not added to the struct, see evolution::widen_structs
+                    if value.is_null() {
+                        continue;
+                    }
+                    let Some((_, field)) = fields.find(key) else {
+                        return false;
+                    };
+                    if !valid_type(field.data_type(), value) {
                         return false;
                     }
                 }
@@ -212,7 +326,14 @@ fn valid_type(data_type: &DataType, value: &Value) -> bool {
                 false
             }
         }
+        // maps are only created with string values, see nested::ObjectMode::Map
+        DataType::Map(_, _) => value.as_object().map_or(false, |object| {
+            object
+                .values()
+                .all(|value| value.is_string() || value.is_null())
+        }),
         DataType::Timestamp(_, _) => value.is_string() || value.is_number(),
-        _ => unreachable!(),
+        // columns of other types can only be created by non JSON sources (Arrow Flight)
+        _ => false,
     }
 }
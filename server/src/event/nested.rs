@@ -0,0 +1,226 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use arrow_schema::{DataType, Field, Fields};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// How the JSON objects of the top level fields of a stream are stored, set with
+// PUT /logstream/{name}/nested
+// {
+//     "default": "flatten",
+//     "fields": { "request": "struct", "headers": "map", "labels": "map" }
+// }
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NestedObjects {
+    // mode of the fields that are not listed
+    #[serde(default)]
+    pub default: ObjectMode,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, ObjectMode>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectMode {
+    // objects are flattened into a column per key, {"a": {"b": 1}} becomes the column a_b
+    #[default]
+    Flatten,
+    // objects are kept as a Struct column, and arrays of objects as a List<Struct> column.
+    // Keys that are new to the struct are added to it as children, null for the rows before.
+    // Events with other types for a key are rejected
+    Struct,
+    // objects are kept as a Map<Utf8, Utf8> column, and arrays of objects as a List<Map>
+    // column. Values that are not strings are stored as their JSON text. Suited to objects
+    // with dynamic keys, such as headers or labels
+    Map,
+}
+
+impl NestedObjects {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.fields.keys().any(|field| field.is_empty()) {
+            return Err("nested object field can not be empty".to_owned());
+        }
+        Ok(())
+    }
+
+    pub fn mode(&self, field: &str) -> ObjectMode {
+        self.fields.get(field).copied().unwrap_or(self.default)
+    }
+
+    // true if every object is flattened, as is the case for streams without this setting
+    pub fn flatten_all(&self) -> bool {
+        self.default == ObjectMode::Flatten
+            && self
+                .fields
+                .values()
+                .all(|mode| *mode == ObjectMode::Flatten)
+    }
+
+    // sets the type of fields kept as maps, inferred as structs from the JSON objects
+    pub fn map_fields(&self, fields: &mut [Arc<Field>]) {
+        if self.flatten_all() {
+            return;
+        }
+        for field in fields {
+            if self.mode(field.name()) != ObjectMode::Map {
+                continue;
+            }
+            match field.data_type() {
+                DataType::Struct(_) => {
+                    *field = Arc::new(Field::new(field.name(), map_type(), true));
+                }
+                DataType::List(item) if matches!(item.data_type(), DataType::Struct(_)) => {
+                    let item = Field::new(item.name(), map_type(), true);
+                    *field = Arc::new(Field::new(
+                        field.name(),
+                        DataType::List(Arc::new(item)),
+                        true,
+                    ));
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+// true for arrays of objects, which may have nulls in between
+pub fn is_objects(values: &[Value]) -> bool {
+    values.iter().any(Value::is_object)
+        && values
+            .iter()
+            .all(|value| value.is_object() || value.is_null())
+}
+
+// the JSON value an object is stored as, None if there is nothing to store
+pub fn keep_object(mode: ObjectMode, object: Map<String, Value>) -> Option<Value> {
+    match mode {
+        ObjectMode::Flatten => Some(Value::Object(object)),
+        ObjectMode::Struct => strip_empty_objects(Value::Object(object)),
+        ObjectMode::Map => Some(Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::Null => Value::Null,
+                        Value::String(s) => Value::String(s),
+                        value => Value::String(value.to_string()),
+                    };
+                    (key, value)
+                })
+                .collect(),
+        )),
+    }
+}
+
+// Struct columns need at least one child, empty objects are treated as null
+fn strip_empty_objects(value: Value) -> Option<Value> {
+    match value {
+        Value::Object(object) => {
+            let object: Map<String, Value> = object
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, strip_empty_objects(value)?)))
+                .collect();
+            (!object.is_empty()).then_some(Value::Object(object))
+        }
+        Value::Array(values) => Some(Value::Array(
+            values
+                .into_iter()
+                .map(|value| strip_empty_objects(value).unwrap_or(Value::Null))
+                .collect(),
+        )),
+        value => Some(value),
+    }
+}
+
+pub fn map_type() -> DataType {
+    let entries = Fields::from(vec![
+        Field::new("keys", DataType::Utf8, false),
+        Field::new("values", DataType::Utf8, true),
+    ]);
+    DataType::Map(
+        Arc::new(Field::new("entries", DataType::Struct(entries), false)),
+        false,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_schema::{DataType, Field, Fields};
+    use serde_json::json;
+
+    use super::{keep_object, map_type, NestedObjects, ObjectMode};
+
+    #[test]
+    fn modes_of_fields() {
+        let nested: NestedObjects =
+            serde_json::from_value(json!({ "default": "struct", "fields": { "headers": "map" } }))
+                .unwrap();
+        assert_eq!(nested.mode("headers"), ObjectMode::Map);
+        assert_eq!(nested.mode("request"), ObjectMode::Struct);
+        assert!(!nested.flatten_all());
+        assert!(NestedObjects::default().flatten_all());
+        assert!(
+            serde_json::from_value::<NestedObjects>(json!({ "fields": { "a": "list" } })).is_err()
+        );
+
+        let mut fields = vec![
+            Arc::new(Field::new(
+                "headers",
+                DataType::Struct(Fields::from(vec![Field::new("a", DataType::Utf8, true)])),
+                true,
+            )),
+            Arc::new(Field::new(
+                "request",
+                DataType::Struct(Fields::from(vec![Field::new("a", DataType::Utf8, true)])),
+                true,
+            )),
+        ];
+        nested.map_fields(&mut fields);
+        assert_eq!(fields[0].data_type(), &map_type());
+        assert!(matches!(fields[1].data_type(), DataType::Struct(_)));
+    }
+
+    #[test]
+    fn objects_are_kept() {
+        let object = json!({ "a": "x", "b": 1, "c": { "d": true }, "e": null, "f": {} });
+        let object = object.as_object().unwrap().clone();
+
+        assert_eq!(
+            keep_object(ObjectMode::Map, object.clone()).unwrap(),
+            json!({ "a": "x", "b": "1", "c": "{\"d\":true}", "e": null, "f": "{}" })
+        );
+        assert_eq!(
+            keep_object(ObjectMode::Struct, object).unwrap(),
+            json!({ "a": "x", "b": 1, "c": { "d": true }, "e": null })
+        );
+        assert_eq!(
+            keep_object(
+                ObjectMode::Struct,
+                json!({ "f": {} }).as_object().unwrap().clone()
+            ),
+            None
+        );
+    }
+}
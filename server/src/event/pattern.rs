@@ -0,0 +1,93 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::ops::Deref;
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+// Regex of a stream's configuration, compiled once when it is deserialized
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl Deref for Pattern {
+    type Target = Regex;
+
+    fn deref(&self) -> &Regex {
+        &self.0
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern)
+            .map(Self)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+// Pattern whose named capture groups become fields, it must have at least one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct CapturePattern(Pattern);
+
+impl CapturePattern {
+    // the named capture groups that match in the text, None if the pattern does not match
+    pub fn fields(&self, text: &str) -> Option<Map<String, Value>> {
+        let captures = self.0.captures(text)?;
+        Some(
+            self.0
+                .capture_names()
+                .flatten()
+                .filter_map(|name| {
+                    let value = captures.name(name)?.as_str();
+                    Some((name.to_owned(), Value::String(value.to_owned())))
+                })
+                .collect(),
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for CapturePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = Pattern::deserialize(deserializer)?;
+        if pattern.capture_names().flatten().next().is_none() {
+            return Err(serde::de::Error::custom(format!(
+                "pattern {} has no named capture group",
+                pattern.as_str()
+            )));
+        }
+        Ok(Self(pattern))
+    }
+}
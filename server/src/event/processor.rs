@@ -0,0 +1,199 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use super::pattern::CapturePattern;
+
+const REDACTED: &str = "[REDACTED]";
+
+// Processors of a stream are applied in order to every (flattened) json record
+// before it is converted to arrow. They are set with PUT /logstream/{name}/processors
+// [
+//     { "type": "drop", "fields": ["password"] },
+//     { "type": "rename", "from": "msg", "to": "message" },
+//     { "type": "redact", "fields": ["email"], "method": "hash" },
+//     { "type": "extract", "field": "message", "pattern": "status=(?P<status>\\d+)" },
+//     { "type": "add", "field": "env", "value": "prod" }
+// ]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Processor {
+    // remove fields from the record
+    Drop {
+        fields: Vec<String>,
+    },
+    // move the value of a field to another field, replacing its value if present
+    Rename {
+        from: String,
+        to: String,
+    },
+    // replace the value of fields with a placeholder or with its sha256 hash
+    Redact {
+        fields: Vec<String>,
+        #[serde(default)]
+        method: RedactMethod,
+    },
+    // add the named capture groups of a regex matched against a string field as fields
+    Extract {
+        field: String,
+        pattern: CapturePattern,
+    },
+    // add a field with a constant value, existing values are kept unless overwrite is set
+    Add {
+        field: String,
+        value: Value,
+        #[serde(default)]
+        overwrite: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactMethod {
+    #[default]
+    Mask,
+    Hash,
+}
+
+impl Processor {
+    fn apply(&self, record: &mut Map<String, Value>) {
+        match self {
+            Processor::Drop { fields } => {
+                for field in fields {
+                    record.remove(field);
+                }
+            }
+            Processor::Rename { from, to } => {
+                if let Some(value) = record.remove(from) {
+                    record.insert(to.to_owned(), value);
+                }
+            }
+            Processor::Redact { fields, method } => {
+                for field in fields {
+                    if let Some(value) = record.get_mut(field) {
+                        if !value.is_null() {
+                            *value = redact(value, *method);
+                        }
+                    }
+                }
+            }
+            Processor::Extract { field, pattern } => {
+                let Some(Value::String(text)) = record.get(field) else {
+                    return;
+                };
+                if let Some(fields) = pattern.fields(text) {
+                    record.extend(fields);
+                }
+            }
+            Processor::Add {
+                field,
+                value,
+                overwrite,
+            } => {
+                if *overwrite || !record.contains_key(field) {
+                    record.insert(field.to_owned(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+fn redact(value: &Value, method: RedactMethod) -> Value {
+    match method {
+        RedactMethod::Mask => Value::String(REDACTED.to_owned()),
+        RedactMethod::Hash => {
+            let digest = match value {
+                Value::String(s) => Sha256::digest(s.as_bytes()),
+                value => Sha256::digest(value.to_string().as_bytes()),
+            };
+            Value::String(hex::encode(digest))
+        }
+    }
+}
+
+// applies the processors in order to a flattened json record
+pub fn apply(processors: &[Processor], record: &mut Map<String, Value>) {
+    for processor in processors {
+        processor.apply(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{apply, Processor};
+
+    fn processors(value: serde_json::Value) -> Vec<Processor> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn processors_are_applied_in_order() {
+        let processors = processors(json!([
+            { "type": "drop", "fields": ["password"] },
+            { "type": "rename", "from": "msg", "to": "message" },
+            { "type": "redact", "fields": ["email"], "method": "hash" },
+            { "type": "redact", "fields": ["card", "missing"] },
+            { "type": "extract", "field": "message", "pattern": "status=(?P<status>\\d+) (?P<path>/\\S*)" },
+            { "type": "add", "field": "env", "value": "prod" },
+            { "type": "add", "field": "host", "value": "default" }
+        ]));
+
+        let mut record = json!({
+            "password": "secret",
+            "msg": "GET status=200 /index.html",
+            "email": "user@example.com",
+            "card": "4111",
+            "host": "node-1"
+        })
+        .as_object()
+        .unwrap()
+        .clone();
+        apply(&processors, &mut record);
+
+        assert_eq!(
+            serde_json::Value::Object(record),
+            json!({
+                "message": "GET status=200 /index.html",
+                "email": "b4c9a289323b21a01c3e940f150eb9b8c542587f1abfd8f0e1cc1ffc5e475514",
+                "card": "[REDACTED]",
+                "status": "200",
+                "path": "/index.html",
+                "env": "prod",
+                "host": "node-1"
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_processors_are_err() {
+        let invalid = [
+            json!([{ "type": "extract", "field": "message", "pattern": "(" }]),
+            json!([{ "type": "extract", "field": "message", "pattern": "\\d+" }]),
+            json!([{ "type": "uppercase", "fields": ["a"] }]),
+            json!([{ "type": "drop" }]),
+        ];
+        for processors in invalid {
+            assert!(serde_json::from_value::<Vec<Processor>>(processors).is_err());
+        }
+    }
+}
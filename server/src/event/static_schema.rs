@@ -0,0 +1,372 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use arrow_array::RecordBatch;
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::Utc;
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::compute::kernels::cast_utils::string_to_datetime;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+use crate::utils::json::flatten_json_body;
+
+use super::nested::map_type;
+use super::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY};
+
+// Schema declared when a stream is created, sent as the body of PUT /logstream/{name}
+// {
+//     "fields": [
+//         { "name": "host", "data_type": "string" },
+//         { "name": "status", "data_type": "int" }
+//     ],
+//     "policy": "reject"
+// }
+// The schema of such a stream is fixed, it is never extended with inferred fields.
+#[derive(Debug, Deserialize)]
+pub struct StaticSchema {
+    pub fields: Vec<StaticField>,
+    #[serde(default)]
+    pub policy: SchemaPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StaticField {
+    pub name: String,
+    pub data_type: String,
+}
+
+// What happens to events that do not conform to the static schema of a stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaPolicy {
+    // events with unknown fields or values of the wrong type are rejected
+    #[default]
+    Reject,
+    // unknown fields are dropped and values are converted to the declared type,
+    // values that can not be converted are set to null
+    Coerce,
+}
+
+impl StaticSchema {
+    // arrow schema of the stream, including the p_timestamp, p_tags and p_metadata
+    // fields every event of the stream is stored with
+    pub fn to_arrow_schema(&self) -> Result<Schema, String> {
+        if self.fields.is_empty() {
+            return Err("static schema has no fields".to_owned());
+        }
+
+        let mut names = HashSet::new();
+        let mut fields = Vec::with_capacity(self.fields.len() + 3);
+        for field in &self.fields {
+            if field.name.is_empty() {
+                return Err("field name can not be empty".to_owned());
+            }
+            if [
+                DEFAULT_TIMESTAMP_KEY,
+                DEFAULT_TAGS_KEY,
+                DEFAULT_METADATA_KEY,
+            ]
+            .contains(&field.name.as_str())
+            {
+                return Err(format!("field {} is a reserved field", field.name));
+            }
+            if !names.insert(field.name.as_str()) {
+                return Err(format!("field {} is declared more than once", field.name));
+            }
+            fields.push(Field::new(&field.name, data_type(&field.data_type)?, true));
+        }
+        fields.sort_by(|a, b| a.name().cmp(b.name()));
+
+        fields.insert(
+            0,
+            Field::new(
+                DEFAULT_TIMESTAMP_KEY,
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+        );
+        fields.push(Field::new(DEFAULT_TAGS_KEY, map_type(), true));
+        fields.push(Field::new(DEFAULT_METADATA_KEY, map_type(), true));
+
+        Ok(Schema::new(fields))
+    }
+}
+
+fn data_type(name: &str) -> Result<DataType, String> {
+    match name {
+        "string" => Ok(DataType::Utf8),
+        "int" => Ok(DataType::Int64),
+        "float" => Ok(DataType::Float64),
+        "boolean" => Ok(DataType::Boolean),
+        "datetime" => Ok(DataType::Timestamp(TimeUnit::Millisecond, None)),
+        _ => Err(format!(
+            "unsupported data type {}, expected one of string, int, float, boolean or datetime",
+            name
+        )),
+    }
+}
+
+// Checks a single json record against the static schema of a stream.
+// Returns the flattened record as it is to be ingested or the reason it was rejected.
+pub fn enforce_record(
+    policy: SchemaPolicy,
+    schema: &HashMap<String, Arc<Field>>,
+    record: Value,
+) -> Result<Value, String> {
+    let Value::Object(_) = record else {
+        return Err("record is not a JSON object".to_owned());
+    };
+    let Value::Object(record) = flatten_json_body(record).map_err(|err| err.to_string())? else {
+        unreachable!("flattened object is an object")
+    };
+
+    let mut res = Map::with_capacity(record.len());
+    for (name, value) in record {
+        let Some(field) = schema.get(&name) else {
+            match policy {
+                SchemaPolicy::Reject => {
+                    return Err(format!("field {} is not part of the static schema", name))
+                }
+                SchemaPolicy::Coerce => continue,
+            }
+        };
+
+        let value = match (convert(field.data_type(), value, policy), policy) {
+            (Ok(value), _) => value,
+            (Err(_), SchemaPolicy::Coerce) => Value::Null,
+            (Err(value), SchemaPolicy::Reject) => {
+                return Err(format!(
+                    "field {} has value {} that does not match its type {}",
+                    name,
+                    value,
+                    field.data_type()
+                ))
+            }
+        };
+        res.insert(name, value);
+    }
+
+    Ok(Value::Object(res))
+}
+
+// Converts a json value to the representation expected for the given type.
+// Lossless numeric conversions (integer to float) are always applied, values of
+// other types are only parsed or formatted with the coerce policy. The value is
+// returned back as the error if it can not be represented by the type.
+fn convert(data_type: &DataType, value: Value, policy: SchemaPolicy) -> Result<Value, Value> {
+    let coerce = policy == SchemaPolicy::Coerce;
+    match (data_type, value) {
+        (_, Value::Null) => Ok(Value::Null),
+        (DataType::Utf8, value @ Value::String(_)) => Ok(value),
+        (DataType::Utf8, Value::Bool(b)) if coerce => Ok(Value::String(b.to_string())),
+        (DataType::Utf8, Value::Number(n)) if coerce => Ok(Value::String(n.to_string())),
+        (DataType::Int64, Value::Number(n)) if n.is_i64() => Ok(Value::Number(n)),
+        (DataType::Int64, Value::Number(n)) => match n.as_f64() {
+            Some(f) if f.fract() == 0.0 && f >= i64::MIN as f64 && f <= i64::MAX as f64 => {
+                Ok(Value::from(f as i64))
+            }
+            _ => Err(Value::Number(n)),
+        },
+        (DataType::Int64, Value::String(s)) if coerce => match s.trim().parse::<i64>() {
+            Ok(n) => Ok(Value::from(n)),
+            Err(_) => Err(Value::String(s)),
+        },
+        (DataType::Float64, Value::Number(n)) => match n.as_f64().and_then(Number::from_f64) {
+            Some(f) => Ok(Value::Number(f)),
+            None => Err(Value::Number(n)),
+        },
+        (DataType::Float64, Value::String(s)) if coerce => {
+            match s.trim().parse::<f64>().ok().and_then(Number::from_f64) {
+                Some(f) => Ok(Value::Number(f)),
+                None => Err(Value::String(s)),
+            }
+        }
+        (DataType::Boolean, value @ Value::Bool(_)) => Ok(value),
+        (DataType::Boolean, Value::String(s)) if coerce => match s.trim().to_lowercase().as_str() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(Value::String(s)),
+        },
+        (DataType::Timestamp(_, _), Value::String(s)) => match string_to_datetime(&Utc, &s) {
+            Ok(_) => Ok(Value::String(s)),
+            Err(_) => Err(Value::String(s)),
+        },
+        (DataType::Timestamp(_, _), Value::Number(n)) if n.is_i64() => Ok(Value::Number(n)),
+        (_, value) => Err(value),
+    }
+}
+
+// Checks an arrow record batch against the static schema of a stream.
+// With the coerce policy unknown columns are dropped and columns of a different
+// type are cast to the declared type, values that fail to cast become null.
+pub fn enforce_batch(
+    policy: SchemaPolicy,
+    schema: &HashMap<String, Arc<Field>>,
+    rb: RecordBatch,
+) -> Result<RecordBatch, String> {
+    let mut fields = Vec::with_capacity(rb.num_columns());
+    let mut columns = Vec::with_capacity(rb.num_columns());
+
+    for (field, column) in rb.schema().fields().iter().zip(rb.columns()) {
+        let Some(declared) = schema.get(field.name()) else {
+            match policy {
+                SchemaPolicy::Reject => {
+                    return Err(format!(
+                        "field {} is not part of the static schema",
+                        field.name()
+                    ))
+                }
+                SchemaPolicy::Coerce => continue,
+            }
+        };
+
+        if declared.data_type() == field.data_type() {
+            fields.push(field.clone());
+            columns.push(column.clone());
+            continue;
+        }
+
+        match policy {
+            SchemaPolicy::Reject => {
+                return Err(format!(
+                    "field {} of type {} does not match its type {}",
+                    field.name(),
+                    field.data_type(),
+                    declared.data_type()
+                ))
+            }
+            SchemaPolicy::Coerce => {
+                let column = cast(column, declared.data_type())
+                    .map_err(|err| format!("field {} can not be cast, {}", field.name(), err))?;
+                fields.push(declared.clone());
+                columns.push(column);
+            }
+        }
+    }
+
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arrow_array::{Array, ArrayRef, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field};
+    use serde_json::json;
+
+    use super::{enforce_batch, enforce_record, SchemaPolicy, StaticSchema};
+
+    fn stream_schema() -> HashMap<String, Arc<Field>> {
+        let schema: StaticSchema = serde_json::from_value(json!({
+            "fields": [
+                { "name": "host", "data_type": "string" },
+                { "name": "status", "data_type": "int" },
+                { "name": "latency", "data_type": "float" },
+                { "name": "time", "data_type": "datetime" }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(schema.policy, SchemaPolicy::Reject);
+
+        schema
+            .to_arrow_schema()
+            .unwrap()
+            .fields()
+            .iter()
+            .map(|field| (field.name().to_owned(), field.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn invalid_static_schema_is_err() {
+        let schema = |fields| {
+            serde_json::from_value::<StaticSchema>(json!({ "fields": fields }))
+                .unwrap()
+                .to_arrow_schema()
+        };
+
+        assert!(schema(json!([])).is_err());
+        assert!(schema(json!([{ "name": "a", "data_type": "uuid" }])).is_err());
+        assert!(schema(json!([{ "name": "p_tags", "data_type": "string" }])).is_err());
+        assert!(schema(json!([
+            { "name": "a", "data_type": "string" },
+            { "name": "a", "data_type": "int" }
+        ]))
+        .is_err());
+
+        let arrow_schema = schema(json!([{ "name": "a", "data_type": "string" }])).unwrap();
+        let names: Vec<&String> = arrow_schema.fields().iter().map(|f| f.name()).collect();
+        assert_eq!(names, ["p_timestamp", "a", "p_tags", "p_metadata"]);
+    }
+
+    #[test]
+    fn reject_policy() {
+        let schema = stream_schema();
+
+        let record =
+            json!({"host": "a", "status": 200, "latency": 3, "time": "2024-01-12T02:33:00Z"});
+        assert_eq!(
+            enforce_record(SchemaPolicy::Reject, &schema, record).unwrap(),
+            json!({"host": "a", "status": 200, "latency": 3.0, "time": "2024-01-12T02:33:00Z"})
+        );
+
+        assert!(enforce_record(SchemaPolicy::Reject, &schema, json!({"user": "a"})).is_err());
+        assert!(enforce_record(SchemaPolicy::Reject, &schema, json!({"status": "200"})).is_err());
+        assert!(enforce_record(SchemaPolicy::Reject, &schema, json!({"host": 1})).is_err());
+        assert!(enforce_record(SchemaPolicy::Reject, &schema, json!({"time": "today"})).is_err());
+    }
+
+    #[test]
+    fn coerce_policy() {
+        let schema = stream_schema();
+
+        let record = json!({"host": 1, "status": "404", "latency": "ok", "user": "a"});
+        assert_eq!(
+            enforce_record(SchemaPolicy::Coerce, &schema, record).unwrap(),
+            json!({"host": "1", "status": 404, "latency": null})
+        );
+    }
+
+    #[test]
+    fn batch_with_policy() {
+        let schema = stream_schema();
+        let rb = RecordBatch::try_from_iter([
+            (
+                "status",
+                Arc::new(StringArray::from(vec!["200", "oops"])) as ArrayRef,
+            ),
+            ("user", Arc::new(Int64Array::from(vec![1, 2])) as ArrayRef),
+        ])
+        .unwrap();
+
+        assert!(enforce_batch(SchemaPolicy::Reject, &schema, rb.clone()).is_err());
+
+        let rb = enforce_batch(SchemaPolicy::Coerce, &schema, rb).unwrap();
+        assert_eq!(rb.num_columns(), 1);
+        assert_eq!(rb.schema().field(0).data_type(), &DataType::Int64);
+        let status = rb.column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(status.value(0), 200);
+        assert!(status.is_null(1));
+    }
+}
@@ -0,0 +1,191 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+mod grok;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::pattern::{CapturePattern, Pattern};
+
+pub use self::grok::Grok;
+
+// column that holds the raw text of events that are not parsed into fields
+pub const MESSAGE_KEY: &str = "message";
+
+// Parser for plain text (text/plain) bodies of a stream, set with PUT /logstream/{name}/parser
+// {
+//     "pattern": { "grok": "%{COMBINEDAPACHELOG}" },
+//     "multiline": { "continuation": "^\\s" }
+// }
+// Every line of the body is an event, unless multiline rules group them.
+// Without a pattern, or if an event does not match it, the raw text is kept in the message column.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextParser {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<LinePattern>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multiline: Option<Multiline>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinePattern {
+    // grok expression, %{SYNTAX:field} or %{SYNTAX:field:int}
+    Grok(Grok),
+    // regex, every named capture group becomes a field
+    Regex(CapturePattern),
+}
+
+// How lines are grouped into a single event, e.g. for stack traces
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Multiline {
+    // lines matching the regex are appended to the previous event
+    Continuation(Pattern),
+    // lines matching the regex begin a new event, all others are appended to the previous one
+    Start(Pattern),
+}
+
+impl TextParser {
+    // splits the text into events and parses each of them into a json record
+    pub fn parse(&self, text: &str) -> Vec<Value> {
+        self.events(text)
+            .into_iter()
+            .map(|event| Value::Object(self.parse_event(event)))
+            .collect()
+    }
+
+    fn events(&self, text: &str) -> Vec<String> {
+        let mut events: Vec<String> = Vec::new();
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let append = match &self.multiline {
+                None => false,
+                Some(Multiline::Continuation(regex)) => regex.is_match(line),
+                Some(Multiline::Start(regex)) => !regex.is_match(line),
+            };
+            match events.last_mut() {
+                Some(event) if append => {
+                    event.push('\n');
+                    event.push_str(line);
+                }
+                _ => events.push(line.to_owned()),
+            }
+        }
+        events
+    }
+
+    fn parse_event(&self, event: String) -> Map<String, Value> {
+        let fields = match &self.pattern {
+            Some(LinePattern::Grok(grok)) => grok.parse(&event),
+            Some(LinePattern::Regex(regex)) => regex.fields(&event),
+            None => None,
+        };
+
+        fields.unwrap_or_else(|| Map::from_iter([(MESSAGE_KEY.to_owned(), Value::String(event))]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::TextParser;
+
+    fn parser(value: serde_json::Value) -> TextParser {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn lines_without_pattern() {
+        let records = TextParser::default().parse("first line\r\n\nsecond line\n");
+        assert_eq!(
+            records,
+            vec![
+                json!({"message": "first line"}),
+                json!({"message": "second line"})
+            ]
+        );
+    }
+
+    #[test]
+    fn regex_pattern_with_unmatched_line() {
+        let parser = parser(json!({
+            "pattern": { "regex": "^(?P<level>[A-Z]+) (?P<msg>.*)$" }
+        }));
+        let records = parser.parse("INFO started\nnot matching");
+        assert_eq!(
+            records,
+            vec![
+                json!({"level": "INFO", "msg": "started"}),
+                json!({"message": "not matching"})
+            ]
+        );
+    }
+
+    #[test]
+    fn multiline_stack_trace() {
+        let text = concat!(
+            "2024-01-12 02:33:00 ERROR failed\n",
+            "java.lang.NullPointerException: oops\n",
+            "    at com.example.App.main(App.java:12)\n",
+            "\tat com.example.App.run(App.java:7)\n",
+            "2024-01-12 02:33:01 INFO recovered\n"
+        );
+
+        let continuation = parser(json!({ "multiline": { "continuation": "^\\s" } }));
+        let records = continuation.parse(text);
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[1],
+            json!({"message": "java.lang.NullPointerException: oops\n    at com.example.App.main(App.java:12)\n\tat com.example.App.run(App.java:7)"})
+        );
+
+        let start = parser(json!({
+            "pattern": { "grok": "%{TIMESTAMP_ISO8601:time} %{LOGLEVEL:level} %{GREEDYDATA:msg}" },
+            "multiline": { "start": "^\\d{4}-\\d{2}-\\d{2} " }
+        }));
+        let records = start.parse(text);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["level"], json!("ERROR"));
+        assert_eq!(
+            records[0]["msg"],
+            json!("failed\njava.lang.NullPointerException: oops\n    at com.example.App.main(App.java:12)\n\tat com.example.App.run(App.java:7)")
+        );
+        assert_eq!(records[1]["msg"], json!("recovered"));
+    }
+
+    #[test]
+    fn invalid_parser_is_err() {
+        assert!(
+            serde_json::from_value::<TextParser>(json!({ "pattern": { "regex": "\\d+" } }))
+                .is_err()
+        );
+        assert!(
+            serde_json::from_value::<TextParser>(json!({ "pattern": { "regex": "(" } })).is_err()
+        );
+        assert!(serde_json::from_value::<TextParser>(
+            json!({ "pattern": { "grok": "%{NOT_A_PATTERN:a}" } })
+        )
+        .is_err());
+    }
+}
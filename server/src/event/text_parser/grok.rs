@@ -0,0 +1,305 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Number, Value};
+
+// patterns can reference each other, this bounds how deep references are followed
+const MAX_DEPTH: usize = 16;
+
+// %{SYNTAX}, %{SYNTAX:field} or %{SYNTAX:field:type}
+static REFERENCE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"%\{(?P<syntax>[A-Z0-9_]+)(?::(?P<field>[^:}]+))?(?::(?P<type>int|float))?\}")
+        .unwrap()
+});
+
+// Subset of the logstash grok patterns, rewritten where needed for
+// the regex crate which has no lookaround or atomic groups.
+const PATTERNS: &[(&str, &str)] = &[
+    ("USERNAME", r"[a-zA-Z0-9._-]+"),
+    ("USER", r"%{USERNAME}"),
+    (
+        "EMAILLOCALPART",
+        r"[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]+(?:\.[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]+)*",
+    ),
+    ("EMAILADDRESS", r"%{EMAILLOCALPART}@%{HOSTNAME}"),
+    ("INT", r"(?:[+-]?(?:[0-9]+))"),
+    ("BASE10NUM", r"(?:[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+))"),
+    ("NUMBER", r"(?:%{BASE10NUM})"),
+    ("BASE16NUM", r"(?:0[xX]?[0-9a-fA-F]+)"),
+    ("POSINT", r"\b(?:[1-9][0-9]*)\b"),
+    ("NONNEGINT", r"\b(?:[0-9]+)\b"),
+    ("WORD", r"\b\w+\b"),
+    ("NOTSPACE", r"\S+"),
+    ("SPACE", r"\s*"),
+    ("DATA", r".*?"),
+    ("GREEDYDATA", r".*"),
+    (
+        "QUOTEDSTRING",
+        r#"(?:"(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'|`(?:[^`\\]|\\.)*`)"#,
+    ),
+    ("QS", r"%{QUOTEDSTRING}"),
+    (
+        "UUID",
+        r"[A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}",
+    ),
+    ("MAC", r"(?:(?:[A-Fa-f0-9]{2}[:-]){5}[A-Fa-f0-9]{2})"),
+    (
+        "IPV6",
+        r"(?:(?:[0-9A-Fa-f]{1,4})?:){2,7}(?:[0-9A-Fa-f]{1,4})?",
+    ),
+    (
+        "IPV4",
+        r"(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)",
+    ),
+    ("IP", r"(?:%{IPV6}|%{IPV4})"),
+    (
+        "HOSTNAME",
+        r"\b(?:[0-9A-Za-z][0-9A-Za-z-]{0,62})(?:\.(?:[0-9A-Za-z][0-9A-Za-z-]{0,62}))*\.?",
+    ),
+    ("IPORHOST", r"(?:%{IP}|%{HOSTNAME})"),
+    ("HOSTPORT", r"%{IPORHOST}:%{POSINT}"),
+    ("PATH", r"(?:/[^\s]*)+"),
+    ("URIPROTO", r"[A-Za-z]+(?:\+[A-Za-z+]+)?"),
+    ("URIPATH", r"(?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_\-]*)+"),
+    ("URIPARAM", r"\?[A-Za-z0-9$.+!*'|(){},~@#%&/=:;_?\-\[\]<>]*"),
+    ("URIPATHPARAM", r"%{URIPATH}(?:%{URIPARAM})?"),
+    (
+        "URI",
+        r"%{URIPROTO}://(?:%{USER}(?::[^@]*)?@)?(?:%{IPORHOST}(?::%{POSINT})?)?(?:%{URIPATHPARAM})?",
+    ),
+    (
+        "MONTH",
+        r"\b(?:[Jj]an(?:uary)?|[Ff]eb(?:ruary)?|[Mm]ar(?:ch)?|[Aa]pr(?:il)?|[Mm]ay|[Jj]un(?:e)?|[Jj]ul(?:y)?|[Aa]ug(?:ust)?|[Ss]ep(?:tember)?|[Oo]ct(?:ober)?|[Nn]ov(?:ember)?|[Dd]ec(?:ember)?)\b",
+    ),
+    ("MONTHNUM", r"(?:0?[1-9]|1[0-2])"),
+    ("MONTHDAY", r"(?:(?:0[1-9])|(?:[12][0-9])|(?:3[01])|[1-9])"),
+    (
+        "DAY",
+        r"(?:Mon(?:day)?|Tue(?:sday)?|Wed(?:nesday)?|Thu(?:rsday)?|Fri(?:day)?|Sat(?:urday)?|Sun(?:day)?)",
+    ),
+    ("YEAR", r"(?:\d\d){1,2}"),
+    ("HOUR", r"(?:2[0123]|[01]?[0-9])"),
+    ("MINUTE", r"(?:[0-5][0-9])"),
+    ("SECOND", r"(?:(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?)"),
+    ("TIME", r"%{HOUR}:%{MINUTE}(?::%{SECOND})?"),
+    ("ISO8601_TIMEZONE", r"(?:Z|[+-]%{HOUR}(?::?%{MINUTE}))"),
+    (
+        "TIMESTAMP_ISO8601",
+        r"%{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?",
+    ),
+    ("HTTPDATE", r"%{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}"),
+    ("SYSLOGTIMESTAMP", r"%{MONTH} +%{MONTHDAY} %{TIME}"),
+    (
+        "LOGLEVEL",
+        r"(?:[Aa]lert|ALERT|[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo|INFO|[Ww]arn(?:ing)?|WARN(?:ING)?|[Ee]rr(?:or)?|ERR(?:OR)?|[Cc]rit(?:ical)?|CRIT(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|[Ee]merg(?:ency)?|EMERG(?:ENCY)?)",
+    ),
+    (
+        "JAVACLASS",
+        r"(?:[a-zA-Z$_][a-zA-Z$_0-9]*\.)*[a-zA-Z$_][a-zA-Z$_0-9]*",
+    ),
+    ("HTTPDUSER", r"(?:%{EMAILADDRESS}|%{USER})"),
+    (
+        "COMMONAPACHELOG",
+        r#"%{IPORHOST:clientip} %{HTTPDUSER:ident} %{HTTPDUSER:auth} \[%{HTTPDATE:timestamp}\] "(?:%{WORD:verb} %{NOTSPACE:request}(?: HTTP/%{NUMBER:httpversion})?|%{DATA:rawrequest})" %{NUMBER:response:int} (?:%{NUMBER:bytes:int}|-)"#,
+    ),
+    (
+        "COMBINEDAPACHELOG",
+        r"%{COMMONAPACHELOG} %{QS:referrer} %{QS:agent}",
+    ),
+];
+
+#[derive(Debug, Clone, Copy)]
+enum FieldType {
+    Int,
+    Float,
+}
+
+#[derive(Debug, Clone)]
+struct GrokField {
+    // name of the capture group in the compiled regex
+    group: String,
+    name: String,
+    field_type: Option<FieldType>,
+}
+
+// Compiled grok expression
+#[derive(Debug, Clone)]
+pub struct Grok {
+    source: String,
+    regex: Regex,
+    fields: Vec<GrokField>,
+}
+
+impl PartialEq for Grok {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Grok {}
+
+impl Serialize for Grok {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Grok {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Grok::new(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Grok {
+    pub fn new(source: &str) -> Result<Self, String> {
+        let mut fields = Vec::new();
+        let expanded = expand(source, 0, &mut fields)?;
+        // events can span multiple lines, let . match new lines as well
+        let regex = Regex::new(&format!("(?s){}", expanded)).map_err(|err| err.to_string())?;
+
+        // named groups written as plain regex are fields as well
+        let raw_groups: Vec<GrokField> = regex
+            .capture_names()
+            .flatten()
+            .filter(|name| !fields.iter().any(|field| field.group == *name))
+            .map(|name| GrokField {
+                group: name.to_owned(),
+                name: name.to_owned(),
+                field_type: None,
+            })
+            .collect();
+        fields.extend(raw_groups);
+        if fields.is_empty() {
+            return Err(format!("grok pattern {} has no named field", source));
+        }
+
+        Ok(Self {
+            source: source.to_owned(),
+            regex,
+            fields,
+        })
+    }
+
+    // fields of the event if it matches the pattern
+    pub fn parse(&self, text: &str) -> Option<Map<String, Value>> {
+        let captures = self.regex.captures(text)?;
+        let mut res = Map::new();
+        for field in &self.fields {
+            let Some(value) = captures.name(&field.group) else {
+                continue;
+            };
+            let value = value.as_str();
+            let value = match field.field_type {
+                Some(FieldType::Int) => value.parse::<i64>().ok().map(Value::from),
+                Some(FieldType::Float) => value
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(Number::from_f64)
+                    .map(Value::Number),
+                None => None,
+            }
+            .unwrap_or_else(|| Value::String(value.to_owned()));
+            res.insert(field.name.to_owned(), value);
+        }
+        Some(res)
+    }
+}
+
+fn expand(pattern: &str, depth: usize, fields: &mut Vec<GrokField>) -> Result<String, String> {
+    if depth > MAX_DEPTH {
+        return Err("grok patterns are nested too deep".to_owned());
+    }
+
+    let mut res = String::with_capacity(pattern.len());
+    let mut last = 0;
+    for captures in REFERENCE.captures_iter(pattern) {
+        let reference = captures.get(0).expect("group 0 is always present");
+        res.push_str(&pattern[last..reference.start()]);
+        last = reference.end();
+
+        let syntax = &captures["syntax"];
+        let Some((_, definition)) = PATTERNS.iter().find(|(name, _)| *name == syntax) else {
+            return Err(format!("unknown grok pattern {}", syntax));
+        };
+        let expanded = expand(definition, depth + 1, fields)?;
+
+        match captures.name("field") {
+            Some(name) => {
+                let group = format!("__grok{}", fields.len());
+                res.push_str(&format!("(?P<{}>{})", group, expanded));
+                fields.push(GrokField {
+                    group,
+                    name: name.as_str().to_owned(),
+                    field_type: captures.name("type").map(|t| match t.as_str() {
+                        "int" => FieldType::Int,
+                        _ => FieldType::Float,
+                    }),
+                });
+            }
+            None => res.push_str(&format!("(?:{})", expanded)),
+        }
+    }
+    res.push_str(&pattern[last..]);
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Grok;
+
+    #[test]
+    fn combined_apache_log() {
+        let grok = Grok::new("%{COMBINEDAPACHELOG}").unwrap();
+        let line = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08""#;
+
+        let fields = grok.parse(line).unwrap();
+        assert_eq!(fields["clientip"], json!("127.0.0.1"));
+        assert_eq!(fields["auth"], json!("frank"));
+        assert_eq!(fields["timestamp"], json!("10/Oct/2000:13:55:36 -0700"));
+        assert_eq!(fields["verb"], json!("GET"));
+        assert_eq!(fields["request"], json!("/apache_pb.gif"));
+        assert_eq!(fields["httpversion"], json!("1.0"));
+        assert_eq!(fields["response"], json!(200));
+        assert_eq!(fields["bytes"], json!(2326));
+        assert_eq!(
+            fields["referrer"],
+            json!("\"http://www.example.com/start.html\"")
+        );
+        assert!(!fields.contains_key("rawrequest"));
+
+        assert!(grok.parse("not an access log").is_none());
+    }
+
+    #[test]
+    fn typed_fields_and_raw_regex() {
+        let grok = Grok::new(r"took %{NUMBER:duration:float}ms user=(?P<user>\w+)").unwrap();
+        let fields = grok.parse("request took 12.5ms user=alice").unwrap();
+        assert_eq!(fields["duration"], json!(12.5));
+        assert_eq!(fields["user"], json!("alice"));
+
+        assert!(Grok::new("%{WORD}").is_err());
+        assert!(Grok::new("%{UNKNOWN:a}").is_err());
+    }
+}
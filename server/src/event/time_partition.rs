@@ -0,0 +1,274 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::TimestampMillisecondType;
+use arrow_array::{Array, ArrayRef, RecordBatch, TimestampMillisecondArray, UInt32Array};
+use arrow_schema::{DataType, TimeUnit};
+use arrow_select::take::take;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Timelike};
+use datafusion::arrow::compute::cast;
+
+use crate::storage::OBJECT_STORE_DATA_GRANULARITY;
+use crate::utils;
+
+use super::error::EventError;
+
+// Time partition of a stream. When set, the time of an event is taken from
+// this field instead of the time the server received it. p_timestamp, staging
+// files and object store prefixes are all derived from the event's own time.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TimePartition {
+    // name of the column after the event is flattened, e.g. `timestamp` or `@timestamp`
+    pub field: String,
+    // strftime format of the field, RFC 3339 is expected when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+impl TimePartition {
+    pub fn new(field: String, format: Option<String>) -> Result<Self, String> {
+        if field.is_empty() {
+            return Err("time partition field can not be empty".to_owned());
+        }
+        if let Some(format) = &format {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(format!("invalid time partition format {}", format));
+            }
+        }
+        Ok(Self { field, format })
+    }
+
+    // parse the value of the time partition field, values without
+    // a timezone are assumed to be in UTC
+    pub fn parse(&self, value: &str) -> Option<NaiveDateTime> {
+        match &self.format {
+            Some(format) => DateTime::parse_from_str(value, format)
+                .map(|time| time.naive_utc())
+                .or_else(|_| NaiveDateTime::parse_from_str(value, format))
+                .or_else(|_| {
+                    NaiveDate::parse_from_str(value, format)
+                        .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+                })
+                .ok(),
+            None => DateTime::parse_from_rfc3339(value)
+                .map(|time| time.naive_utc())
+                .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f"))
+                .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f"))
+                .ok(),
+        }
+    }
+
+    // Set p_timestamp (column 0) of the batch from the time partition field and
+    // split the batch into one batch per object store time prefix.
+    // Returns the batch with p_timestamp set along with the split batches.
+    pub fn partition_batch(
+        &self,
+        rb: RecordBatch,
+    ) -> Result<(RecordBatch, Vec<(NaiveDateTime, RecordBatch)>), EventError> {
+        let column = rb.column_by_name(&self.field).ok_or_else(|| {
+            EventError::TimePartition(
+                self.field.clone(),
+                "field is not present in the event".to_owned(),
+            )
+        })?;
+        let timestamps = self.timestamps(column)?;
+        self.split_batch(rb, timestamps)
+    }
+
+    // milliseconds since epoch of every value of the time partition field
+    pub fn timestamps(&self, column: &ArrayRef) -> Result<Vec<i64>, EventError> {
+        let invalid = |reason: String| EventError::TimePartition(self.field.clone(), reason);
+        let timestamps: Vec<i64> = match column.data_type() {
+            DataType::Utf8 => column
+                .as_string::<i32>()
+                .iter()
+                .map(|value| {
+                    let value =
+                        value.ok_or_else(|| invalid("field is null in the event".to_owned()))?;
+                    self.parse(value)
+                        .map(|time| time.timestamp_millis())
+                        .ok_or_else(|| invalid(format!("could not parse {}", value)))
+                })
+                .collect::<Result<_, _>>()?,
+            DataType::Timestamp(_, _) => {
+                let column = cast(column, &DataType::Timestamp(TimeUnit::Millisecond, None))?;
+                if column.null_count() > 0 {
+                    return Err(invalid("field is null in the event".to_owned()));
+                }
+                column
+                    .as_primitive::<TimestampMillisecondType>()
+                    .values()
+                    .to_vec()
+            }
+            data_type => return Err(invalid(format!("unsupported type {}", data_type))),
+        };
+        Ok(timestamps)
+    }
+
+    // sets p_timestamp (column 0) of the batch to the given times and splits
+    // the batch into one batch per object store time prefix
+    pub fn split_batch(
+        &self,
+        rb: RecordBatch,
+        timestamps: Vec<i64>,
+    ) -> Result<(RecordBatch, Vec<(NaiveDateTime, RecordBatch)>), EventError> {
+        let invalid = |reason: String| EventError::TimePartition(self.field.clone(), reason);
+
+        // group rows by the minute prefix they belong to
+        let mut groups: BTreeMap<NaiveDateTime, Vec<u32>> = BTreeMap::new();
+        for (idx, millis) in timestamps.iter().enumerate() {
+            let time = NaiveDateTime::from_timestamp_millis(*millis)
+                .ok_or_else(|| invalid(format!("time {} is out of range", millis)))?;
+            groups
+                .entry(prefix_time(time))
+                .or_default()
+                .push(idx as u32);
+        }
+
+        let rb = utils::arrow::replace_columns(
+            rb.schema(),
+            &rb,
+            &[0],
+            &[Arc::new(TimestampMillisecondArray::from(timestamps))],
+        );
+
+        if groups.len() == 1 {
+            let (time, _) = groups.pop_first().expect("one group");
+            return Ok((rb.clone(), vec![(time, rb)]));
+        }
+
+        let mut batches = Vec::with_capacity(groups.len());
+        for (time, indices) in groups {
+            let indices = UInt32Array::from(indices);
+            let columns = rb
+                .columns()
+                .iter()
+                .map(|column| take(column, &indices, None))
+                .collect::<Result<Vec<_>, _>>()?;
+            batches.push((time, RecordBatch::try_new(rb.schema(), columns)?));
+        }
+
+        Ok((rb, batches))
+    }
+}
+
+// start of the object store time prefix this time falls into
+fn prefix_time(time: NaiveDateTime) -> NaiveDateTime {
+    let minute = time.minute() / OBJECT_STORE_DATA_GRANULARITY * OBJECT_STORE_DATA_GRANULARITY;
+    time.date()
+        .and_hms_opt(time.hour(), minute, 0)
+        .expect("valid time")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampMillisecondArray};
+    use chrono::{NaiveDate, Timelike};
+
+    use super::TimePartition;
+
+    fn batch(times: Vec<Option<&str>>) -> RecordBatch {
+        let len = times.len();
+        RecordBatch::try_from_iter([
+            (
+                "p_timestamp",
+                Arc::new(TimestampMillisecondArray::from(vec![None; len])) as ArrayRef,
+            ),
+            ("timestamp", Arc::new(StringArray::from(times)) as ArrayRef),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_values() {
+        let partition = TimePartition::new("timestamp".to_owned(), None).unwrap();
+        let time = NaiveDate::from_ymd_opt(2024, 1, 12)
+            .unwrap()
+            .and_hms_milli_opt(2, 33, 0, 451)
+            .unwrap();
+        assert_eq!(partition.parse("2024-01-12T02:33:00.451Z"), Some(time));
+        assert_eq!(partition.parse("2024-01-12T08:03:00.451+05:30"), Some(time));
+        assert_eq!(partition.parse("2024-01-12 02:33:00.451"), Some(time));
+        assert_eq!(partition.parse("12/01/2024"), None);
+
+        let partition =
+            TimePartition::new("ts".to_owned(), Some("%d/%b/%Y:%H:%M:%S %z".to_owned())).unwrap();
+        assert_eq!(
+            partition.parse("12/Jan/2024:02:33:00 +0000"),
+            Some(time.with_nanosecond(0).unwrap())
+        );
+
+        assert!(TimePartition::new("ts".to_owned(), Some("%Q".to_owned())).is_err());
+        assert!(TimePartition::new(String::new(), None).is_err());
+    }
+
+    #[test]
+    fn batch_is_split_by_minute() {
+        let partition = TimePartition::new("timestamp".to_owned(), None).unwrap();
+        let rb = batch(vec![
+            Some("2024-01-12T02:33:10Z"),
+            Some("2023-12-31T23:59:59Z"),
+            Some("2024-01-12T02:33:50Z"),
+        ]);
+
+        let (rb, batches) = partition.partition_batch(rb).unwrap();
+        assert_eq!(rb.num_rows(), 3);
+        assert_eq!(
+            rb.column(0)
+                .as_any()
+                .downcast_ref::<TimestampMillisecondArray>()
+                .unwrap()
+                .value(1),
+            1704067199000
+        );
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(
+            batches[0].0,
+            NaiveDate::from_ymd_opt(2023, 12, 31)
+                .unwrap()
+                .and_hms_opt(23, 59, 0)
+                .unwrap()
+        );
+        assert_eq!(batches[0].1.num_rows(), 1);
+        assert_eq!(batches[1].1.num_rows(), 2);
+    }
+
+    #[test]
+    fn missing_or_invalid_time_is_err() {
+        let partition = TimePartition::new("timestamp".to_owned(), None).unwrap();
+        assert!(partition
+            .partition_batch(batch(vec![Some("2024-01-12T02:33:10Z"), None]))
+            .is_err());
+        assert!(partition
+            .partition_batch(batch(vec![Some("yesterday")]))
+            .is_err());
+
+        let partition = TimePartition::new("time".to_owned(), None).unwrap();
+        assert!(partition
+            .partition_batch(batch(vec![Some("2024-01-12T02:33:10Z")]))
+            .is_err());
+    }
+}
@@ -22,15 +22,18 @@ mod mem_writer;
 
 use std::{
     collections::HashMap,
+    path::PathBuf,
     sync::{Arc, Mutex, RwLock},
 };
 
 use crate::utils;
 
+use super::durability;
+
 use self::{errors::StreamWriterError, file_writer::FileWriter, mem_writer::MemWriter};
 use arrow_array::{RecordBatch, TimestampMillisecondArray};
 use arrow_schema::Schema;
-use chrono::Utc;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use derive_more::{Deref, DerefMut};
 use once_cell::sync::Lazy;
 
@@ -43,22 +46,33 @@ pub struct Writer {
 }
 
 impl Writer {
+    // time is the time the batch is partitioned by, in which case p_timestamp
+    // is already set. Otherwise the batch is stamped with the time of arrival.
+    // Returns the staging file the batch is written to
     fn push(
         &mut self,
         stream_name: &str,
         schema_key: &str,
         rb: RecordBatch,
-    ) -> Result<(), StreamWriterError> {
-        let rb = utils::arrow::replace_columns(
-            rb.schema(),
-            &rb,
-            &[0],
-            &[Arc::new(get_timestamp_array(rb.num_rows()))],
-        );
-
-        self.disk.push(stream_name, schema_key, &rb)?;
+        time: Option<NaiveDateTime>,
+    ) -> Result<PathBuf, StreamWriterError> {
+        let (rb, time) = match time {
+            Some(time) => (rb, time),
+            None => {
+                let now = Utc::now();
+                let rb = utils::arrow::replace_columns(
+                    rb.schema(),
+                    &rb,
+                    &[0],
+                    &[Arc::new(get_timestamp_array(now, rb.num_rows()))],
+                );
+                (rb, now.naive_utc())
+            }
+        };
+
+        let path = self.disk.push(stream_name, schema_key, &rb, time)?;
         self.mem.push(schema_key, rb);
-        Ok(())
+        Ok(path)
     }
 }
 
@@ -67,12 +81,14 @@ pub struct WriterTable(RwLock<HashMap<String, Mutex<Writer>>>);
 
 impl WriterTable {
     // append to a existing stream
+    // returns the staging file the record is written to
     pub fn append_to_local(
         &self,
         stream_name: &str,
         schema_key: &str,
         record: RecordBatch,
-    ) -> Result<(), StreamWriterError> {
+        time: Option<NaiveDateTime>,
+    ) -> Result<PathBuf, StreamWriterError> {
         let hashmap_guard = self.read().unwrap();
 
         match hashmap_guard.get(stream_name) {
@@ -80,7 +96,7 @@ impl WriterTable {
                 stream_writer
                     .lock()
                     .unwrap()
-                    .push(stream_name, schema_key, record)?;
+                    .push(stream_name, schema_key, record, time)
             }
             None => {
                 drop(hashmap_guard);
@@ -91,15 +107,15 @@ impl WriterTable {
                     writer
                         .lock()
                         .unwrap()
-                        .push(stream_name, schema_key, record)?;
+                        .push(stream_name, schema_key, record, time)
                 } else {
                     let mut writer = Writer::default();
-                    writer.push(stream_name, schema_key, record)?;
+                    let path = writer.push(stream_name, schema_key, record, time)?;
                     map.insert(stream_name.to_owned(), Mutex::new(writer));
+                    Ok(path)
                 }
             }
-        };
-        Ok(())
+        }
     }
 
     pub fn delete_stream(&self, stream_name: &str) {
@@ -110,12 +126,29 @@ impl WriterTable {
         let mut table = self.write().unwrap();
         let map = std::mem::take(&mut *table);
         drop(table);
-        for writer in map.into_values() {
+        for (stream_name, writer) in map {
             let writer = writer.into_inner().unwrap();
-            writer.disk.close_all();
+            writer.disk.close_all(durability::is_durable(&stream_name));
         }
     }
 
+    // paths of the staging files that are still being written to
+    pub fn open_files(&self, stream_name: &str) -> Vec<PathBuf> {
+        self.read()
+            .unwrap()
+            .get(stream_name)
+            .map(|writer| {
+                writer
+                    .lock()
+                    .unwrap()
+                    .disk
+                    .values()
+                    .map(|writer| writer.file_path.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     pub fn recordbatches_cloned(
         &self,
         stream_name: &str,
@@ -135,8 +168,8 @@ impl WriterTable {
     }
 }
 
-fn get_timestamp_array(size: usize) -> TimestampMillisecondArray {
-    TimestampMillisecondArray::from_value(Utc::now().timestamp_millis(), size)
+fn get_timestamp_array(time: DateTime<Utc>, size: usize) -> TimestampMillisecondArray {
+    TimestampMillisecondArray::from_value(time.timestamp_millis(), size)
 }
 
 pub mod errors {
@@ -23,9 +23,11 @@ use std::path::PathBuf;
 
 use arrow_array::RecordBatch;
 use arrow_ipc::writer::StreamWriter;
+use chrono::NaiveDateTime;
 use derive_more::{Deref, DerefMut};
 
-use crate::storage::staging::StorageDir;
+use crate::event::durability;
+use crate::storage::staging::{self, StorageDir};
 
 use super::errors::StreamWriterError;
 
@@ -38,55 +40,91 @@ pub struct ArrowWriter {
 pub struct FileWriter(HashMap<String, ArrowWriter>);
 
 impl FileWriter {
-    // append to a existing stream
+    // append to a existing stream, returns the file the record is written to
     pub fn push(
         &mut self,
         stream_name: &str,
         schema_key: &str,
         record: &RecordBatch,
-    ) -> Result<(), StreamWriterError> {
-        match self.get_mut(schema_key) {
+        time: NaiveDateTime,
+    ) -> Result<PathBuf, StreamWriterError> {
+        // there is one file per schema and time prefix
+        let filename = StorageDir::filename_by_time(schema_key, time);
+        let path = match self.get_mut(&filename) {
             Some(writer) => {
                 writer
                     .writer
                     .write(record)
                     .map_err(StreamWriterError::Writer)?;
+                writer.file_path.clone()
             }
             // entry is not present thus we create it
             None => {
                 // this requires mutable borrow of the map so we drop this read lock and wait for write lock
-                let (path, writer) = init_new_stream_writer_file(stream_name, schema_key, record)?;
+                let (path, writer) = init_new_stream_writer_file(stream_name, &filename, record)?;
                 self.insert(
-                    schema_key.to_owned(),
+                    filename,
                     ArrowWriter {
-                        file_path: path,
+                        file_path: path.clone(),
                         writer,
                     },
                 );
+                path
             }
         };
 
-        Ok(())
+        Ok(path)
     }
 
-    pub fn close_all(self) {
+    // sync is set for streams with a durability other than none
+    pub fn close_all(self, sync: bool) {
         for mut writer in self.0.into_values() {
             _ = writer.writer.finish();
+            if sync {
+                if let Err(err) = writer.writer.get_ref().sync_data() {
+                    log::error!(
+                        "failed to sync staging file {}. {}",
+                        writer.file_path.display(),
+                        err
+                    );
+                }
+            }
         }
     }
 }
 
 fn init_new_stream_writer_file(
     stream_name: &str,
-    schema_key: &str,
+    filename: &str,
     record: &RecordBatch,
 ) -> Result<(PathBuf, StreamWriter<std::fs::File>), StreamWriterError> {
     let dir = StorageDir::new(stream_name);
-    let path = dir.path_by_current_time(schema_key);
+    // a writer for the same schema and time may be opened again once the previous one is
+    // closed, when a late event arrives for a time partitioned stream. Every writer gets its
+    // own file so that nothing is written after the end of stream marker of a finished file.
+    // The prefix is dropped again when the files are grouped by time
+    let path = dir.data_path.join(format!(
+        "{}-{}",
+        ulid::Ulid::new().to_string().to_lowercase(),
+        filename
+    ));
+
+    let created = !dir.data_path.exists();
+    std::fs::create_dir_all(&dir.data_path)?;
 
-    std::fs::create_dir_all(dir.data_path)?;
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
 
-    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    // a new file is only kept across a crash of the machine once its entry in the
+    // directory is on disk, which syncing the file itself does not do
+    if durability::is_durable(stream_name) {
+        staging::sync_dir(&dir.data_path)?;
+        if let Some(parent) = dir.data_path.parent().filter(|_| created) {
+            staging::sync_dir(parent)?;
+        }
+    }
 
     let mut stream_writer = StreamWriter::try_new(file, &record.schema())
         .expect("File and RecordBatch both are checked");
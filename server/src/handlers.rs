@@ -16,16 +16,36 @@
  *
  */
 
+pub mod forward;
 pub mod http;
+#[cfg(feature = "kafka")]
+pub mod kafka;
 pub mod livetail;
+pub mod syslog;
+pub mod tail;
 
+// prefix of the headers parseable reads from ingest requests
+const PREFIX_PARSEABLE: &str = "x-p-";
 const PREFIX_TAGS: &str = "x-p-tag-";
 const PREFIX_META: &str = "x-p-meta-";
 const STREAM_NAME_HEADER_KEY: &str = "x-p-stream";
 const LOG_SOURCE_KEY: &str = "x-p-log-source";
+const TIME_PARTITION_KEY: &str = "x-p-time-partition";
+const TIME_PARTITION_FORMAT_KEY: &str = "x-p-time-partition-format";
+// when set to true, records that do not match the stream schema are
+// rejected individually instead of failing the whole request
+const PARTIAL_INGEST_KEY: &str = "x-p-partial-ingest";
+// format of the body when it is not picked from the content type, csv or tsv
+const EVENT_FORMAT_KEY: &str = "x-p-format";
+const CSV_DELIMITER_KEY: &str = "x-p-csv-delimiter";
+// true or false, the first row is checked for a header if not set
+const CSV_HEADER_KEY: &str = "x-p-csv-header";
+// requests with a key that was already ingested into the stream are acknowledged without ingesting them again
+const IDEMPOTENCY_KEY: &str = "x-p-idempotency-key";
 
 const AUTHORIZATION_KEY: &str = "authorization";
-const SEPARATOR: char = '^';
+// separator of the tags and metadata of streams that store them as strings
+pub const SEPARATOR: char = '^';
 
 const OIDC_SCOPE: &str = "openid profile email";
 const COOKIE_AGE_DAYS: usize = 7;
@@ -41,3 +61,6 @@ const LOG_SOURCE_OTEL: &str = "otel";
 
 // AWS Kinesis constants
 const KINESIS_COMMON_ATTRIBUTES_KEY: &str = "x-amz-firehose-common-attributes";
+const KINESIS_REQUEST_ID_KEY: &str = "x-amz-firehose-request-id";
+// access key configured for the HTTP endpoint destination, holds the basic auth credentials
+const KINESIS_ACCESS_KEY: &str = "x-amz-firehose-access-key";
@@ -0,0 +1,573 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use bytes::{Buf, BytesMut};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use flate2::read::MultiGzDecoder;
+use rmp::Marker;
+use rmpv::Value as MsgPack;
+use serde_json::{Map, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::option::CONFIG;
+
+use super::http::ingest::push_records;
+
+// upper bound for a single forward message, larger messages close the connection
+const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
+// upper bound for the decompressed entries of a CompressedPackedForward message
+const MAX_UNPACKED_SIZE: usize = 4 * MAX_MESSAGE_SIZE;
+// msgpack extension type of EventTime, seconds and nanoseconds as big endian u32
+const EVENT_TIME_EXT: i8 = 0;
+// field that holds the time of the event if the record has none
+const TIME_KEY: &str = "timestamp";
+
+// Fluentd Forward protocol listener (msgpack over TCP), as sent by the forward output
+// of Fluentd and Fluent Bit. Supports the Message, Forward, PackedForward and
+// CompressedPackedForward modes, chunks are acked once their events are ingested.
+// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1
+// Events go to the stream named after their tag, kube.var.log is ingested into kubevarlog.
+pub async fn server() {
+    let Some(address) = CONFIG.parseable.forward_address.clone() else {
+        return;
+    };
+
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("could not start forward listener on {}. {:?}", address, err);
+            return;
+        }
+    };
+    log::info!("forward listener started on {}", address);
+
+    loop {
+        match listener.accept().await {
+            Ok((socket, peer)) => {
+                actix_web::rt::spawn(async move {
+                    if let Err(err) = handle_connection(socket).await {
+                        log::warn!("forward connection from {} closed. {}", peer, err);
+                    }
+                });
+            }
+            Err(err) => log::warn!("failed to accept forward connection. {:?}", err),
+        }
+    }
+}
+
+async fn handle_connection(mut socket: TcpStream) -> anyhow::Result<()> {
+    let mut buf = BytesMut::with_capacity(64 * 1024);
+    let mut scanner = MessageScanner::default();
+    loop {
+        let read = socket.read_buf(&mut buf).await?;
+        while let Some((message, size)) = next_message(&mut buf, &mut scanner)? {
+            let entries = decode_message(message).map_err(anyhow::Error::msg)?;
+            if !ingest_entries(&entries.tag, entries.records, size).await {
+                // without an ack the client sends the chunk again
+                continue;
+            }
+            if let Some(chunk) = entries.chunk {
+                let ack = MsgPack::Map(vec![(MsgPack::from("ack"), chunk)]);
+                let mut response = Vec::new();
+                rmpv::encode::write_value(&mut response, &ack)?;
+                socket.write_all(&response).await?;
+            }
+        }
+        if read == 0 {
+            return Ok(());
+        }
+    }
+}
+
+// returns whether the events are done with, either ingested or rejected for good
+async fn ingest_entries(tag: &str, records: Vec<BTreeMap<String, Value>>, size: usize) -> bool {
+    if records.is_empty() {
+        return true;
+    }
+    let stream_name = stream_name(tag);
+    let source = format!("forward tag {}", tag);
+    match push_records(&stream_name, records, size, &source).await {
+        Ok(_) => true,
+        // the events will not be accepted on a retry either
+        Err(err) if err.is_rejection() => {
+            log::warn!(
+                "dropping forward events with tag {} not accepted by {}. {}",
+                tag,
+                stream_name,
+                err
+            );
+            true
+        }
+        Err(err) => {
+            log::error!(
+                "failed to ingest forward events with tag {} into {}. {}",
+                tag,
+                stream_name,
+                err
+            );
+            false
+        }
+    }
+}
+
+// Stream names are lowercase alphanumeric, every other character of the tag is dropped
+fn stream_name(tag: &str) -> String {
+    tag.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+// Decode the next complete message off the buffer, along with its size in bytes
+fn next_message(
+    buf: &mut BytesMut,
+    scanner: &mut MessageScanner,
+) -> anyhow::Result<Option<(MsgPack, usize)>> {
+    let Some(size) = scanner.scan(buf)? else {
+        return Ok(None);
+    };
+    let message = rmpv::decode::read_value(&mut &buf[..size])?;
+    buf.advance(size);
+    Ok(Some((message, size)))
+}
+
+// Finds where the message at the start of the buffer ends without decoding it.
+// A scan picks up where the previous one stopped, so a message that arrives over
+// many reads is not gone over again from its start on every read
+#[derive(Debug, Default)]
+struct MessageScanner {
+    // bytes of the message scanned so far
+    offset: usize,
+    // values left in each of the arrays and maps that the scan is in
+    pending: Vec<u64>,
+}
+
+impl MessageScanner {
+    // size of the message at the start of the buffer, once all of it is in the buffer
+    fn scan(&mut self, buf: &[u8]) -> anyhow::Result<Option<usize>> {
+        while self.offset < buf.len() {
+            let Some((size, values)) = value_layout(&buf[self.offset..])? else {
+                return Ok(None);
+            };
+            let end = self.offset as u64 + size;
+            if end > MAX_MESSAGE_SIZE as u64 {
+                anyhow::bail!("message exceeds the size limit");
+            }
+            if end > buf.len() as u64 {
+                return Ok(None);
+            }
+            self.offset = end as usize;
+            if values > 0 {
+                self.pending.push(values);
+                continue;
+            }
+
+            // the value is complete, and so is every array or map it is the last value of
+            loop {
+                let Some(left) = self.pending.last_mut() else {
+                    let size = self.offset;
+                    *self = Self::default();
+                    return Ok(Some(size));
+                };
+                *left -= 1;
+                if *left > 0 {
+                    break;
+                }
+                self.pending.pop();
+            }
+        }
+        Ok(None)
+    }
+}
+
+// Size of the value at the start of the buffer without its array or map values,
+// and the number of values it holds. None if its length is not in the buffer yet
+fn value_layout(buf: &[u8]) -> anyhow::Result<Option<(u64, u64)>> {
+    let marker = Marker::from_u8(buf[0]);
+    if marker == Marker::Reserved {
+        anyhow::bail!("invalid msgpack marker {:#x}", buf[0]);
+    }
+    // big endian length in the n bytes after the marker
+    let len = |n: usize| {
+        buf.get(1..1 + n)
+            .map(|bytes| bytes.iter().fold(0, |len, &byte| len << 8 | byte as u64))
+    };
+    let layout = || {
+        Some(match marker {
+            Marker::FixStr(n) => (1 + n as u64, 0),
+            Marker::Str8 | Marker::Bin8 => (2 + len(1)?, 0),
+            Marker::Str16 | Marker::Bin16 => (3 + len(2)?, 0),
+            Marker::Str32 | Marker::Bin32 => (5 + len(4)?, 0),
+            Marker::FixArray(n) => (1, n as u64),
+            Marker::Array16 => (3, len(2)?),
+            Marker::Array32 => (5, len(4)?),
+            Marker::FixMap(n) => (1, 2 * n as u64),
+            Marker::Map16 => (3, 2 * len(2)?),
+            Marker::Map32 => (5, 2 * len(4)?),
+            Marker::U8 | Marker::I8 => (2, 0),
+            Marker::U16 | Marker::I16 => (3, 0),
+            Marker::U32 | Marker::I32 | Marker::F32 => (5, 0),
+            Marker::U64 | Marker::I64 | Marker::F64 => (9, 0),
+            Marker::FixExt1 => (3, 0),
+            Marker::FixExt2 => (4, 0),
+            Marker::FixExt4 => (6, 0),
+            Marker::FixExt8 => (10, 0),
+            Marker::FixExt16 => (18, 0),
+            Marker::Ext8 => (3 + len(1)?, 0),
+            Marker::Ext16 => (4 + len(2)?, 0),
+            Marker::Ext32 => (6 + len(4)?, 0),
+            _ => (1, 0),
+        })
+    };
+    Ok(layout())
+}
+
+#[derive(Debug)]
+struct Entries {
+    tag: String,
+    records: Vec<BTreeMap<String, Value>>,
+    // chunk id to ack once the records are ingested
+    chunk: Option<MsgPack>,
+}
+
+// Message:          [tag, time, record, option?]
+// Forward:          [tag, [[time, record], ...], option?]
+// PackedForward:    [tag, bin of concatenated [time, record] entries, option?]
+// option is a map with the chunk id to ack and whether the packed entries are gzip compressed
+fn decode_message(message: MsgPack) -> Result<Entries, String> {
+    let MsgPack::Array(mut fields) = message else {
+        return Err("forward message is not an array".to_owned());
+    };
+    if fields.len() < 2 {
+        return Err("forward message has no events".to_owned());
+    }
+    let tag = match fields.remove(0) {
+        MsgPack::String(tag) => tag.into_str().ok_or("tag is not valid UTF-8")?,
+        _ => return Err("tag is not a string".to_owned()),
+    };
+
+    let (entries, option) = match fields.remove(0) {
+        MsgPack::Array(entries) => (entries, fields.into_iter().next()),
+        MsgPack::Binary(packed) => (unpack(packed, fields.first())?, fields.into_iter().next()),
+        MsgPack::String(packed) => (
+            unpack(packed.into_bytes(), fields.first())?,
+            fields.into_iter().next(),
+        ),
+        time => {
+            let mut fields = fields.into_iter();
+            let record = fields.next().ok_or("message has no record")?;
+            (vec![MsgPack::Array(vec![time, record])], fields.next())
+        }
+    };
+
+    let records = entries
+        .into_iter()
+        .map(decode_entry)
+        .collect::<Result<_, _>>()?;
+    let chunk = option.and_then(|option| option_value(&option, "chunk").cloned());
+
+    Ok(Entries {
+        tag,
+        records,
+        chunk,
+    })
+}
+
+fn unpack(packed: Vec<u8>, option: Option<&MsgPack>) -> Result<Vec<MsgPack>, String> {
+    let compressed = option.and_then(|option| option_value(option, "compressed"));
+    let packed = match compressed.and_then(MsgPack::as_str) {
+        Some("gzip") => gunzip(&packed, MAX_UNPACKED_SIZE)?,
+        Some(other) => return Err(format!("unsupported compression {}", other)),
+        None => packed,
+    };
+
+    let mut entries = Vec::new();
+    let mut cursor = packed.as_slice();
+    while !cursor.is_empty() {
+        let entry = rmpv::decode::read_value(&mut cursor)
+            .map_err(|err| format!("invalid packed entry, {}", err))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn gunzip(compressed: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let mut decompressed = Vec::new();
+    MultiGzDecoder::new(compressed)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|err| format!("entries are not valid gzip, {}", err))?;
+    if decompressed.len() > limit {
+        return Err(format!(
+            "decompressed entries exceed the size limit of {} bytes",
+            limit
+        ));
+    }
+    Ok(decompressed)
+}
+
+fn option_value<'a>(option: &'a MsgPack, key: &str) -> Option<&'a MsgPack> {
+    let MsgPack::Map(option) = option else {
+        return None;
+    };
+    option
+        .iter()
+        .find(|(k, _)| k.as_str() == Some(key))
+        .map(|(_, value)| value)
+}
+
+// [time, record], the time is added to the record unless it already has a timestamp field
+fn decode_entry(entry: MsgPack) -> Result<BTreeMap<String, Value>, String> {
+    let MsgPack::Array(entry) = entry else {
+        return Err("entry is not an array".to_owned());
+    };
+    let mut entry = entry.into_iter();
+    let (Some(time), Some(MsgPack::Map(record))) = (entry.next(), entry.next()) else {
+        return Err("entry is not a [time, record] pair".to_owned());
+    };
+
+    let mut record: BTreeMap<String, Value> = record
+        .into_iter()
+        .map(|(key, value)| (map_key(key), to_json(value)))
+        .collect();
+    if let Some(time) = event_time(&time) {
+        record.entry(TIME_KEY.to_owned()).or_insert(time);
+    }
+    Ok(record)
+}
+
+// time is either an integer of seconds or an EventTime with nanoseconds
+fn event_time(time: &MsgPack) -> Option<Value> {
+    let (secs, nanos) = match time {
+        MsgPack::Ext(EVENT_TIME_EXT, data) if data.len() == 8 => (
+            u32::from_be_bytes(data[..4].try_into().ok()?) as i64,
+            u32::from_be_bytes(data[4..].try_into().ok()?),
+        ),
+        MsgPack::Integer(secs) => (secs.as_i64()?, 0),
+        MsgPack::F64(secs) => (secs.trunc() as i64, (secs.fract() * 1e9) as u32),
+        _ => return None,
+    };
+    let time = Utc.timestamp_opt(secs, nanos).single()?;
+    Some(Value::String(
+        time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    ))
+}
+
+fn map_key(key: MsgPack) -> String {
+    match key {
+        MsgPack::String(key) => String::from_utf8_lossy(key.as_bytes()).into_owned(),
+        MsgPack::Binary(key) => String::from_utf8_lossy(&key).into_owned(),
+        key => key.to_string(),
+    }
+}
+
+fn to_json(value: MsgPack) -> Value {
+    match value {
+        MsgPack::Nil => Value::Null,
+        MsgPack::Boolean(b) => Value::Bool(b),
+        MsgPack::Integer(i) => match (i.as_i64(), i.as_u64()) {
+            (Some(i), _) => Value::from(i),
+            (_, Some(u)) => Value::from(u),
+            _ => Value::Null,
+        },
+        MsgPack::F32(f) => {
+            serde_json::Number::from_f64(f as f64).map_or(Value::Null, Value::Number)
+        }
+        MsgPack::F64(f) => serde_json::Number::from_f64(f).map_or(Value::Null, Value::Number),
+        // Fluent Bit sends strings as raw bytes at times
+        MsgPack::String(s) => Value::String(String::from_utf8_lossy(s.as_bytes()).into_owned()),
+        MsgPack::Binary(b) => Value::String(String::from_utf8_lossy(&b).into_owned()),
+        MsgPack::Array(values) => Value::Array(values.into_iter().map(to_json).collect()),
+        MsgPack::Map(entries) => Value::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (map_key(key), to_json(value)))
+                .collect::<Map<_, _>>(),
+        ),
+        ext @ MsgPack::Ext(..) => event_time(&ext).unwrap_or(Value::Null),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use bytes::BytesMut;
+    use flate2::{write::GzEncoder, Compression};
+    use rmpv::Value as MsgPack;
+    use serde_json::json;
+
+    use super::{decode_message, gunzip, next_message, stream_name, MessageScanner};
+
+    fn encode(value: &MsgPack) -> Vec<u8> {
+        let mut buf = Vec::new();
+        rmpv::encode::write_value(&mut buf, value).unwrap();
+        buf
+    }
+
+    fn record(message: &str) -> MsgPack {
+        MsgPack::Map(vec![
+            (MsgPack::from("log"), MsgPack::from(message)),
+            (
+                MsgPack::from("kubernetes"),
+                MsgPack::Map(vec![(MsgPack::from("pod"), MsgPack::from("api-0"))]),
+            ),
+        ])
+    }
+
+    fn event_time() -> MsgPack {
+        // 2024-01-12T02:33:00.5Z
+        let mut data = 1705026780u32.to_be_bytes().to_vec();
+        data.extend(500_000_000u32.to_be_bytes());
+        MsgPack::Ext(0, data)
+    }
+
+    #[test]
+    fn message_mode() {
+        let message = MsgPack::Array(vec![
+            MsgPack::from("app.access"),
+            MsgPack::from(1705026780),
+            record("GET /"),
+        ]);
+        let entries = decode_message(message).unwrap();
+        assert_eq!(entries.tag, "app.access");
+        assert!(entries.chunk.is_none());
+        assert_eq!(entries.records.len(), 1);
+        assert_eq!(entries.records[0]["log"], json!("GET /"));
+        assert_eq!(entries.records[0]["kubernetes"], json!({"pod": "api-0"}));
+        assert_eq!(
+            entries.records[0]["timestamp"],
+            json!("2024-01-12T02:33:00Z")
+        );
+    }
+
+    #[test]
+    fn forward_mode_with_chunk() {
+        let message = MsgPack::Array(vec![
+            MsgPack::from("app"),
+            MsgPack::Array(vec![
+                MsgPack::Array(vec![event_time(), record("first")]),
+                MsgPack::Array(vec![event_time(), record("second")]),
+            ]),
+            MsgPack::Map(vec![(
+                MsgPack::from("chunk"),
+                MsgPack::from("p8n9gmxTQVC8"),
+            )]),
+        ]);
+        let entries = decode_message(message).unwrap();
+        assert_eq!(entries.records.len(), 2);
+        assert_eq!(entries.records[1]["log"], json!("second"));
+        assert_eq!(
+            entries.records[1]["timestamp"],
+            json!("2024-01-12T02:33:00.500Z")
+        );
+        assert_eq!(entries.chunk, Some(MsgPack::from("p8n9gmxTQVC8")));
+    }
+
+    #[test]
+    fn packed_forward_modes() {
+        let mut packed = encode(&MsgPack::Array(vec![event_time(), record("first")]));
+        packed.extend(encode(&MsgPack::Array(vec![
+            MsgPack::from(1705026781),
+            record("second"),
+        ])));
+
+        let entries = decode_message(MsgPack::Array(vec![
+            MsgPack::from("app"),
+            MsgPack::Binary(packed.clone()),
+        ]))
+        .unwrap();
+        assert_eq!(entries.records.len(), 2);
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&packed).unwrap();
+        let entries = decode_message(MsgPack::Array(vec![
+            MsgPack::from("app"),
+            MsgPack::Binary(encoder.finish().unwrap()),
+            MsgPack::Map(vec![
+                (MsgPack::from("chunk"), MsgPack::from("abc")),
+                (MsgPack::from("compressed"), MsgPack::from("gzip")),
+            ]),
+        ]))
+        .unwrap();
+        assert_eq!(entries.records.len(), 2);
+        assert_eq!(entries.records[1]["log"], json!("second"));
+        assert_eq!(entries.chunk, Some(MsgPack::from("abc")));
+    }
+
+    #[test]
+    fn messages_split_across_reads() {
+        let message = encode(&MsgPack::Array(vec![
+            MsgPack::from("app"),
+            MsgPack::from(1705026780),
+            record("GET /"),
+        ]));
+        let mut scanner = MessageScanner::default();
+        let mut buf = BytesMut::from(&message[..10]);
+        assert!(next_message(&mut buf, &mut scanner).unwrap().is_none());
+        // the scan picks up after the values that were complete
+        assert!(scanner.offset > 0 && scanner.offset <= 10);
+        for byte in &message[10..] {
+            buf.extend_from_slice(&[*byte]);
+            if buf.len() < message.len() {
+                assert!(next_message(&mut buf, &mut scanner).unwrap().is_none());
+            }
+        }
+        buf.extend_from_slice(&message);
+        let (decoded, size) = next_message(&mut buf, &mut scanner).unwrap().unwrap();
+        assert_eq!(size, message.len());
+        assert_eq!(
+            decode_message(decoded).unwrap().records[0]["log"],
+            json!("GET /")
+        );
+        assert!(next_message(&mut buf, &mut scanner).unwrap().is_some());
+        assert!(buf.is_empty());
+
+        // the size limit is checked against the declared length of values
+        let mut buf = BytesMut::from(&[0xc6, 0xff, 0xff, 0xff, 0xff][..]);
+        assert!(next_message(&mut buf, &mut scanner).is_err());
+    }
+
+    #[test]
+    fn decompressed_entries_are_limited() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 4096]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(gunzip(&compressed, 4096).unwrap().len(), 4096);
+        assert!(gunzip(&compressed, 4095).is_err());
+    }
+
+    #[test]
+    fn invalid_messages_are_err() {
+        assert!(decode_message(MsgPack::from("app")).is_err());
+        assert!(decode_message(MsgPack::Array(vec![MsgPack::from("app")])).is_err());
+        assert!(decode_message(MsgPack::Array(vec![
+            MsgPack::from(1),
+            MsgPack::Array(vec![])
+        ]))
+        .is_err());
+        assert_eq!(
+            stream_name("kube.var.log.Containers"),
+            "kubevarlogcontainers"
+        );
+    }
+}
@@ -38,13 +38,17 @@ use crate::rbac::role::Action;
 use self::middleware::{DisAllowRootUser, RouteExt};
 
 mod about;
+mod elastic;
 mod health_check;
-mod ingest;
+pub mod ingest;
 mod kinesis;
 mod llm;
 mod logstream;
+mod loki;
 mod middleware;
 mod oidc;
+mod otel;
+mod prometheus;
 mod query;
 mod rbac;
 mod role;
@@ -179,6 +183,14 @@ pub fn configure_routes(
                     .authorize_for_stream(Action::GetSchema),
             ),
         )
+        .service(
+            // GET "/logstream/{logstream}/schema/history" ==> Get the versions of the schema of given log stream
+            web::resource("/schema/history").route(
+                web::get()
+                    .to(logstream::get_schema_history)
+                    .authorize_for_stream(Action::GetSchema),
+            ),
+        )
         .service(
             // GET "/logstream/{logstream}/stats" ==> Get stats for given log stream
             web::resource("/stats").route(
@@ -216,6 +228,129 @@ pub fn configure_routes(
                         .to(logstream::get_cache_enabled)
                         .authorize_for_stream(Action::GetCacheEnabled),
                 ),
+        )
+        .service(
+            web::resource("/processors")
+                // PUT "/logstream/{logstream}/processors" ==> Set ingest processors for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_processors)
+                        .authorize_for_stream(Action::PutProcessors),
+                )
+                // GET "/logstream/{logstream}/processors" ==> Get ingest processors for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_processors)
+                        .authorize_for_stream(Action::GetProcessors),
+                ),
+        )
+        .service(
+            web::resource("/parser")
+                // PUT "/logstream/{logstream}/parser" ==> Set plain text parser for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_text_parser)
+                        .authorize_for_stream(Action::PutTextParser),
+                )
+                // GET "/logstream/{logstream}/parser" ==> Get plain text parser for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_text_parser)
+                        .authorize_for_stream(Action::GetTextParser),
+                ),
+        )
+        .service(
+            web::resource("/ratelimit")
+                // PUT "/logstream/{logstream}/ratelimit" ==> Set ingestion rate limits for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_rate_limit)
+                        .authorize_for_stream(Action::PutRateLimit),
+                )
+                // GET "/logstream/{logstream}/ratelimit" ==> Get ingestion rate limits for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_rate_limit)
+                        .authorize_for_stream(Action::GetRateLimit),
+                ),
+        )
+        .service(
+            web::resource("/dedup")
+                // PUT "/logstream/{logstream}/dedup" ==> Set the deduplication field for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_dedup)
+                        .authorize_for_stream(Action::PutDedup),
+                )
+                // GET "/logstream/{logstream}/dedup" ==> Get the deduplication field for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_dedup)
+                        .authorize_for_stream(Action::GetDedup),
+                ),
+        )
+        .service(
+            web::resource("/durability")
+                // PUT "/logstream/{logstream}/durability" ==> Set the durability level for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_durability)
+                        .authorize_for_stream(Action::PutDurability),
+                )
+                // GET "/logstream/{logstream}/durability" ==> Get the durability level for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_durability)
+                        .authorize_for_stream(Action::GetDurability),
+                ),
+        )
+        .service(
+            web::resource("/nested")
+                // PUT "/logstream/{logstream}/nested" ==> Set how nested JSON objects are stored for given logstream
+                .route(
+                    web::put()
+                        .to(logstream::put_nested_objects)
+                        .authorize_for_stream(Action::PutNestedObjects),
+                )
+                // GET "/logstream/{logstream}/nested" ==> Get how nested JSON objects are stored for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_nested_objects)
+                        .authorize_for_stream(Action::GetNestedObjects),
+                ),
+        )
+        .service(
+            web::resource("/deadletter")
+                // PUT "/logstream/{logstream}/deadletter" ==> Set the stream that rejected events of given logstream are kept in
+                .route(
+                    web::put()
+                        .to(logstream::put_dead_letter)
+                        .authorize_for_stream(Action::PutDeadLetter),
+                )
+                // GET "/logstream/{logstream}/deadletter" ==> Get the dead letter stream for given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_dead_letter)
+                        .authorize_for_stream(Action::GetDeadLetter),
+                ),
+        )
+        .service(
+            web::resource("/import")
+                // POST "/logstream/{logstream}/import" ==> Start importing parquet files in the import directory of the server into given logstream
+                .route(
+                    web::post()
+                        .to(logstream::import)
+                        .authorize_for_stream(Action::Import),
+                ),
+        )
+        .service(
+            web::resource("/import/{id}")
+                // GET "/logstream/{logstream}/import/{id}" ==> Get the status of an import job of given logstream
+                .route(
+                    web::get()
+                        .to(logstream::get_import)
+                        .authorize_for_stream(Action::Import),
+                ),
         );
 
     // User API
@@ -299,6 +434,14 @@ pub fn configure_routes(
     cfg.service(
         // Base path "{url}/api/v1"
         web::scope(&base_path())
+            // GET "/" ==> Elasticsearch compatible cluster info, checked by clients before bulk ingestion
+            .service(
+                web::resource(["", "/"]).route(
+                    web::get()
+                        .to(ingest::elastic_info)
+                        .authorize(Action::Ingest),
+                ),
+            )
             // POST "/query" ==> Get results of the SQL query passed in request body
             .service(
                 web::resource("/query")
@@ -314,6 +457,46 @@ pub fn configure_routes(
                     )
                     .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE)),
             )
+            // POST "/_bulk" ==> Elasticsearch compatible bulk ingestion, stream is picked from _index
+            .service(
+                web::resource("/_bulk")
+                    .route(
+                        web::post()
+                            .to(ingest::ingest_bulk)
+                            .authorize_for_stream(Action::Ingest),
+                    )
+                    .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE)),
+            )
+            // POST "/{index}/_bulk" ==> Elasticsearch compatible bulk ingestion, index is the default stream
+            .service(
+                web::resource("/{logstream}/_bulk")
+                    .route(
+                        web::post()
+                            .to(ingest::ingest_bulk)
+                            .authorize_for_stream(Action::Ingest),
+                    )
+                    .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE)),
+            )
+            // POST "/logs" ==> OTLP/HTTP logs receiver, stream is picked from header
+            .service(
+                web::resource("/logs")
+                    .route(
+                        web::post()
+                            .to(ingest::ingest_otel_logs)
+                            .authorize_for_stream(Action::Ingest),
+                    )
+                    .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE)),
+            )
+            // POST "/write" ==> Prometheus remote write receiver, stream is picked from header
+            .service(
+                web::resource("/write")
+                    .route(
+                        web::post()
+                            .to(ingest::ingest_prometheus_write)
+                            .authorize_for_stream(Action::Ingest),
+                    )
+                    .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE)),
+            )
             // GET "/liveness" ==> Liveness check as per https://kubernetes.io/docs/tasks/configure-pod-container/configure-liveness-readiness-startup-probes/#define-a-liveness-command
             .service(web::resource("/liveness").route(web::get().to(health_check::liveness)))
             // GET "/readiness" ==> Readiness check as per https://kubernetes.io/docs/tasks/configure-pod-container/configure-liveness-readiness-startup-probes/#define-readiness-probes
@@ -340,6 +523,16 @@ pub fn configure_routes(
             .service(oauth_api)
             .service(role_api),
     )
+    // POST "/loki/api/v1/push" ==> Grafana Loki compatible push API, stream is picked from header
+    .service(
+        web::resource("/loki/api/v1/push")
+            .route(
+                web::post()
+                    .to(ingest::ingest_loki_push)
+                    .authorize_for_stream(Action::Ingest),
+            )
+            .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE)),
+    )
     // GET "/" ==> Serve the static frontend directory
     .service(ResourceFiles::new("/", generated).resolve_not_found_to_root());
 }
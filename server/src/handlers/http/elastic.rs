@@ -0,0 +1,257 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use anyhow::anyhow;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+// Elasticsearch bulk API compatibility.
+// A bulk request body is NDJSON made of action/document line pairs
+// { "index": { "_index": "app", "_id": "1" } }
+// { "message": "hello" }
+// { "create": { "_index": "web" } }
+// { "message": "world" }
+// Only the `index` and `create` actions are supported, `update` and `delete`
+// are rejected per item since events in a stream are immutable.
+
+const ACTION_INDEX: &str = "index";
+const ACTION_CREATE: &str = "create";
+const ACTION_UPDATE: &str = "update";
+const ACTION_DELETE: &str = "delete";
+
+// version reported to clients that check it before sending bulk requests, such as
+// Filebeat and Vector. Responses follow the 8.x bulk API, which has no `_type`
+pub const COMPATIBLE_VERSION: &str = "8.11.0";
+pub const PRODUCT_HEADER_KEY: &str = "x-elastic-product";
+pub const PRODUCT: &str = "Elasticsearch";
+
+pub const ERROR_TYPE_MAPPER_PARSING: &str = "mapper_parsing_exception";
+pub const ERROR_TYPE_ILLEGAL_ARGUMENT: &str = "illegal_argument_exception";
+pub const ERROR_TYPE_INTERNAL: &str = "exception";
+pub const ERROR_TYPE_REJECTED_EXECUTION: &str = "es_rejected_execution_exception";
+pub const ERROR_TYPE_SECURITY: &str = "security_exception";
+
+#[derive(Debug)]
+pub struct BulkOperation {
+    pub action: String,
+    pub index: Option<String>,
+    pub id: Option<String>,
+    // document to ingest or the reason why this operation is rejected
+    pub document: Result<Value, String>,
+    // document line of an index or create action that is not a valid document, as sent
+    pub invalid_source: Option<String>,
+}
+
+pub fn parse_bulk_request(body: &[u8]) -> Result<Vec<BulkOperation>, anyhow::Error> {
+    let body = std::str::from_utf8(body)?;
+    let mut lines = body.lines().filter(|line| !line.trim().is_empty());
+    let mut operations = Vec::new();
+
+    while let Some(line) = lines.next() {
+        let action_line: Map<String, Value> = serde_json::from_str(line)
+            .map_err(|err| anyhow!("Malformed action/metadata line: {}", err))?;
+        let mut action_line = action_line.into_iter();
+        let (Some((action, metadata)), None) = (action_line.next(), action_line.next()) else {
+            return Err(anyhow!(
+                "Malformed action/metadata line, expected exactly one action"
+            ));
+        };
+
+        let index = metadata
+            .get("_index")
+            .and_then(Value::as_str)
+            .map(str::to_owned);
+        let id = metadata.get("_id").and_then(|id| match id {
+            Value::String(id) => Some(id.to_owned()),
+            Value::Number(id) => Some(id.to_string()),
+            _ => None,
+        });
+
+        let mut invalid_source = None;
+        let document = match action.as_str() {
+            ACTION_INDEX | ACTION_CREATE => {
+                let Some(source) = lines.next() else {
+                    return Err(anyhow!("Missing document for {} action", action));
+                };
+                let document = match serde_json::from_str::<Value>(source) {
+                    Ok(document @ Value::Object(_)) => Ok(document),
+                    Ok(_) => Err("document must be a JSON object".to_owned()),
+                    Err(err) => Err(format!("failed to parse document: {}", err)),
+                };
+                if document.is_err() {
+                    invalid_source = Some(source.to_owned());
+                }
+                document
+            }
+            ACTION_UPDATE => {
+                // skip the partial document that belongs to this action
+                lines.next();
+                Err("update action is not supported".to_owned())
+            }
+            ACTION_DELETE => Err("delete action is not supported".to_owned()),
+            _ => return Err(anyhow!("Unknown bulk action {}", action)),
+        };
+
+        operations.push(BulkOperation {
+            action,
+            index,
+            id,
+            document,
+            invalid_source,
+        });
+    }
+
+    Ok(operations)
+}
+
+// root response of an Elasticsearch cluster
+// { "name": "parseable", "cluster_name": "parseable", "version": { "number": "8.11.0", .. }, .. }
+#[derive(Debug, Serialize)]
+pub struct ClusterInfo {
+    pub name: &'static str,
+    pub cluster_name: &'static str,
+    pub version: ClusterVersion,
+    pub tagline: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClusterVersion {
+    pub number: &'static str,
+    pub build_flavor: &'static str,
+}
+
+impl Default for ClusterInfo {
+    fn default() -> Self {
+        Self {
+            name: "parseable",
+            cluster_name: "parseable",
+            version: ClusterVersion {
+                number: COMPATIBLE_VERSION,
+                build_flavor: "default",
+            },
+            tagline: "You Know, for Search",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkResponse {
+    pub took: u128,
+    pub errors: bool,
+    pub items: Vec<Map<String, Value>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkItemResponse {
+    #[serde(rename = "_index")]
+    pub index: Option<String>,
+    #[serde(rename = "_id")]
+    pub id: Option<String>,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<BulkItemError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkItemError {
+    #[serde(rename = "type")]
+    pub error_type: &'static str,
+    pub reason: String,
+}
+
+impl BulkResponse {
+    pub fn new(took: u128, items: Vec<(String, BulkItemResponse)>) -> Self {
+        let errors = items.iter().any(|(_, item)| item.error.is_some());
+        let items = items
+            .into_iter()
+            .map(|(action, item)| {
+                let item = serde_json::to_value(item).expect("bulk item is serializable");
+                Map::from_iter([(action, item)])
+            })
+            .collect();
+
+        Self {
+            took,
+            errors,
+            items,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse_bulk_request;
+
+    #[test]
+    fn parse_index_and_create() {
+        let body = concat!(
+            "{\"index\":{\"_index\":\"app\",\"_id\":\"1\"}}\n",
+            "{\"message\":\"hello\"}\n",
+            "\n",
+            "{\"create\":{\"_index\":\"web\",\"_id\":2}}\n",
+            "{\"message\":\"world\"}\n"
+        );
+
+        let operations = parse_bulk_request(body.as_bytes()).unwrap();
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].action, "index");
+        assert_eq!(operations[0].index.as_deref(), Some("app"));
+        assert_eq!(operations[0].id.as_deref(), Some("1"));
+        assert_eq!(
+            operations[0].document.as_ref().unwrap(),
+            &json!({"message": "hello"})
+        );
+        assert_eq!(operations[1].action, "create");
+        assert_eq!(operations[1].index.as_deref(), Some("web"));
+        assert_eq!(operations[1].id.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn unsupported_and_invalid_documents_are_item_errors() {
+        let body = concat!(
+            "{\"delete\":{\"_index\":\"app\",\"_id\":\"1\"}}\n",
+            "{\"update\":{\"_index\":\"app\",\"_id\":\"1\"}}\n",
+            "{\"doc\":{\"a\":1}}\n",
+            "{\"index\":{\"_index\":\"app\"}}\n",
+            "[1, 2]\n",
+            "{\"index\":{}}\n",
+            "{\"a\":1}\n"
+        );
+
+        let operations = parse_bulk_request(body.as_bytes()).unwrap();
+        assert_eq!(operations.len(), 4);
+        assert!(operations[0].document.is_err());
+        assert!(operations[0].invalid_source.is_none());
+        assert!(operations[1].document.is_err());
+        assert!(operations[2].document.is_err());
+        assert_eq!(operations[2].invalid_source.as_deref(), Some("[1, 2]"));
+        assert!(operations[3].document.is_ok());
+        assert!(operations[3].index.is_none());
+    }
+
+    #[test]
+    fn malformed_action_line_is_err() {
+        assert!(parse_bulk_request(b"{\"index\":{}}").is_err());
+        assert!(parse_bulk_request(b"not json\n{}\n").is_err());
+        assert!(parse_bulk_request(b"{\"upsert\":{}}\n{}\n").is_err());
+    }
+}
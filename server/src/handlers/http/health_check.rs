@@ -20,12 +20,18 @@ use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 
 use crate::option::CONFIG;
+use crate::storage::watermark;
 
 pub async fn liveness() -> HttpResponse {
     HttpResponse::new(StatusCode::OK)
 }
 
 pub async fn readiness() -> HttpResponse {
+    // the server can not take events while the staging disk is above its watermark
+    if let Err(msg) = watermark::check() {
+        return HttpResponse::ServiceUnavailable().body(msg);
+    }
+
     if CONFIG.storage().get_object_store().check().await.is_ok() {
         return HttpResponse::new(StatusCode::OK);
     }
@@ -16,31 +16,80 @@
  *
  */
 
-use actix_web::{http::header::ContentType, HttpRequest, HttpResponse};
+use actix_web::{
+    http::header::{self, ContentType},
+    HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::anyhow;
 use arrow_schema::Field;
 use bytes::Bytes;
+use chrono::Utc;
 use http::StatusCode;
+use serde::Serialize;
 use serde_json::Value;
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::Instant;
 
+use crate::event::dead_letter::{self, Letter};
+use crate::event::dedup::{Claim, DEDUP};
 use crate::event::error::EventError;
+use crate::event::format::json::RejectedRecord;
 use crate::event::format::EventFormat;
+use crate::event::nested::NestedObjects;
+use crate::event::processor::{self, Processor};
+use crate::event::static_schema::{self, SchemaPolicy};
+use crate::event::text_parser::TextParser;
+use crate::event::time_partition::TimePartition;
 use crate::event::{self, format};
 use crate::handlers::{
-    LOG_SOURCE_KEY, LOG_SOURCE_KINESIS, LOG_SOURCE_OTEL, PREFIX_META, PREFIX_TAGS, SEPARATOR,
-    STREAM_NAME_HEADER_KEY,
+    CSV_DELIMITER_KEY, CSV_HEADER_KEY, EVENT_FORMAT_KEY, IDEMPOTENCY_KEY, KINESIS_REQUEST_ID_KEY,
+    LOG_SOURCE_KEY, LOG_SOURCE_KINESIS, LOG_SOURCE_OTEL, PARTIAL_INGEST_KEY, PREFIX_META,
+    PREFIX_PARSEABLE, PREFIX_TAGS, SEPARATOR, STREAM_NAME_HEADER_KEY,
 };
 use crate::metadata::STREAM_INFO;
+use crate::rate_limit::{RateLimit, Throttled, RATE_LIMITER};
+use crate::rbac::map::{sessions, SessionKey};
+use crate::rbac::role::Action;
+use crate::rbac::{self, Users};
+use crate::utils::actix::extract_session_key_from_req;
 use crate::utils::header_parsing::{collect_labelled_headers, ParseHeaderError};
+use crate::utils::json::flatten_json_body_nested;
 
+use super::elastic::{self, BulkItemError, BulkItemResponse, BulkResponse};
 use super::kinesis;
 use super::logstream::error::CreateStreamError;
+use super::loki;
+use super::otel;
+use super::prometheus;
+
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+const TEXT_CONTENT_TYPE: &str = "text/plain";
+const CSV_CONTENT_TYPE: &str = "text/csv";
+const TSV_CONTENT_TYPE: &str = "text/tab-separated-values";
 
 // Handler for POST /api/v1/ingest
 // ingests events by extracting stream name from header
 // creates if stream does not exist
 pub async fn ingest(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
+    // Firehose expects an acknowledgement with the id of its request, on success and on failure
+    if let Some(request_id) = firehose_request_id(&req) {
+        let response = match ingest_with_stream_header(req, body).await {
+            Ok(_) => HttpResponse::Ok().json(kinesis::FirehoseResponse::ack(&request_id)),
+            Err(err) => HttpResponse::build(err.status_code()).json(
+                kinesis::FirehoseResponse::error(&request_id, err.to_string()),
+            ),
+        };
+        return Ok(response);
+    }
+
+    ingest_with_stream_header(req, body).await
+}
+
+async fn ingest_with_stream_header(
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<HttpResponse, PostError> {
     if let Some((_, stream_name)) = req
         .headers()
         .iter()
@@ -49,8 +98,7 @@ pub async fn ingest(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostE
         let stream_name = stream_name.to_str().unwrap().to_owned();
         create_stream_if_not_exists(&stream_name).await?;
 
-        flatten_and_push_logs(req, body, stream_name).await?;
-        Ok(HttpResponse::Ok().finish())
+        ingest_body(stream_name, req, body).await
     } else {
         Err(PostError::Header(ParseHeaderError::MissingStreamName))
     }
@@ -62,17 +110,7 @@ async fn flatten_and_push_logs(
     stream_name: String,
 ) -> Result<(), PostError> {
     //flatten logs
-    if let Some((_, log_source)) = req.headers().iter().find(|&(key, _)| key == LOG_SOURCE_KEY) {
-        let mut json: Vec<BTreeMap<String, Value>> = Vec::new();
-        let log_source: String = log_source.to_str().unwrap().to_owned();
-        match log_source.as_str() {
-            LOG_SOURCE_KINESIS => json = kinesis::flatten_kinesis_logs(&body),
-            LOG_SOURCE_OTEL => {}
-            _ => {
-                log::warn!("Unknown log source: {}", log_source);
-                push_logs(stream_name.to_string(), req.clone(), body).await?;
-            }
-        }
+    if let Some(mut json) = flatten_log_source(&req, &body)? {
         for record in json.iter_mut() {
             let body: Bytes = serde_json::to_vec(record).unwrap().into();
             push_logs(stream_name.to_string(), req.clone(), body).await?;
@@ -83,25 +121,835 @@ async fn flatten_and_push_logs(
     Ok(())
 }
 
+// flattens the body into records if it comes from a known log source
+// returns None if there is no (known) log source and the body is to be ingested as is
+fn flatten_log_source(
+    req: &HttpRequest,
+    body: &Bytes,
+) -> Result<Option<Vec<BTreeMap<String, Value>>>, PostError> {
+    let Some((_, log_source)) = req.headers().iter().find(|&(key, _)| key == LOG_SOURCE_KEY) else {
+        return Ok(None);
+    };
+    let log_source: String = log_source.to_str().unwrap().to_owned();
+    match log_source.as_str() {
+        LOG_SOURCE_KINESIS => Ok(Some(kinesis::flatten_kinesis_logs(body)?)),
+        LOG_SOURCE_OTEL => Ok(Some(otel::flatten_otel_logs(
+            otel::decode_json(body)?,
+            Utc::now(),
+        ))),
+        _ => {
+            log::warn!("Unknown log source: {}", log_source);
+            Ok(None)
+        }
+    }
+}
+
+fn firehose_request_id(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(KINESIS_REQUEST_ID_KEY)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+fn idempotency_key(req: &HttpRequest) -> Result<Option<String>, PostError> {
+    match req.headers().get(IDEMPOTENCY_KEY) {
+        Some(value) => match value.to_str() {
+            Ok(key) if !key.is_empty() => Ok(Some(key.to_owned())),
+            _ => Err(ParseHeaderError::InvalidValue.into()),
+        },
+        None => Ok(None),
+    }
+}
+
+fn is_partial_ingest(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(PARTIAL_INGEST_KEY)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.eq_ignore_ascii_case("true"))
+}
+
+fn is_text_body(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with(TEXT_CONTENT_TYPE))
+}
+
+// splits a plain text body into events and parses them into json records
+// with the text parser of the stream, the records are then ingested as a json array
+fn text_into_json(stream_name: &str, body: &Bytes) -> Result<Bytes, PostError> {
+    let text = std::str::from_utf8(body)
+        .map_err(|err| anyhow!("Plain text body is not valid UTF-8, {}", err))?;
+    let records = {
+        let hash_map = STREAM_INFO.read().unwrap();
+        let stream = hash_map
+            .get(stream_name)
+            .ok_or(PostError::StreamNotFound(stream_name.to_owned()))?;
+        match &stream.text_parser {
+            Some(text_parser) => text_parser.parse(text),
+            None => TextParser::default().parse(text),
+        }
+    };
+    Ok(serde_json::to_vec(&records)?.into())
+}
+
+#[derive(Debug, Serialize)]
+pub struct IngestReport {
+    ingested: usize,
+    rejected: Vec<RejectedRecord>,
+}
+
+// ingests the records of the body that match the schema of the stream
+// and reports back the ones that do not instead of failing the whole batch
+async fn push_logs_partial(
+    stream_name: String,
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<IngestReport, PostError> {
+    let records: Vec<Value> = match flatten_log_source(&req, &body)? {
+        Some(json) => json
+            .into_iter()
+            .map(|record| Value::Object(record.into_iter().collect()))
+            .collect(),
+        None => match serde_json::from_slice(&body)? {
+            Value::Array(arr) => arr,
+            value => vec![value],
+        },
+    };
+    let tags = collect_labelled_headers(&req, PREFIX_TAGS, SEPARATOR)?;
+    let metadata = collect_labelled_headers(&req, PREFIX_META, SEPARATOR)?;
+    let credential = request_credential(&req);
+    // the records are processed in place, the ones rejected are kept as they were sent
+    let originals =
+        matches!(STREAM_INFO.dead_letter(&stream_name), Ok(Some(_))).then(|| records.clone());
+
+    let (batch, rejected) = {
+        let hash_map = STREAM_INFO.read().unwrap();
+        let stream = hash_map
+            .get(&stream_name)
+            .ok_or(PostError::StreamNotFound(stream_name.clone()))?;
+        let schema = &stream.schema;
+        let nested = stream.nested_objects.clone().unwrap_or_default();
+
+        // processors and the static schema of the stream are applied one record at a time first
+        let mut rejected = Vec::new();
+        let (indexes, records): (Vec<usize>, Vec<Value>) = records
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, record)| {
+                match prepare_record(
+                    &stream.processors,
+                    stream.static_schema_policy,
+                    &nested,
+                    schema,
+                    record,
+                ) {
+                    Ok(record) => Some((index, record)),
+                    Err(reason) => {
+                        rejected.push(RejectedRecord { index, reason });
+                        None
+                    }
+                }
+            })
+            .unzip();
+
+        let (accepted, partitioned) = format::json::partition_records(schema, &nested, records);
+        rejected.extend(partitioned.into_iter().map(|record| RejectedRecord {
+            index: indexes[record.index],
+            reason: record.reason,
+        }));
+        rejected.sort_by_key(|record| record.index);
+        let batch = if accepted.is_empty() {
+            None
+        } else {
+            let ingested = accepted.len();
+            let event = format::json::Event {
+                data: Value::Array(accepted),
+                tags,
+                metadata,
+                nested,
+            };
+            check_rate_limit(
+                &stream_name,
+                stream.rate_limit.as_ref(),
+                credential.as_deref(),
+                ingested,
+                body.len(),
+            )?;
+            Some((ingested, event.into_recordbatch(schema.clone())?))
+        };
+        (batch, rejected)
+    };
+
+    if let Some(originals) = originals {
+        let letters = rejected
+            .iter()
+            .map(|record| {
+                (
+                    originals[record.index].to_string().into_bytes(),
+                    record.reason.clone(),
+                )
+            })
+            .collect();
+        push_dead_letters(&stream_name, &dead_letter_headers(&req), letters).await;
+    }
+
+    let Some((ingested, (rb, is_first_event))) = batch else {
+        return Ok(IngestReport {
+            ingested: 0,
+            rejected,
+        });
+    };
+
+    event::Event {
+        rb,
+        stream_name,
+        origin_format: "json",
+        origin_size: body.len() as u64,
+        is_first_event,
+    }
+    .process()
+    .await?;
+
+    Ok(IngestReport { ingested, rejected })
+}
+
+// Handler for POST /api/v1/logs
+// ingests OTLP/HTTP log export requests, protobuf or JSON encoded,
+// into the stream named in the x-p-stream header.
+// Creates the stream if it does not exist, with a time partition on the time of the
+// log records so that it is the time of the events
+pub async fn ingest_otel_logs(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
+    let received_at = Utc::now();
+    let Some(stream_name) = req.headers().get(STREAM_NAME_HEADER_KEY) else {
+        return Err(PostError::Header(ParseHeaderError::MissingStreamName));
+    };
+    let stream_name = stream_name
+        .to_str()
+        .map_err(|_| ParseHeaderError::InvalidValue)?
+        .to_owned();
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        let time_partition = TimePartition::new(otel::TIME_KEY.to_owned(), None)
+            .expect("time_unix_nano is a valid time partition");
+        super::logstream::create_stream(stream_name.clone(), Some(time_partition), None).await?;
+    }
+
+    let is_protobuf = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with(PROTOBUF_CONTENT_TYPE));
+
+    let request = if is_protobuf {
+        otel::decode_protobuf(&body).map_err(PostError::from)
+    } else {
+        otel::decode_json(&body).map_err(PostError::from)
+    };
+    let request = dead_letter_rejected(&stream_name, &req, &body, request).await?;
+
+    let records = otel::flatten_otel_logs(request, received_at);
+    push_flattened_logs(stream_name, req, &records).await?;
+
+    // OTLP expects an (empty) ExportLogsServiceResponse in the same encoding as the request
+    if is_protobuf {
+        Ok(HttpResponse::Ok()
+            .content_type(PROTOBUF_CONTENT_TYPE)
+            .finish())
+    } else {
+        Ok(HttpResponse::Ok().json(serde_json::json!({})))
+    }
+}
+
+// Handler for POST /loki/api/v1/push
+// ingests Grafana Loki push requests, snappy compressed protobuf or JSON,
+// into the stream named in the x-p-stream header.
+// Creates the stream if it does not exist, with a time partition on the timestamp of
+// the entries so that it is the time of the events. Pushes into existing streams
+// without that time partition are rejected, as the entry time would only be a column
+pub async fn ingest_loki_push(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
+    let received_at = Utc::now();
+    let Some(stream_name) = req.headers().get(STREAM_NAME_HEADER_KEY) else {
+        return Err(PostError::Header(ParseHeaderError::MissingStreamName));
+    };
+    let stream_name = stream_name
+        .to_str()
+        .map_err(|_| ParseHeaderError::InvalidValue)?
+        .to_owned();
+    let time_partition = TimePartition::new(loki::TIMESTAMP_KEY.to_owned(), None)
+        .expect("timestamp is a valid time partition");
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        super::logstream::create_stream(stream_name.clone(), Some(time_partition), None).await?;
+    } else if STREAM_INFO.time_partition(&stream_name).ok().flatten() != Some(time_partition) {
+        return Err(PostError::Invalid(anyhow!(
+            "Stream {} has no time partition on {}, which is required for the time of Loki entries to be the event time",
+            stream_name,
+            loki::TIMESTAMP_KEY
+        )));
+    }
+
+    let is_protobuf = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with(PROTOBUF_CONTENT_TYPE));
+
+    let records = if is_protobuf {
+        loki::flatten_protobuf_push(&body, received_at)
+    } else {
+        loki::flatten_json_push(&body)
+    }
+    .map_err(PostError::from);
+    let records = dead_letter_rejected(&stream_name, &req, &body, records).await?;
+    push_flattened_logs(stream_name, req, &records).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Handler for POST /api/v1/write
+// ingests Prometheus remote write requests, snappy compressed protobuf,
+// into the stream named in the x-p-stream header. Every sample is a row
+// creates if stream does not exist
+pub async fn ingest_prometheus_write(
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<HttpResponse, PostError> {
+    let Some(stream_name) = req.headers().get(STREAM_NAME_HEADER_KEY) else {
+        return Err(PostError::Header(ParseHeaderError::MissingStreamName));
+    };
+    let stream_name = stream_name
+        .to_str()
+        .map_err(|_| ParseHeaderError::InvalidValue)?
+        .to_owned();
+    create_stream_if_not_exists(&stream_name).await?;
+
+    let records = prometheus::flatten_write_request(&body).map_err(PostError::from);
+    let records = dead_letter_rejected(&stream_name, &req, &body, records).await?;
+    push_flattened_logs(stream_name, req, &records).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// pushes the records an OTel, Loki or Prometheus request is flattened into,
+// adding them to the dead letter stream of the stream if they are rejected
+async fn push_flattened_logs<T: Serialize>(
+    stream_name: String,
+    req: HttpRequest,
+    records: &[T],
+) -> Result<(), PostError> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let body: Bytes = serde_json::to_vec(records)?.into();
+    let result = push_logs(stream_name.clone(), req.clone(), body.clone()).await;
+    dead_letter_rejected(&stream_name, &req, &body, result).await
+}
+
+// Handler for GET /api/v1
+// answers like the root of an Elasticsearch cluster, for clients that
+// check the version before they send bulk requests
+pub async fn elastic_info() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((elastic::PRODUCT_HEADER_KEY, elastic::PRODUCT))
+        .json(elastic::ClusterInfo::default())
+}
+
+// Handler for POST /api/v1/_bulk and POST /api/v1/{logstream}/_bulk
+// ingests documents sent with the Elasticsearch bulk protocol.
+// `_index` of every action is used as the stream name, falling back to the
+// index in the path, then to the x-p-stream header. Streams are created if they do not exist.
+// Actions are only carried out for the streams the user is allowed to ingest into,
+// the route itself only checks the stream in the path
+pub async fn ingest_bulk(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
+    let start = Instant::now();
+    let default_stream = match req.match_info().get("logstream") {
+        Some(index) => Some(index.to_owned()),
+        None => req
+            .headers()
+            .get(STREAM_NAME_HEADER_KEY)
+            .map(|value| value.to_str().map(str::to_owned))
+            .transpose()
+            .map_err(|_| ParseHeaderError::InvalidValue)?,
+    };
+
+    let operations = elastic::parse_bulk_request(&body)?;
+    let mut results: Vec<Option<Result<(), (u16, BulkItemError)>>> = Vec::new();
+    let mut streams: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    // documents rejected as invalid, by stream, for the dead letter streams
+    let mut letters: BTreeMap<String, Vec<(Vec<u8>, String)>> = BTreeMap::new();
+
+    let session_key = extract_session_key_from_req(&req).ok();
+    let mut authorized: HashMap<String, bool> = HashMap::new();
+    let mut is_authorized = |stream_name: &str| {
+        *authorized.entry(stream_name.to_owned()).or_insert_with(|| {
+            session_key.clone().map_or(false, |key| {
+                matches!(
+                    Users.authorize(key, Action::Ingest, Some(stream_name), None),
+                    rbac::Response::Authorized
+                )
+            })
+        })
+    };
+
+    for (idx, operation) in operations.iter().enumerate() {
+        let stream_name = operation.index.as_ref().or(default_stream.as_ref());
+        if let Some(stream_name) = stream_name.filter(|stream_name| !is_authorized(stream_name)) {
+            results.push(Some(Err(bulk_item_error(
+                StatusCode::FORBIDDEN,
+                elastic::ERROR_TYPE_SECURITY,
+                format!("not allowed to ingest into {}", stream_name),
+            ))));
+            continue;
+        }
+        let result = match (&operation.document, stream_name) {
+            (Err(reason), stream_name) => {
+                if let (Some(source), Some(stream_name)) = (&operation.invalid_source, stream_name)
+                {
+                    letters
+                        .entry(stream_name.to_owned())
+                        .or_default()
+                        .push((source.as_bytes().to_vec(), reason.to_owned()));
+                }
+                Some(Err(bulk_item_error(
+                    StatusCode::BAD_REQUEST,
+                    elastic::ERROR_TYPE_MAPPER_PARSING,
+                    reason.to_owned(),
+                )))
+            }
+            (Ok(_), None) => Some(Err(bulk_item_error(
+                StatusCode::BAD_REQUEST,
+                elastic::ERROR_TYPE_ILLEGAL_ARGUMENT,
+                "no _index in action and no x-p-stream header".to_owned(),
+            ))),
+            (Ok(_), Some(stream_name)) => {
+                streams.entry(stream_name.to_owned()).or_default().push(idx);
+                None
+            }
+        };
+        results.push(result);
+    }
+
+    for (stream_name, indexes) in streams {
+        if let Err(err) = create_stream_if_not_exists(&stream_name).await {
+            let error = bulk_post_error(&err);
+            for idx in indexes {
+                results[idx] = Some(Err(error.clone()));
+            }
+            continue;
+        }
+
+        // push all documents of a stream at once and only fall back to
+        // pushing them one by one to find out which of them were rejected
+        let documents: Vec<&Value> = indexes
+            .iter()
+            .filter_map(|&idx| operations[idx].document.as_ref().ok())
+            .collect();
+        let batch: Bytes = serde_json::to_vec(&documents)?.into();
+        match push_logs(stream_name.clone(), req.clone(), batch).await {
+            Ok(()) => {
+                for idx in indexes {
+                    results[idx] = Some(Ok(()));
+                }
+            }
+            Err(err) if indexes.len() == 1 => {
+                if err.status_code() == StatusCode::BAD_REQUEST {
+                    let letter = (serde_json::to_vec(documents[0])?, err.to_string());
+                    letters.entry(stream_name).or_default().push(letter);
+                }
+                results[indexes[0]] = Some(Err(bulk_post_error(&err)))
+            }
+            Err(_) => {
+                for idx in indexes {
+                    let document = operations[idx]
+                        .document
+                        .as_ref()
+                        .expect("grouped documents are valid");
+                    let body: Bytes = serde_json::to_vec(document)?.into();
+                    let result = push_logs(stream_name.clone(), req.clone(), body.clone()).await;
+                    if let Err(err) = &result {
+                        if err.status_code() == StatusCode::BAD_REQUEST {
+                            let letter = (body.to_vec(), err.to_string());
+                            letters.entry(stream_name.clone()).or_default().push(letter);
+                        }
+                    }
+                    results[idx] = Some(result.map_err(|err| bulk_post_error(&err)));
+                }
+            }
+        }
+    }
+
+    let headers = dead_letter_headers(&req);
+    for (stream_name, letters) in letters {
+        push_dead_letters(&stream_name, &headers, letters).await;
+    }
+
+    let items = operations
+        .into_iter()
+        .zip(results)
+        .map(|(operation, result)| {
+            let index = operation.index.or_else(|| default_stream.clone());
+            let id = operation.id.or_else(|| Some(ulid::Ulid::new().to_string()));
+            let item = match result.expect("every operation has a result") {
+                Ok(()) => BulkItemResponse {
+                    index,
+                    id,
+                    status: StatusCode::CREATED.as_u16(),
+                    result: Some("created"),
+                    error: None,
+                },
+                Err((status, error)) => BulkItemResponse {
+                    index,
+                    id,
+                    status,
+                    result: None,
+                    error: Some(error),
+                },
+            };
+            (operation.action, item)
+        })
+        .collect();
+
+    let response = BulkResponse::new(start.elapsed().as_millis(), items);
+    Ok(HttpResponse::Ok()
+        .insert_header((elastic::PRODUCT_HEADER_KEY, elastic::PRODUCT))
+        .json(response))
+}
+
+fn bulk_item_error(
+    status: StatusCode,
+    error_type: &'static str,
+    reason: String,
+) -> (u16, BulkItemError) {
+    (status.as_u16(), BulkItemError { error_type, reason })
+}
+
+fn bulk_post_error(err: &PostError) -> (u16, BulkItemError) {
+    let status = err.status_code();
+    let error_type = if status == StatusCode::TOO_MANY_REQUESTS {
+        elastic::ERROR_TYPE_REJECTED_EXECUTION
+    } else if status.is_client_error() {
+        elastic::ERROR_TYPE_MAPPER_PARSING
+    } else {
+        elastic::ERROR_TYPE_INTERNAL
+    };
+    bulk_item_error(status, error_type, err.to_string())
+}
+
 // Handler for POST /api/v1/logstream/{logstream}
 // only ingests events into the specified logstream
 // fails if the logstream does not exist
 pub async fn post_event(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
 
+    ingest_body(stream_name, req, body).await
+}
+
+// ingests the body of a request to the ingest or the post event endpoint
+// as per its format, json unless the request says otherwise
+async fn ingest_body(
+    stream_name: String,
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<HttpResponse, PostError> {
+    let Some(key) = idempotency_key(&req)? else {
+        let reply = push_body(stream_name, req, body).await?;
+        return Ok(ingest_response(reply));
+    };
+
+    // duplicates are answered with the reply to the request, so the rejections of a
+    // partial ingest are reported again. Records fixed since are to be sent with a new key
+    let guard = match DEDUP.claim(&stream_name, &key) {
+        Claim::New(guard) => guard,
+        Claim::Duplicate(reply) => {
+            log::debug!(
+                "skipping request with idempotency key {} already ingested into {}",
+                key,
+                stream_name
+            );
+            return Ok(ingest_response(reply));
+        }
+        Claim::InProgress => return Err(PostError::IdempotencyKeyInProgress(key)),
+    };
+    let reply = push_body(stream_name, req, body).await?;
+    guard.accept(reply.clone());
+    Ok(ingest_response(reply))
+}
+
+// reply is the json body of the response, if it has one
+fn ingest_response(reply: Option<Bytes>) -> HttpResponse {
+    match reply {
+        Some(body) => HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .body(body),
+        None => HttpResponse::Ok().finish(),
+    }
+}
+
+// bodies rejected as invalid are added to the dead letter stream of the stream, if it has one.
+// Returns the body to reply with, the report of a partial ingest
+async fn push_body(
+    stream_name: String,
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<Option<Bytes>, PostError> {
+    let result = push_body_as_format(stream_name.clone(), req.clone(), body.clone()).await;
+    dead_letter_rejected(&stream_name, &req, &body, result).await
+}
+
+// adds the payload to the dead letter stream of the stream if the result rejects it as invalid
+async fn dead_letter_rejected<T>(
+    stream_name: &str,
+    req: &HttpRequest,
+    payload: &[u8],
+    result: Result<T, PostError>,
+) -> Result<T, PostError> {
+    if let Err(err) = &result {
+        if err.status_code() == StatusCode::BAD_REQUEST {
+            let letters = vec![(payload.to_vec(), err.to_string())];
+            push_dead_letters(stream_name, &dead_letter_headers(req), letters).await;
+        }
+    }
+    result
+}
+
+async fn push_body_as_format(
+    stream_name: String,
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<Option<Bytes>, PostError> {
+    if let Some((delimiter, has_header)) = csv_format(&req)? {
+        push_csv(&stream_name, &req, body, delimiter, has_header).await?;
+        return Ok(None);
+    }
+
+    let body = if is_text_body(&req) {
+        text_into_json(&stream_name, &body)?
+    } else {
+        body
+    };
+    if is_partial_ingest(&req) {
+        let report = push_logs_partial(stream_name, req, body).await?;
+        return Ok(Some(serde_json::to_vec(&report)?.into()));
+    }
     flatten_and_push_logs(req, body, stream_name).await?;
-    Ok(HttpResponse::Ok().finish())
+    Ok(None)
+}
+
+// Adds rejected events, as their payload and the reason they were rejected, to the dead
+// letter stream of the stream if it has one. Payloads are run through the processors of
+// the stream first. Events that can not be added are only logged, the request is answered
+// the same either way
+pub async fn push_dead_letters(
+    stream_name: &str,
+    headers: &BTreeMap<String, String>,
+    letters: Vec<(Vec<u8>, String)>,
+) {
+    if letters.is_empty() {
+        return;
+    }
+    let (dead_letter, processors, nested) = {
+        let hash_map = STREAM_INFO.read().unwrap();
+        let Some(stream) = hash_map.get(stream_name) else {
+            return;
+        };
+        let Some(dead_letter) = stream.dead_letter.clone() else {
+            return;
+        };
+        (
+            dead_letter,
+            stream.processors.clone(),
+            stream.nested_objects.clone().unwrap_or_default(),
+        )
+    };
+
+    let received_at = Utc::now();
+    let mut size = 0;
+    let records = letters
+        .into_iter()
+        .map(|(payload, reason)| {
+            let payload = dead_letter::process_payload(&processors, &nested, payload);
+            size += payload.len();
+            Letter {
+                stream: stream_name,
+                reason,
+                payload,
+                headers,
+                received_at,
+            }
+            .into_record()
+        })
+        .collect();
+    if let Err(err) = push_stream_records(&dead_letter.stream, records, size).await {
+        log::warn!(
+            "Could not add rejected events of stream {} to dead letter stream {}, {}",
+            stream_name,
+            dead_letter.stream,
+            err
+        );
+    }
+}
+
+// headers of a request that are kept with its rejected events, credentials are left out
+fn dead_letter_headers(req: &HttpRequest) -> BTreeMap<String, String> {
+    req.headers()
+        .iter()
+        .filter(|&(key, _)| {
+            key == header::CONTENT_TYPE
+                || key == header::USER_AGENT
+                || key.as_str().starts_with(PREFIX_PARSEABLE)
+        })
+        .filter_map(|(key, value)| Some((key.to_string(), value.to_str().ok()?.to_owned())))
+        .collect()
+}
+
+// delimiter and header option of a csv or tsv body, None for other formats.
+// The format is picked from the x-p-format header or else the content type
+fn csv_format(req: &HttpRequest) -> Result<Option<(u8, Option<bool>)>, PostError> {
+    let header = |key| {
+        req.headers()
+            .get(key)
+            .map(|value| value.to_str())
+            .transpose()
+            .map_err(|_| ParseHeaderError::InvalidValue)
+    };
+
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let delimiter = match header(EVENT_FORMAT_KEY)? {
+        Some(format) if format.eq_ignore_ascii_case("csv") => b',',
+        Some(format) if format.eq_ignore_ascii_case("tsv") => b'\t',
+        Some(format) => return Err(PostError::Invalid(anyhow!("Unknown format {}", format))),
+        None if content_type.starts_with(CSV_CONTENT_TYPE) => b',',
+        None if content_type.starts_with(TSV_CONTENT_TYPE) => b'\t',
+        None => return Ok(None),
+    };
+
+    let delimiter = match header(CSV_DELIMITER_KEY)? {
+        Some("\\t") => b'\t',
+        Some(value) if value.len() == 1 => value.as_bytes()[0],
+        Some(value) => {
+            return Err(PostError::Invalid(anyhow!(
+                "Delimiter must be a single character, got {}",
+                value
+            )))
+        }
+        None => delimiter,
+    };
+
+    let has_header = match header(CSV_HEADER_KEY)? {
+        Some(value) => Some(
+            value
+                .parse::<bool>()
+                .map_err(|_| ParseHeaderError::InvalidValue)?,
+        ),
+        None => None,
+    };
+
+    Ok(Some((delimiter, has_header)))
+}
+
+async fn push_csv(
+    stream_name: &str,
+    req: &HttpRequest,
+    body: Bytes,
+    delimiter: u8,
+    has_header: Option<bool>,
+) -> Result<(), PostError> {
+    let tags = collect_labelled_headers(req, PREFIX_TAGS, SEPARATOR)?;
+    let metadata = collect_labelled_headers(req, PREFIX_META, SEPARATOR)?;
+    let size = body.len();
+    let credential = request_credential(req);
+
+    let (rb, is_first_event) = {
+        let hash_map = STREAM_INFO.read().unwrap();
+        let stream = hash_map
+            .get(stream_name)
+            .ok_or(PostError::StreamNotFound(stream_name.to_owned()))?;
+        let event = format::csv::Event {
+            data: body,
+            delimiter,
+            has_header,
+            tags,
+            metadata,
+        };
+        // the processors of a stream run on json records, rows are turned into records for them
+        let (rb, is_first_event) = if stream.processors.is_empty() {
+            event.into_recordbatch(stream.schema.clone())?
+        } else {
+            let nested = stream.nested_objects.clone().unwrap_or_default();
+            let (records, tags, metadata) = event.into_records(stream.schema.clone())?;
+            let data = prepare_records(
+                &stream.processors,
+                stream.static_schema_policy,
+                &nested,
+                &stream.schema,
+                records,
+            )?;
+            let event = format::json::Event {
+                data,
+                tags,
+                metadata,
+                nested,
+            };
+            event.into_recordbatch(stream.schema.clone())?
+        };
+        check_rate_limit(
+            stream_name,
+            stream.rate_limit.as_ref(),
+            credential.as_deref(),
+            rb.num_rows(),
+            size,
+        )?;
+        match stream.static_schema_policy {
+            // columns that are not part of a static schema are dropped or rejected here
+            Some(policy) if stream.processors.is_empty() => {
+                let rb = static_schema::enforce_batch(policy, &stream.schema, rb)
+                    .map_err(|reason| anyhow!("Could not process this event, {}", reason))?;
+                (rb, false)
+            }
+            _ => (rb, is_first_event),
+        }
+    };
+
+    event::Event {
+        rb,
+        stream_name: stream_name.to_owned(),
+        // stats are tracked under the json format for every source
+        origin_format: "json",
+        origin_size: size as u64,
+        is_first_event,
+    }
+    .process()
+    .await?;
+
+    Ok(())
 }
 
 async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result<(), PostError> {
+    let credential = request_credential(&req);
     let (size, rb, is_first_event) = {
         let hash_map = STREAM_INFO.read().unwrap();
-        let schema = hash_map
+        let stream = hash_map
             .get(&stream_name)
-            .ok_or(PostError::StreamNotFound(stream_name.clone()))?
-            .schema
-            .clone();
-        into_event_batch(req, body, schema)?
+            .ok_or(PostError::StreamNotFound(stream_name.clone()))?;
+        let (size, rb, is_first_event) = into_event_batch(
+            req,
+            body,
+            stream.schema.clone(),
+            &stream.processors,
+            stream.static_schema_policy,
+            stream.nested_objects.clone().unwrap_or_default(),
+        )?;
+        check_rate_limit(
+            &stream_name,
+            stream.rate_limit.as_ref(),
+            credential.as_deref(),
+            rb.num_rows(),
+            size,
+        )?;
+        (size, rb, is_first_event)
     };
 
     event::Event {
@@ -117,30 +965,268 @@ async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result
     Ok(())
 }
 
+// the user a request is made as, rate limits per credential are tracked under it.
+fn request_credential(req: &HttpRequest) -> Option<String> {
+    Some(session_credential(extract_session_key_from_req(req).ok()?))
+}
+
+// Requests of a session are tracked under its user and under the session id otherwise
+pub fn session_credential(key: SessionKey) -> String {
+    match key {
+        SessionKey::BasicAuth { username, .. } => username,
+        SessionKey::SessionId(id) => sessions()
+            .get_username(&SessionKey::SessionId(id))
+            .cloned()
+            .unwrap_or_else(|| id.to_string()),
+    }
+}
+
+// takes the events and bytes of a request from the rate limits of the stream, if it has any
+fn check_rate_limit(
+    stream_name: &str,
+    rate_limit: Option<&RateLimit>,
+    credential: Option<&str>,
+    events: usize,
+    bytes: usize,
+) -> Result<(), PostError> {
+    let Some(rate_limit) = rate_limit else {
+        return Ok(());
+    };
+    RATE_LIMITER
+        .acquire(
+            stream_name,
+            credential,
+            rate_limit,
+            events as u64,
+            bytes as u64,
+        )
+        .map_err(PostError::RateLimited)
+}
+
+// pushes already decoded records into a stream for ingestion paths that
+// do not go through an HTTP request, such as the syslog listener.
+// Records rejected as invalid are added to the dead letter stream of the stream,
+// with the source they were read from. Creates the stream if it does not exist
+pub async fn push_records(
+    stream_name: &str,
+    records: Vec<BTreeMap<String, Value>>,
+    origin_size: usize,
+    source: &str,
+) -> Result<(), PostError> {
+    let originals =
+        matches!(STREAM_INFO.dead_letter(stream_name), Ok(Some(_))).then(|| records.clone());
+    let result = push_stream_records(stream_name, records, origin_size).await;
+    if let (Err(err), Some(originals)) = (&result, originals) {
+        if err.status_code() == StatusCode::BAD_REQUEST {
+            let letters = originals
+                .iter()
+                .map(|record| {
+                    let payload = serde_json::to_vec(record).unwrap_or_default();
+                    (payload, err.to_string())
+                })
+                .collect();
+            let headers = dead_letter::source_headers(source.to_owned());
+            push_dead_letters(stream_name, &headers, letters).await;
+        }
+    }
+    result
+}
+
+async fn push_stream_records(
+    stream_name: &str,
+    records: Vec<BTreeMap<String, Value>>,
+    origin_size: usize,
+) -> Result<(), PostError> {
+    create_stream_if_not_exists(stream_name).await?;
+
+    let (rb, is_first_event) = {
+        let hash_map = STREAM_INFO.read().unwrap();
+        let stream = hash_map
+            .get(stream_name)
+            .ok_or(PostError::StreamNotFound(stream_name.to_owned()))?;
+        let schema = stream.schema.clone();
+        let nested = stream.nested_objects.clone().unwrap_or_default();
+        let data = records
+            .into_iter()
+            .map(|record| Value::Object(record.into_iter().collect()))
+            .collect();
+        let data = prepare_records(
+            &stream.processors,
+            stream.static_schema_policy,
+            &nested,
+            &schema,
+            data,
+        )?;
+        let event = format::json::Event {
+            data,
+            tags: BTreeMap::default(),
+            metadata: BTreeMap::default(),
+            nested,
+        };
+        let (rb, is_first_event) = event.into_recordbatch(schema)?;
+        // these sources have no credentials, only the stream limit applies
+        check_rate_limit(
+            stream_name,
+            stream.rate_limit.as_ref(),
+            None,
+            rb.num_rows(),
+            origin_size,
+        )?;
+        (rb, is_first_event)
+    };
+
+    event::Event {
+        rb,
+        stream_name: stream_name.to_owned(),
+        origin_format: "json",
+        origin_size: origin_size as u64,
+        is_first_event,
+    }
+    .process()
+    .await?;
+
+    Ok(())
+}
+
+// pushes an arrow record batch into a stream as is, used by Arrow Flight do_put.
+// creates the stream if it does not exist
+pub async fn push_arrow_batch(
+    stream_name: &str,
+    credential: Option<&str>,
+    rb: arrow_array::RecordBatch,
+    origin_size: usize,
+) -> Result<(), PostError> {
+    create_stream_if_not_exists(stream_name).await?;
+
+    let (rb, is_first_event) = {
+        let hash_map = STREAM_INFO.read().unwrap();
+        let stream = hash_map
+            .get(stream_name)
+            .ok_or(PostError::StreamNotFound(stream_name.to_owned()))?;
+        // processors run on json records, batches would reach storage without them
+        if !stream.processors.is_empty() {
+            return Err(PostError::Invalid(anyhow!(
+                "Stream {} has processors, which are not applied to arrow batches, send its events as JSON instead",
+                stream_name
+            )));
+        }
+        let schema = stream.schema.clone();
+        let rb = match stream.static_schema_policy {
+            Some(policy) => static_schema::enforce_batch(policy, &schema, rb)
+                .map_err(|reason| anyhow!("Could not process this event, {}", reason))?,
+            None => rb,
+        };
+        check_rate_limit(
+            stream_name,
+            stream.rate_limit.as_ref(),
+            credential,
+            rb.num_rows(),
+            origin_size,
+        )?;
+        let event = format::arrow::Event {
+            rb,
+            tags: BTreeMap::default(),
+            metadata: BTreeMap::default(),
+        };
+        event.into_recordbatch(schema)?
+    };
+
+    event::Event {
+        rb,
+        stream_name: stream_name.to_owned(),
+        // stats are tracked under the json format for every source
+        origin_format: "json",
+        origin_size: origin_size as u64,
+        is_first_event,
+    }
+    .process()
+    .await?;
+
+    Ok(())
+}
+
 fn into_event_batch(
     req: HttpRequest,
     body: Bytes,
     schema: HashMap<String, Arc<Field>>,
+    processors: &[Processor],
+    static_schema_policy: Option<SchemaPolicy>,
+    nested: NestedObjects,
 ) -> Result<(usize, arrow_array::RecordBatch, bool), PostError> {
     let tags = collect_labelled_headers(&req, PREFIX_TAGS, SEPARATOR)?;
     let metadata = collect_labelled_headers(&req, PREFIX_META, SEPARATOR)?;
     let size = body.len();
     let body: Value = serde_json::from_slice(&body)?;
+    let records = match body {
+        Value::Array(arr) => arr,
+        body => vec![body],
+    };
+    let body = prepare_records(processors, static_schema_policy, &nested, &schema, records)?;
     let event = format::json::Event {
         data: body,
         tags,
         metadata,
+        nested,
     };
     let (rb, is_first) = event.into_recordbatch(schema)?;
     Ok((size, rb, is_first))
 }
 
+// runs the per stream steps on every record of a batch, see prepare_record.
+// Any record that fails these steps fails the whole batch
+fn prepare_records(
+    processors: &[Processor],
+    static_schema_policy: Option<SchemaPolicy>,
+    nested: &NestedObjects,
+    schema: &HashMap<String, Arc<Field>>,
+    records: Vec<Value>,
+) -> Result<Value, PostError> {
+    if processors.is_empty() && static_schema_policy.is_none() {
+        return Ok(Value::Array(records));
+    }
+
+    let records = records
+        .into_iter()
+        .enumerate()
+        .map(|(index, record)| {
+            prepare_record(processors, static_schema_policy, nested, schema, record)
+                .map_err(|reason| anyhow!("Could not process record {}, {}", index, reason))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Value::Array(records))
+}
+
+// runs the processors of the stream on the flattened record and then
+// checks it against the static schema of the stream, if it has one
+fn prepare_record(
+    processors: &[Processor],
+    static_schema_policy: Option<SchemaPolicy>,
+    nested: &NestedObjects,
+    schema: &HashMap<String, Arc<Field>>,
+    record: Value,
+) -> Result<Value, String> {
+    let record = if processors.is_empty() {
+        record
+    } else {
+        let mut record = flatten_json_body_nested(record, nested).map_err(|err| err.to_string())?;
+        if let Value::Object(record) = &mut record {
+            processor::apply(processors, record);
+        }
+        record
+    };
+
+    match static_schema_policy {
+        Some(policy) => static_schema::enforce_record(policy, schema, record),
+        None => Ok(record),
+    }
+}
+
 // Check if the stream exists and create a new stream if doesn't exist
 pub async fn create_stream_if_not_exists(stream_name: &str) -> Result<(), PostError> {
     if STREAM_INFO.stream_exists(stream_name) {
         return Ok(());
     }
-    super::logstream::create_stream(stream_name.to_string()).await?;
+    super::logstream::create_stream(stream_name.to_string(), None, None).await?;
     Ok(())
 }
 
@@ -150,6 +1236,8 @@ pub enum PostError {
     StreamNotFound(String),
     #[error("Could not deserialize into JSON object, {0}")]
     SerdeError(#[from] serde_json::Error),
+    #[error("Could not decode protobuf message, {0}")]
+    ProtobufError(#[from] prost::DecodeError),
     #[error("Header Error: {0}")]
     Header(#[from] ParseHeaderError),
     #[error("Event Error: {0}")]
@@ -158,13 +1246,28 @@ pub enum PostError {
     Invalid(#[from] anyhow::Error),
     #[error("{0}")]
     CreateStream(#[from] CreateStreamError),
+    #[error("Rate limit of the {} exceeded, retry after {} seconds", .0.limit, .0.retry_after_secs)]
+    RateLimited(Throttled),
+    #[error("A request with idempotency key {0} is being ingested")]
+    IdempotencyKeyInProgress(String),
+}
+
+impl PostError {
+    // events of the error are not accepted on a retry either, unlike when they are throttled
+    pub fn is_rejection(&self) -> bool {
+        let status = self.status_code();
+        status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS
+    }
 }
 
 impl actix_web::ResponseError for PostError {
     fn status_code(&self) -> http::StatusCode {
         match self {
             PostError::SerdeError(_) => StatusCode::BAD_REQUEST,
+            PostError::ProtobufError(_) => StatusCode::BAD_REQUEST,
             PostError::Header(_) => StatusCode::BAD_REQUEST,
+            PostError::Event(EventError::TimePartition(..)) => StatusCode::BAD_REQUEST,
+            PostError::Event(EventError::StagingFull(_)) => StatusCode::SERVICE_UNAVAILABLE,
             PostError::Event(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PostError::Invalid(_) => StatusCode::BAD_REQUEST,
             PostError::CreateStream(CreateStreamError::StreamNameValidation(_)) => {
@@ -172,35 +1275,46 @@ impl actix_web::ResponseError for PostError {
             }
             PostError::CreateStream(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PostError::StreamNotFound(_) => StatusCode::NOT_FOUND,
+            PostError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            PostError::IdempotencyKeyInProgress(_) => StatusCode::CONFLICT,
         }
     }
 
     fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
-        actix_web::HttpResponse::build(self.status_code())
-            .insert_header(ContentType::plaintext())
-            .body(self.to_string())
+        let mut response = actix_web::HttpResponse::build(self.status_code());
+        response.insert_header(ContentType::plaintext());
+        if let PostError::RateLimited(throttled) = self {
+            response.insert_header((header::RETRY_AFTER, throttled.retry_after_secs));
+        }
+        response.body(self.to_string())
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::{collections::HashMap, sync::Arc};
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::Arc,
+    };
 
     use actix_web::test::TestRequest;
+    use actix_web::{http::StatusCode, ResponseError};
+    use arrow_array::cast::AsArray;
     use arrow_array::{
         types::Int64Type, ArrayRef, Float64Array, Int64Array, ListArray, StringArray,
     };
-    use arrow_schema::{DataType, Field};
+    use arrow_schema::{DataType, Field, Schema};
     use bytes::Bytes;
     use serde_json::json;
 
     use crate::{
-        event,
+        event::{self, nested::NestedObjects},
         handlers::{PREFIX_META, PREFIX_TAGS},
+        metadata::STREAM_INFO,
     };
 
-    use super::into_event_batch;
+    use super::{dead_letter_headers, ingest_loki_push, into_event_batch};
 
     trait TestExt {
         fn as_int64_arr(&self) -> &Int64Array;
@@ -243,6 +1357,9 @@ mod tests {
             req,
             Bytes::from(serde_json::to_vec(&json).unwrap()),
             HashMap::default(),
+            &[],
+            None,
+            NestedObjects::default(),
         )
         .unwrap();
 
@@ -261,17 +1378,68 @@ mod tests {
             rb.column_by_name("c").unwrap().as_float64_arr(),
             &Float64Array::from_iter([4.23])
         );
+        let tags = rb.column_by_name(event::DEFAULT_TAGS_KEY).unwrap().as_map();
+        assert_eq!(
+            tags.keys().as_utf8_arr(),
+            &StringArray::from_iter_values(["a"])
+        );
+        assert_eq!(
+            tags.values().as_utf8_arr(),
+            &StringArray::from_iter_values(["tag1"])
+        );
+        let metadata = rb
+            .column_by_name(event::DEFAULT_METADATA_KEY)
+            .unwrap()
+            .as_map();
+        assert_eq!(
+            metadata.keys().as_utf8_arr(),
+            &StringArray::from_iter_values(["c"])
+        );
+        assert_eq!(
+            metadata.values().as_utf8_arr(),
+            &StringArray::from_iter_values(["meta1"])
+        );
+    }
+
+    #[test]
+    fn tags_of_stream_with_string_tags_into_rb() {
+        let json = json!({ "a": 1 });
+
+        let req = TestRequest::default()
+            .append_header((PREFIX_TAGS.to_string() + "A", "tag1"))
+            .append_header((PREFIX_TAGS.to_string() + "B", "tag2"))
+            .to_http_request();
+
+        let schema = fields_to_map(
+            [
+                Field::new("a", DataType::Int64, true),
+                Field::new(event::DEFAULT_TAGS_KEY, DataType::Utf8, true),
+                Field::new(event::DEFAULT_METADATA_KEY, DataType::Utf8, true),
+            ]
+            .into_iter(),
+        );
+
+        let (_, rb, _) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            schema,
+            &[],
+            None,
+            NestedObjects::default(),
+        )
+        .unwrap();
+
         assert_eq!(
             rb.column_by_name(event::DEFAULT_TAGS_KEY)
                 .unwrap()
                 .as_utf8_arr(),
-            &StringArray::from_iter_values(["a=tag1"])
+            &StringArray::from_iter_values(["a=tag1^b=tag2"])
         );
         assert_eq!(
             rb.column_by_name(event::DEFAULT_METADATA_KEY)
                 .unwrap()
                 .as_utf8_arr(),
-            &StringArray::from_iter_values(["c=meta1"])
+            &StringArray::from_iter_values([""])
         );
     }
 
@@ -289,6 +1457,9 @@ mod tests {
             req,
             Bytes::from(serde_json::to_vec(&json).unwrap()),
             HashMap::default(),
+            &[],
+            None,
+            NestedObjects::default(),
         )
         .unwrap();
 
@@ -322,8 +1493,15 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) =
-            into_event_batch(req, Bytes::from(serde_json::to_vec(&json).unwrap()), schema).unwrap();
+        let (_, rb, _) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            schema,
+            &[],
+            None,
+            NestedObjects::default(),
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 5);
@@ -341,7 +1519,38 @@ mod tests {
     fn basic_object_schema_mismatch() {
         let json = json!({
             "a": 1,
-            "b": 1, // type mismatch
+            "b": [1], // type mismatch
+        });
+
+        let schema = fields_to_map(
+            [
+                Field::new("a", DataType::Int64, true),
+                Field::new("b", DataType::Utf8, true),
+                Field::new("c", DataType::Float64, true),
+            ]
+            .into_iter(),
+        );
+
+        let req = TestRequest::default().to_http_request();
+
+        assert!(into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            schema,
+            &[],
+            None,
+            NestedObjects::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn basic_object_widened_into_rb() {
+        let json = json!({
+            "a": 1.5,
+            "b": 1,
+            "c": "x",
+            "d": 2
         });
 
         let schema = fields_to_map(
@@ -349,15 +1558,39 @@ mod tests {
                 Field::new("a", DataType::Int64, true),
                 Field::new("b", DataType::Utf8, true),
                 Field::new("c", DataType::Float64, true),
+                Field::new("d", DataType::Float64, true),
             ]
             .into_iter(),
         );
 
         let req = TestRequest::default().to_http_request();
 
-        assert!(
-            into_event_batch(req, Bytes::from(serde_json::to_vec(&json).unwrap()), schema,)
-                .is_err()
+        let (_, rb, is_first) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            schema,
+            &[],
+            None,
+            NestedObjects::default(),
+        )
+        .unwrap();
+
+        assert!(is_first);
+        assert_eq!(
+            rb.column_by_name("a").unwrap().as_float64_arr(),
+            &Float64Array::from_iter([1.5])
+        );
+        assert_eq!(
+            rb.column_by_name("b").unwrap().as_utf8_arr(),
+            &StringArray::from_iter_values(["1"])
+        );
+        assert_eq!(
+            rb.column_by_name("c").unwrap().as_utf8_arr(),
+            &StringArray::from_iter_values(["x"])
+        );
+        assert_eq!(
+            rb.column_by_name("d").unwrap().as_float64_arr(),
+            &Float64Array::from_iter([2.0])
         );
     }
 
@@ -376,8 +1609,15 @@ mod tests {
 
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) =
-            into_event_batch(req, Bytes::from(serde_json::to_vec(&json).unwrap()), schema).unwrap();
+        let (_, rb, _) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            schema,
+            &[],
+            None,
+            NestedObjects::default(),
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 3);
@@ -393,6 +1633,9 @@ mod tests {
             req,
             Bytes::from(serde_json::to_vec(&json).unwrap()),
             HashMap::default(),
+            &[],
+            None,
+            NestedObjects::default(),
         )
         .is_err())
     }
@@ -421,6 +1664,9 @@ mod tests {
             req,
             Bytes::from(serde_json::to_vec(&json).unwrap()),
             HashMap::default(),
+            &[],
+            None,
+            NestedObjects::default(),
         )
         .unwrap();
 
@@ -474,6 +1720,9 @@ mod tests {
             req,
             Bytes::from(serde_json::to_vec(&json).unwrap()),
             HashMap::default(),
+            &[],
+            None,
+            NestedObjects::default(),
         )
         .unwrap();
 
@@ -523,8 +1772,15 @@ mod tests {
         );
         let req = TestRequest::default().to_http_request();
 
-        let (_, rb, _) =
-            into_event_batch(req, Bytes::from(serde_json::to_vec(&json).unwrap()), schema).unwrap();
+        let (_, rb, _) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            schema,
+            &[],
+            None,
+            NestedObjects::default(),
+        )
+        .unwrap();
 
         assert_eq!(rb.num_rows(), 3);
         assert_eq!(rb.num_columns(), 6);
@@ -568,6 +1824,9 @@ mod tests {
             req,
             Bytes::from(serde_json::to_vec(&json).unwrap()),
             HashMap::default(),
+            &[],
+            None,
+            NestedObjects::default(),
         )
         .unwrap();
 
@@ -594,7 +1853,7 @@ mod tests {
             {
                 "a": 1,
                 "b": "hello",
-                "c": 1
+                "c": [1]
             },
             {
                 "a": 1,
@@ -614,10 +1873,15 @@ mod tests {
             .into_iter(),
         );
 
-        assert!(
-            into_event_batch(req, Bytes::from(serde_json::to_vec(&json).unwrap()), schema,)
-                .is_err()
-        );
+        assert!(into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            schema,
+            &[],
+            None,
+            NestedObjects::default(),
+        )
+        .is_err());
     }
 
     #[test]
@@ -649,6 +1913,9 @@ mod tests {
             req,
             Bytes::from(serde_json::to_vec(&json).unwrap()),
             HashMap::default(),
+            &[],
+            None,
+            NestedObjects::default(),
         )
         .unwrap();
 
@@ -689,4 +1956,345 @@ mod tests {
             &ListArray::from_iter_primitive::<Int64Type, _, _>(c_b)
         );
     }
+
+    #[test]
+    fn partition_rejects_mismatched_records() {
+        let schema = fields_to_map(
+            [
+                Field::new("a", DataType::Int64, true),
+                Field::new("b", DataType::Utf8, true),
+            ]
+            .into_iter(),
+        );
+
+        let records = vec![
+            json!({"a": 1, "b": "hello"}),
+            json!({"a": ["one"]}),
+            json!({"a": 2, "c": 4.23}),
+            json!({"c": ["text"]}),
+            json!({"p_tags": "x"}),
+            json!([1, 2]),
+            json!({"b": null, "c": 1.5}),
+        ];
+
+        let (accepted, rejected) =
+            event::format::json::partition_records(&schema, &NestedObjects::default(), records);
+        assert_eq!(accepted.len(), 3);
+        assert_eq!(
+            rejected.iter().map(|r| r.index).collect::<Vec<_>>(),
+            vec![1, 3, 4, 5]
+        );
+        assert!(rejected[0].reason.contains("field a"));
+        assert!(rejected[1].reason.contains("field c"));
+    }
+
+    #[test]
+    fn partition_widens_fields() {
+        let schema = fields_to_map([Field::new("a", DataType::Int64, true)].into_iter());
+
+        let records = vec![
+            json!({"a": 1}),
+            json!({"a": 1.5}),
+            json!({"a": "one"}),
+            json!({"a": 2}),
+        ];
+
+        let (accepted, rejected) =
+            event::format::json::partition_records(&schema, &NestedObjects::default(), records);
+        assert_eq!(accepted.len(), 4);
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn partition_accepted_records_into_rb() {
+        let records = vec![
+            json!({"a": 1, "b": {"c": "nested"}}),
+            json!({"a": [true]}),
+            json!({"a": 2}),
+        ];
+
+        let (accepted, rejected) = event::format::json::partition_records(
+            &HashMap::default(),
+            &NestedObjects::default(),
+            records,
+        );
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].index, 1);
+
+        let req = TestRequest::default().to_http_request();
+        let (_, rb, _) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&accepted).unwrap()),
+            HashMap::default(),
+            &[],
+            None,
+            NestedObjects::default(),
+        )
+        .unwrap();
+
+        assert_eq!(rb.num_rows(), 2);
+        assert_eq!(
+            rb.column_by_name("a").unwrap().as_int64_arr(),
+            &Int64Array::from_iter([1, 2])
+        );
+        assert_eq!(
+            rb.column_by_name("b_c").unwrap().as_utf8_arr(),
+            &StringArray::from(vec![Some("nested"), None])
+        );
+    }
+
+    #[test]
+    fn nested_objects_into_rb() {
+        let nested: NestedObjects = serde_json::from_value(json!({
+            "fields": { "request": "struct", "headers": "map" }
+        }))
+        .unwrap();
+        let json = json!([
+            {
+                "request": { "method": "GET", "size": 10 },
+                "headers": { "host": "a", "x-retry": 1 },
+                "user": { "id": 1 }
+            },
+            {
+                "request": { "method": "POST" },
+                "headers": { "accept": "*/*" }
+            }
+        ]);
+
+        let req = TestRequest::default().to_http_request();
+        let (_, rb, _) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            HashMap::default(),
+            &[],
+            None,
+            nested.clone(),
+        )
+        .unwrap();
+
+        assert_eq!(rb.num_rows(), 2);
+        assert!(matches!(
+            rb.schema().field_with_name("request").unwrap().data_type(),
+            DataType::Struct(fields) if fields.len() == 2
+        ));
+        assert_eq!(
+            rb.schema().field_with_name("headers").unwrap().data_type(),
+            &event::nested::map_type()
+        );
+        assert!(rb.column_by_name("user_id").is_some());
+
+        // new keys are added to a struct, keys keep their type
+        let schema = fields_to_map(
+            rb.schema()
+                .fields()
+                .iter()
+                .map(|field| field.as_ref().clone()),
+        );
+        let req = TestRequest::default().to_http_request();
+        let (_, rb, is_first) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json!({"request": {"path": "/"}})).unwrap()),
+            schema.clone(),
+            &[],
+            None,
+            nested.clone(),
+        )
+        .unwrap();
+        assert!(is_first);
+        assert!(matches!(
+            rb.schema().field_with_name("request").unwrap().data_type(),
+            DataType::Struct(fields) if fields.len() == 3
+        ));
+        let req = TestRequest::default().to_http_request();
+        assert!(into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json!({"request": {"size": "big"}})).unwrap()),
+            schema.clone(),
+            &[],
+            None,
+            nested.clone(),
+        )
+        .is_err());
+
+        let req = TestRequest::default().to_http_request();
+        let (_, rb, _) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json!({"headers": {"new": "key"}})).unwrap()),
+            schema,
+            &[],
+            None,
+            nested,
+        )
+        .unwrap();
+        assert_eq!(rb.num_rows(), 1);
+    }
+
+    #[test]
+    fn arrays_of_nested_objects_into_rb() {
+        let nested: NestedObjects = serde_json::from_value(json!({
+            "fields": { "items": "struct", "labels": "map" }
+        }))
+        .unwrap();
+        let json = json!({
+            "items": [{ "k": 1 }, { "k": 2 }],
+            "labels": [{ "k": 1 }, { "k": 2 }]
+        });
+
+        let req = TestRequest::default().to_http_request();
+        let (_, rb, _) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json).unwrap()),
+            HashMap::default(),
+            &[],
+            None,
+            nested.clone(),
+        )
+        .unwrap();
+
+        assert_eq!(rb.num_columns(), 5);
+        let items = rb.schema().field_with_name("items").unwrap().clone();
+        assert!(matches!(
+            items.data_type(),
+            DataType::List(item) if matches!(item.data_type(), DataType::Struct(fields) if fields.len() == 1)
+        ));
+        assert!(matches!(
+            rb.schema().field_with_name("labels").unwrap().data_type(),
+            DataType::List(item) if item.data_type() == &event::nested::map_type()
+        ));
+        let column = rb.column_by_name("items").unwrap().as_list::<i32>();
+        assert_eq!(column.value(0).len(), 2);
+
+        // the structs of the list get new keys, keys keep their type
+        let schema = fields_to_map(
+            rb.schema()
+                .fields()
+                .iter()
+                .map(|field| field.as_ref().clone()),
+        );
+        let req = TestRequest::default().to_http_request();
+        let (_, rb, is_first) = into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json!({"items": [{"k": 3, "v": "x"}]})).unwrap()),
+            schema.clone(),
+            &[],
+            None,
+            nested.clone(),
+        )
+        .unwrap();
+        assert!(is_first);
+        assert!(matches!(
+            rb.schema().field_with_name("items").unwrap().data_type(),
+            DataType::List(item) if matches!(item.data_type(), DataType::Struct(fields) if fields.len() == 2)
+        ));
+        let req = TestRequest::default().to_http_request();
+        assert!(into_event_batch(
+            req,
+            Bytes::from(serde_json::to_vec(&json!({"items": [{"k": "x"}]})).unwrap()),
+            schema,
+            &[],
+            None,
+            nested,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn arrow_batch_into_rb() {
+        use crate::event::format::EventFormat;
+
+        let schema = fields_to_map(
+            [
+                Field::new("a", DataType::Int64, true),
+                Field::new("z", DataType::Utf8, true),
+            ]
+            .into_iter(),
+        );
+        let rb = arrow_array::RecordBatch::try_from_iter([
+            ("b", Arc::new(StringArray::from(vec!["x", "y"])) as ArrayRef),
+            ("a", Arc::new(Int64Array::from(vec![1, 2])) as ArrayRef),
+        ])
+        .unwrap();
+
+        let event = event::format::arrow::Event {
+            rb,
+            tags: BTreeMap::default(),
+            metadata: BTreeMap::default(),
+        };
+        let (rb, is_first) = event.into_recordbatch(schema).unwrap();
+
+        assert!(is_first);
+        assert_eq!(rb.num_rows(), 2);
+        let names: Vec<String> = rb
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().to_owned())
+            .collect();
+        assert_eq!(names, ["p_timestamp", "a", "b", "p_tags", "p_metadata"]);
+        assert_eq!(
+            rb.column_by_name("a").unwrap().as_int64_arr(),
+            &Int64Array::from_iter([1, 2])
+        );
+    }
+
+    #[test]
+    fn arrow_batch_type_mismatch() {
+        use crate::event::format::EventFormat;
+
+        let schema = fields_to_map([Field::new("a", DataType::Utf8, true)].into_iter());
+        let rb = arrow_array::RecordBatch::try_from_iter([(
+            "a",
+            Arc::new(Int64Array::from(vec![1])) as ArrayRef,
+        )])
+        .unwrap();
+
+        let event = event::format::arrow::Event {
+            rb,
+            tags: BTreeMap::default(),
+            metadata: BTreeMap::default(),
+        };
+        assert!(event.into_recordbatch(schema).is_err());
+    }
+
+    #[test]
+    fn dead_letter_headers_leave_out_credentials() {
+        let req = TestRequest::default()
+            .insert_header(("content-type", "application/json"))
+            .insert_header(("authorization", "Basic YWRtaW46YWRtaW4="))
+            .insert_header(("cookie", "session=abc"))
+            .insert_header(("x-amz-firehose-access-key", "secret"))
+            .insert_header(("x-p-stream", "app"))
+            .insert_header((PREFIX_TAGS.to_string() + "env", "prod"))
+            .to_http_request();
+
+        assert_eq!(
+            dead_letter_headers(&req),
+            BTreeMap::from([
+                ("content-type".to_owned(), "application/json".to_owned()),
+                ("x-p-stream".to_owned(), "app".to_owned()),
+                ("x-p-tag-env".to_owned(), "prod".to_owned()),
+            ])
+        );
+    }
+
+    #[actix_web::test]
+    async fn loki_push_into_stream_without_time_partition_is_rejected() {
+        STREAM_INFO.add_stream("loki_existing".to_owned(), &Schema::empty(), None, None);
+        let req = TestRequest::default()
+            .insert_header(("content-type", "application/json"))
+            .insert_header(("x-p-stream", "loki_existing"))
+            .to_http_request();
+        let body = json!({
+            "streams": [{
+                "stream": { "job": "app" },
+                "values": [["1705026780451000000", "order placed"]]
+            }]
+        });
+
+        let err = ingest_loki_push(req, Bytes::from(body.to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        assert!(err.to_string().contains("no time partition on timestamp"));
+    }
 }
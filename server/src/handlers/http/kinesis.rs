@@ -18,10 +18,19 @@
 
 use base64::{engine::general_purpose::STANDARD, Engine as _};
 use bytes::Bytes;
+use chrono::Utc;
+use flate2::read::MultiGzDecoder;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::BTreeMap;
-use std::str;
+use std::io::Read;
+
+// first bytes of gzip compressed data
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// column that holds the data of records that are not JSON objects
+const MESSAGE_KEY: &str = "message";
+// upper bound for the decompressed data of a single record, Firehose records are at most 1000 KiB
+const MAX_RECORD_SIZE: usize = 4 * 1024 * 1024;
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Message {
@@ -37,6 +46,34 @@ struct Data {
     data: String,
 }
 
+// Body of the response that Firehose expects from an HTTP endpoint, errorMessage is set on failure.
+// https://docs.aws.amazon.com/firehose/latest/dev/httpdeliveryrequestresponse.html
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FirehoseResponse {
+    request_id: String,
+    timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_message: Option<String>,
+}
+
+impl FirehoseResponse {
+    pub fn ack(request_id: &str) -> Self {
+        Self {
+            request_id: request_id.to_owned(),
+            timestamp: Utc::now().timestamp_millis(),
+            error_message: None,
+        }
+    }
+
+    pub fn error(request_id: &str, error_message: String) -> Self {
+        Self {
+            error_message: Some(error_message),
+            ..Self::ack(request_id)
+        }
+    }
+}
+
 // Flatten Kinesis logs is used to flatten the Kinesis logs into a queryable JSON format.
 // Kinesis logs are in the format
 // {
@@ -49,6 +86,8 @@ struct Data {
 //     ]
 // }
 // The data field is base64 encoded JSON (there can be multiple data fields), and there is a requestId and timestamp field.
+// The data may also be gzip compressed (e.g. CloudWatch Logs subscriptions) or plain text,
+// text that is not a JSON object is kept in the message field.
 // Kinesis logs are flattened to the following format:
 // {
 //     "CHANGE": 3.16,
@@ -61,18 +100,26 @@ struct Data {
 //     "requestId": "b858288a-f5d8-4181-a746-3f3dd716be8a",
 //     "timestamp": "1704964113659"
 // }
-pub fn flatten_kinesis_logs(body: &Bytes) -> Vec<BTreeMap<String, Value>> {
-    let body_str = std::str::from_utf8(body).unwrap();
-    let message: Message = serde_json::from_str(body_str).unwrap();
+// Records that can not be decoded are logged and skipped, so that one bad record
+// does not make Firehose retry the whole request.
+pub fn flatten_kinesis_logs(
+    body: &Bytes,
+) -> Result<Vec<BTreeMap<String, Value>>, serde_json::Error> {
+    let message: Message = serde_json::from_slice(body)?;
     let mut vec_kinesis_json: Vec<BTreeMap<String, Value>> = Vec::new();
 
-    for record in message.records.iter() {
-        let bytes = STANDARD.decode(record.data.clone()).unwrap();
-        let json_string: String = String::from_utf8(bytes).unwrap();
-        let json: serde_json::Value = serde_json::from_str(&json_string).unwrap();
-        let mut kinesis_json: BTreeMap<String, Value> = match serde_json::from_value(json) {
-            Ok(value) => value,
-            Err(error) => panic!("Failed to deserialize JSON: {}", error),
+    for (index, record) in message.records.iter().enumerate() {
+        let mut kinesis_json = match decode_record(&record.data) {
+            Ok(json) => json,
+            Err(reason) => {
+                log::warn!(
+                    "Skipping record {} of Firehose request {}, {}",
+                    index,
+                    message.request_id,
+                    reason
+                );
+                continue;
+            }
         };
 
         kinesis_json.insert(
@@ -86,5 +133,98 @@ pub fn flatten_kinesis_logs(body: &Bytes) -> Vec<BTreeMap<String, Value>> {
 
         vec_kinesis_json.push(kinesis_json);
     }
-    vec_kinesis_json
+    Ok(vec_kinesis_json)
+}
+
+fn decode_record(data: &str) -> Result<BTreeMap<String, Value>, String> {
+    let mut bytes = STANDARD
+        .decode(data)
+        .map_err(|err| format!("data is not valid base64, {}", err))?;
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        MultiGzDecoder::new(bytes.as_slice())
+            .take(MAX_RECORD_SIZE as u64 + 1)
+            .read_to_end(&mut decompressed)
+            .map_err(|err| format!("data is not valid gzip, {}", err))?;
+        if decompressed.len() > MAX_RECORD_SIZE {
+            return Err(format!(
+                "decompressed data exceeds the size limit of {} bytes",
+                MAX_RECORD_SIZE
+            ));
+        }
+        bytes = decompressed;
+    }
+    let text =
+        String::from_utf8(bytes).map_err(|err| format!("data is not valid UTF-8, {}", err))?;
+
+    match serde_json::from_str(&text) {
+        Ok(Value::Object(map)) => Ok(map.into_iter().collect()),
+        _ => Ok(BTreeMap::from([(
+            MESSAGE_KEY.to_owned(),
+            Value::String(text.trim_end().to_owned()),
+        )])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use bytes::Bytes;
+    use flate2::{write::GzEncoder, Compression};
+    use serde_json::{json, Value};
+
+    use super::{decode_record, flatten_kinesis_logs, MAX_RECORD_SIZE};
+
+    #[test]
+    fn records_are_decoded_or_skipped() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(br#"{"level":"error"}"#).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let body = json!({
+            "requestId": "ed4acda5-034f-9f42-bba1-f29aea6d7d8f",
+            "timestamp": 1578090901599u64,
+            "records": [
+                { "data": STANDARD.encode(r#"{"level":"info"}"#) },
+                { "data": STANDARD.encode(gzipped) },
+                { "data": STANDARD.encode("plain text line\n") },
+                { "data": "not base64!" },
+                { "data": STANDARD.encode([0xff, 0xfe]) }
+            ]
+        });
+        let records = flatten_kinesis_logs(&Bytes::from(body.to_string())).unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["level"], json!("info"));
+        assert_eq!(records[1]["level"], json!("error"));
+        assert_eq!(records[2]["message"], json!("plain text line"));
+        assert_eq!(
+            records[2]["requestId"],
+            json!("ed4acda5-034f-9f42-bba1-f29aea6d7d8f")
+        );
+        assert_eq!(
+            records[2]["timestamp"],
+            Value::String("1578090901599".into())
+        );
+    }
+
+    #[test]
+    fn oversized_gzip_record_is_skipped() {
+        let gzip = |len: usize| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&vec![b'a'; len]).unwrap();
+            STANDARD.encode(encoder.finish().unwrap())
+        };
+
+        assert!(decode_record(&gzip(MAX_RECORD_SIZE)).is_ok());
+        assert!(decode_record(&gzip(MAX_RECORD_SIZE + 1)).is_err());
+    }
+
+    #[test]
+    fn malformed_request_is_err() {
+        assert!(flatten_kinesis_logs(&Bytes::from_static(b"{\"records\": 1}")).is_err());
+        assert!(flatten_kinesis_logs(&Bytes::from_static(b"\xff")).is_err());
+    }
 }
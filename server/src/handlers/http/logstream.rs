@@ -20,12 +20,25 @@ use std::fs;
 
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpRequest, Responder};
+use arrow_schema::{DataType, Schema};
+use bytes::Bytes;
 use chrono::Utc;
 use serde_json::Value;
 
 use crate::alerts::Alerts;
+use crate::event::dead_letter::DeadLetter;
+use crate::event::dedup::{Dedup, DEDUP};
+use crate::event::durability::Durability;
+use crate::event::nested::{self, NestedObjects, ObjectMode};
+use crate::event::processor::Processor;
+use crate::event::static_schema::StaticSchema;
+use crate::event::text_parser::TextParser;
+use crate::event::time_partition::TimePartition;
+use crate::handlers::{TIME_PARTITION_FORMAT_KEY, TIME_PARTITION_KEY};
 use crate::metadata::STREAM_INFO;
 use crate::option::CONFIG;
+use crate::rate_limit::{RateLimit, RATE_LIMITER};
+use crate::storage::import::{self, ImportRequest};
 use crate::storage::retention::{self, Retention};
 use crate::storage::{LogStream, StorageDir};
 use crate::{event, stats};
@@ -44,6 +57,8 @@ pub async fn delete(req: HttpRequest) -> Result<impl Responder, StreamError> {
     objectstore.delete_stream(&stream_name).await?;
     metadata::STREAM_INFO.delete_stream(&stream_name);
     event::STREAM_WRITERS.delete_stream(&stream_name);
+    RATE_LIMITER.reset(&stream_name);
+    DEDUP.reset(&stream_name);
     stats::delete_stats(&stream_name, "json").unwrap_or_else(|e| {
         log::warn!("failed to delete stats for stream {}: {:?}", stream_name, e)
     });
@@ -76,6 +91,20 @@ pub async fn schema(req: HttpRequest) -> Result<impl Responder, StreamError> {
     Ok((web::Json(schema), StatusCode::OK))
 }
 
+// versions of the schema of the stream, one for every time fields were widened
+pub async fn get_schema_history(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+    let history = CONFIG
+        .storage()
+        .get_object_store()
+        .get_schema_history(&stream_name)
+        .await?;
+    Ok((web::Json(history), StatusCode::OK))
+}
+
 pub async fn get_alert(req: HttpRequest) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
 
@@ -109,7 +138,9 @@ pub async fn get_alert(req: HttpRequest) -> Result<impl Responder, StreamError>
     Ok((web::Json(alerts), StatusCode::OK))
 }
 
-pub async fn put_stream(req: HttpRequest) -> Result<impl Responder, StreamError> {
+// Handler for PUT /logstream/{logstream}
+// an optional body declares the static schema of the stream
+pub async fn put_stream(req: HttpRequest, body: Bytes) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
 
     if metadata::STREAM_INFO.stream_exists(&stream_name) {
@@ -121,7 +152,18 @@ pub async fn put_stream(req: HttpRequest) -> Result<impl Responder, StreamError>
             status: StatusCode::BAD_REQUEST,
         });
     } else {
-        create_stream(stream_name).await?;
+        let time_partition = time_partition_from_headers(&req)?;
+        let static_schema = if body.is_empty() {
+            None
+        } else {
+            let static_schema =
+                serde_json::from_slice(&body).map_err(|err| StreamError::Custom {
+                    msg: format!("invalid static schema, {err}"),
+                    status: StatusCode::BAD_REQUEST,
+                })?;
+            Some(static_schema)
+        };
+        create_stream(stream_name, time_partition, static_schema).await?;
     }
 
     Ok(("log stream created", StatusCode::OK))
@@ -258,6 +300,345 @@ pub async fn put_enable_cache(
     ))
 }
 
+pub async fn get_processors(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let processors = STREAM_INFO.processors(&stream_name)?;
+    Ok((web::Json(processors), StatusCode::OK))
+}
+
+pub async fn put_processors(
+    req: HttpRequest,
+    body: web::Json<serde_json::Value>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let processors: Vec<Processor> =
+        serde_json::from_value(body.into_inner()).map_err(StreamError::InvalidProcessorConfig)?;
+
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.processors = processors.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_processors(&stream_name, processors)?;
+    Ok((
+        format!("set processors for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_text_parser(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let text_parser = STREAM_INFO.text_parser(&stream_name)?;
+    Ok((web::Json(text_parser), StatusCode::OK))
+}
+
+// a null body removes the parser of the stream
+pub async fn put_text_parser(
+    req: HttpRequest,
+    body: web::Json<serde_json::Value>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let text_parser: Option<TextParser> =
+        serde_json::from_value(body.into_inner()).map_err(StreamError::InvalidTextParserConfig)?;
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.text_parser = text_parser.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_text_parser(&stream_name, text_parser)?;
+    Ok((
+        format!("set text parser for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_rate_limit(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let rate_limit = STREAM_INFO.rate_limit(&stream_name)?;
+    Ok((web::Json(rate_limit), StatusCode::OK))
+}
+
+// a null body removes the limits of the stream
+pub async fn put_rate_limit(
+    req: HttpRequest,
+    body: web::Json<serde_json::Value>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let rate_limit: Option<RateLimit> =
+        serde_json::from_value(body.into_inner()).map_err(StreamError::InvalidRateLimitConfig)?;
+
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.rate_limit = rate_limit.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_rate_limit(&stream_name, rate_limit)?;
+    RATE_LIMITER.reset(&stream_name);
+    Ok((
+        format!("set rate limit for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_dedup(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let dedup = STREAM_INFO.dedup(&stream_name)?;
+    Ok((web::Json(dedup), StatusCode::OK))
+}
+
+// a null body turns off deduplication of records for the stream
+pub async fn put_dedup(
+    req: HttpRequest,
+    body: web::Json<serde_json::Value>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let dedup: Option<Dedup> =
+        serde_json::from_value(body.into_inner()).map_err(StreamError::InvalidDedupConfig)?;
+    if let Some(dedup) = &dedup {
+        dedup.validate().map_err(|msg| StreamError::Custom {
+            msg,
+            status: StatusCode::BAD_REQUEST,
+        })?;
+    }
+
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.dedup = dedup.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_dedup(&stream_name, dedup)?;
+    DEDUP.reset(&stream_name);
+    Ok((
+        format!("set dedup for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_durability(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let durability = STREAM_INFO.durability(&stream_name)?;
+    Ok((web::Json(durability), StatusCode::OK))
+}
+
+// a null body makes the stream use the durability level of the server
+pub async fn put_durability(
+    req: HttpRequest,
+    body: web::Json<serde_json::Value>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let durability: Option<Durability> =
+        serde_json::from_value(body.into_inner()).map_err(StreamError::InvalidDurabilityConfig)?;
+
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.durability = durability;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_durability(&stream_name, durability)?;
+    Ok((
+        format!("set durability for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_nested_objects(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let nested_objects = STREAM_INFO.nested_objects(&stream_name)?;
+    Ok((web::Json(nested_objects), StatusCode::OK))
+}
+
+// a null body makes the stream flatten all nested objects again
+pub async fn put_nested_objects(
+    req: HttpRequest,
+    body: web::Json<serde_json::Value>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let nested_objects: Option<NestedObjects> = serde_json::from_value(body.into_inner())
+        .map_err(StreamError::InvalidNestedObjectsConfig)?;
+    if let Some(nested_objects) = &nested_objects {
+        nested_objects
+            .validate()
+            .map_err(|msg| StreamError::Custom {
+                msg,
+                status: StatusCode::BAD_REQUEST,
+            })?;
+    }
+
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    // a column that already holds structs can not start holding maps, or the other way round
+    let schema = STREAM_INFO.schema(&stream_name)?;
+    let mode = |field: &str| {
+        nested_objects
+            .as_ref()
+            .map_or(ObjectMode::Flatten, |nested_objects| {
+                nested_objects.mode(field)
+            })
+    };
+    for field in schema.fields() {
+        let conflict = match field.data_type() {
+            DataType::Struct(_) => mode(field.name()) == ObjectMode::Map,
+            data_type => {
+                *data_type == nested::map_type() && mode(field.name()) == ObjectMode::Struct
+            }
+        };
+        if conflict {
+            return Err(StreamError::Custom {
+                msg: format!(
+                    "field {} of log stream {stream_name} already has type {}",
+                    field.name(),
+                    field.data_type()
+                ),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.nested_objects = nested_objects.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_nested_objects(&stream_name, nested_objects)?;
+    Ok((
+        format!("set nested objects for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_dead_letter(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let dead_letter = STREAM_INFO.dead_letter(&stream_name)?;
+    Ok((web::Json(dead_letter), StatusCode::OK))
+}
+
+// a null body stops keeping the rejected events of the stream,
+// the dead letter stream is created if it does not exist
+pub async fn put_dead_letter(
+    req: HttpRequest,
+    body: web::Json<serde_json::Value>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let dead_letter: Option<DeadLetter> =
+        serde_json::from_value(body.into_inner()).map_err(StreamError::InvalidDeadLetterConfig)?;
+    if let Some(dead_letter) = &dead_letter {
+        dead_letter
+            .validate(&stream_name)
+            .map_err(|msg| StreamError::Custom {
+                msg,
+                status: StatusCode::BAD_REQUEST,
+            })?;
+    }
+
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    if let Some(dead_letter) = &dead_letter {
+        if !STREAM_INFO.stream_exists(&dead_letter.stream) {
+            create_stream(dead_letter.stream.clone(), None, None).await?;
+        }
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    let mut stream_metadata = storage.get_stream_metadata(&stream_name).await?;
+    stream_metadata.dead_letter = dead_letter.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_dead_letter(&stream_name, dead_letter)?;
+    Ok((
+        format!("set dead letter stream for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+// starts an import of parquet files in the import directory of the server,
+// responds with the id of the import job
+pub async fn import(
+    req: HttpRequest,
+    body: web::Json<serde_json::Value>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let request: ImportRequest =
+        serde_json::from_value(body.into_inner()).map_err(StreamError::InvalidImportRequest)?;
+    request.validate().map_err(|msg| StreamError::Custom {
+        msg,
+        status: StatusCode::BAD_REQUEST,
+    })?;
+
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+    if !STREAM_INFO.processors(&stream_name)?.is_empty() {
+        return Err(StreamError::Custom {
+            msg: format!(
+                "log stream {stream_name} has processors, which are not applied to imported files"
+            ),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    let Some(root) = import::import_dir() else {
+        return Err(StreamError::Custom {
+            msg: "import is disabled, the server has no import directory".to_owned(),
+            status: StatusCode::BAD_REQUEST,
+        });
+    };
+    let id = import::start(&stream_name, root, request);
+    Ok((
+        web::Json(serde_json::json!({ "id": id })),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+pub async fn get_import(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let id = req.match_info().get("id").unwrap();
+    match import::job(&stream_name, id) {
+        Some(job) => Ok((web::Json(job), StatusCode::OK)),
+        None => Err(StreamError::Custom {
+            msg: format!("import job {id} not found for log stream {stream_name}"),
+            status: StatusCode::NOT_FOUND,
+        }),
+    }
+}
+
 pub async fn get_stats(req: HttpRequest) -> Result<impl Responder, StreamError> {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
 
@@ -298,16 +679,82 @@ fn remove_id_from_alerts(value: &mut Value) {
     }
 }
 
-pub async fn create_stream(stream_name: String) -> Result<(), CreateStreamError> {
+// time partition of a new stream is picked from the x-p-time-partition
+// and x-p-time-partition-format headers
+fn time_partition_from_headers(req: &HttpRequest) -> Result<Option<TimePartition>, StreamError> {
+    let header = |key| {
+        req.headers()
+            .get(key)
+            .map(|value| value.to_str().map(str::to_owned))
+            .transpose()
+            .map_err(|_| StreamError::Custom {
+                msg: format!("header {key} has an invalid value"),
+                status: StatusCode::BAD_REQUEST,
+            })
+    };
+
+    let Some(field) = header(TIME_PARTITION_KEY)? else {
+        return Ok(None);
+    };
+    let format = header(TIME_PARTITION_FORMAT_KEY)?;
+    TimePartition::new(field, format)
+        .map(Some)
+        .map_err(|msg| StreamError::Custom {
+            msg,
+            status: StatusCode::BAD_REQUEST,
+        })
+}
+
+pub async fn create_stream(
+    stream_name: String,
+    time_partition: Option<TimePartition>,
+    static_schema: Option<StaticSchema>,
+) -> Result<(), CreateStreamError> {
     // fail to proceed if invalid stream name
     validator::stream_name(&stream_name)?;
 
+    let (schema, static_schema_policy) = match static_schema {
+        Some(static_schema) => {
+            let schema = static_schema
+                .to_arrow_schema()
+                .map_err(CreateStreamError::InvalidStaticSchema)?;
+            if let Some(time_partition) = &time_partition {
+                let valid = schema
+                    .field_with_name(&time_partition.field)
+                    .map_or(false, |field| {
+                        matches!(field.data_type(), DataType::Utf8 | DataType::Timestamp(..))
+                    });
+                if !valid {
+                    return Err(CreateStreamError::InvalidStaticSchema(format!(
+                        "time partition field {} must be a string or datetime field of the schema",
+                        time_partition.field
+                    )));
+                }
+            }
+            (schema, Some(static_schema.policy))
+        }
+        None => (Schema::empty(), None),
+    };
+
     // Proceed to create log stream if it doesn't exist
     let storage = CONFIG.storage().get_object_store();
-    if let Err(err) = storage.create_stream(&stream_name).await {
+    if let Err(err) = storage
+        .create_stream(
+            &stream_name,
+            &schema,
+            time_partition.clone(),
+            static_schema_policy,
+        )
+        .await
+    {
         return Err(CreateStreamError::Storage { stream_name, err });
     }
-    metadata::STREAM_INFO.add_stream(stream_name.to_string());
+    metadata::STREAM_INFO.add_stream(
+        stream_name.to_string(),
+        &schema,
+        time_partition,
+        static_schema_policy,
+    );
 
     Ok(())
 }
@@ -327,6 +774,8 @@ pub mod error {
     pub enum CreateStreamError {
         #[error("Stream name validation failed due to {0}")]
         StreamNameValidation(#[from] StreamNameValidationError),
+        #[error("Invalid static schema: {0}")]
+        InvalidStaticSchema(String),
         #[error("failed to create log stream {stream_name} due to err: {err}")]
         Storage {
             stream_name: String,
@@ -365,6 +814,22 @@ pub mod error {
         InvalidAlertMessage(String, String),
         #[error("failed to set retention configuration due to err: {0}")]
         InvalidRetentionConfig(serde_json::Error),
+        #[error("failed to set processors due to err: {0}")]
+        InvalidProcessorConfig(serde_json::Error),
+        #[error("failed to set text parser due to err: {0}")]
+        InvalidTextParserConfig(serde_json::Error),
+        #[error("failed to set rate limit due to err: {0}")]
+        InvalidRateLimitConfig(serde_json::Error),
+        #[error("failed to set dedup due to err: {0}")]
+        InvalidDedupConfig(serde_json::Error),
+        #[error("failed to set durability due to err: {0}")]
+        InvalidDurabilityConfig(serde_json::Error),
+        #[error("failed to set nested objects due to err: {0}")]
+        InvalidNestedObjectsConfig(serde_json::Error),
+        #[error("failed to set dead letter stream due to err: {0}")]
+        InvalidDeadLetterConfig(serde_json::Error),
+        #[error("invalid import request: {0}")]
+        InvalidImportRequest(serde_json::Error),
         #[error("{msg}")]
         Custom { msg: String, status: StatusCode },
     }
@@ -375,6 +840,9 @@ pub mod error {
                 StreamError::CreateStream(CreateStreamError::StreamNameValidation(_)) => {
                     StatusCode::BAD_REQUEST
                 }
+                StreamError::CreateStream(CreateStreamError::InvalidStaticSchema(_)) => {
+                    StatusCode::BAD_REQUEST
+                }
                 StreamError::CreateStream(CreateStreamError::Storage { .. }) => {
                     StatusCode::INTERNAL_SERVER_ERROR
                 }
@@ -389,6 +857,14 @@ pub mod error {
                 StreamError::InvalidAlert(_) => StatusCode::BAD_REQUEST,
                 StreamError::InvalidAlertMessage(_, _) => StatusCode::BAD_REQUEST,
                 StreamError::InvalidRetentionConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidProcessorConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidTextParserConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidRateLimitConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidDedupConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidDurabilityConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidNestedObjectsConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidDeadLetterConfig(_) => StatusCode::BAD_REQUEST,
+                StreamError::InvalidImportRequest(_) => StatusCode::BAD_REQUEST,
             }
         }
 
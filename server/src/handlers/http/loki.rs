@@ -0,0 +1,332 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use prost::Message;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+const LINE_KEY: &str = "message";
+// time of the entry, streams created by a push take the time of their events from it
+pub const TIMESTAMP_KEY: &str = "timestamp";
+
+// Loki push request as sent by promtail and the docker driver (logproto.PushRequest).
+// The protobuf payload is snappy (block format) compressed.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PushRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub streams: Vec<StreamAdapter>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamAdapter {
+    // label set in prometheus format, e.g. {job="varlogs", host="node-1"}
+    #[prost(string, tag = "1")]
+    pub labels: String,
+    #[prost(message, repeated, tag = "2")]
+    pub entries: Vec<EntryAdapter>,
+    #[prost(uint64, tag = "3")]
+    pub hash: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EntryAdapter {
+    #[prost(message, optional, tag = "1")]
+    pub timestamp: Option<Timestamp>,
+    #[prost(string, tag = "2")]
+    pub line: String,
+    #[prost(message, repeated, tag = "3")]
+    pub structured_metadata: Vec<LabelPairAdapter>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LabelPairAdapter {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+// google.protobuf.Timestamp
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Timestamp {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    #[prost(int32, tag = "2")]
+    pub nanos: i32,
+}
+
+// JSON variant of the push request
+// {
+//     "streams": [{
+//         "stream": { "job": "varlogs" },
+//         "values": [["1705026780451000000", "log line", { "trace_id": "abc" }]]
+//     }]
+// }
+#[derive(Debug, Deserialize)]
+struct JsonPushRequest {
+    streams: Vec<JsonStream>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonStream {
+    #[serde(default)]
+    stream: BTreeMap<String, String>,
+    values: Vec<JsonEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonEntry {
+    Entry(String, String),
+    EntryWithMetadata(String, String, BTreeMap<String, String>),
+}
+
+// entries without a timestamp are taken to be from when the push was received
+pub fn flatten_protobuf_push(
+    body: &Bytes,
+    received_at: DateTime<Utc>,
+) -> Result<Vec<BTreeMap<String, Value>>, anyhow::Error> {
+    let body = snap::raw::Decoder::new()
+        .decompress_vec(body)
+        .map_err(|err| anyhow!("Could not decompress snappy payload, {}", err))?;
+    let request = PushRequest::decode(body.as_slice())?;
+
+    let mut rows = Vec::new();
+    for stream in request.streams {
+        let labels = parse_labels(&stream.labels)?;
+        for entry in stream.entries {
+            let timestamp = match entry.timestamp {
+                Some(ts) => ts.seconds as i128 * 1_000_000_000 + ts.nanos as i128,
+                None => received_at.timestamp_nanos_opt().unwrap_or_default() as i128,
+            };
+            let metadata = entry
+                .structured_metadata
+                .into_iter()
+                .map(|pair| (pair.name, pair.value));
+            rows.push(to_row(&labels, metadata, timestamp, entry.line)?);
+        }
+    }
+
+    Ok(rows)
+}
+
+pub fn flatten_json_push(body: &Bytes) -> Result<Vec<BTreeMap<String, Value>>, anyhow::Error> {
+    let request: JsonPushRequest = serde_json::from_slice(body)?;
+
+    let mut rows = Vec::new();
+    for stream in request.streams {
+        let labels: Vec<(String, String)> = stream.stream.into_iter().collect();
+        for entry in stream.values {
+            let (timestamp, line, metadata) = match entry {
+                JsonEntry::Entry(timestamp, line) => (timestamp, line, BTreeMap::new()),
+                JsonEntry::EntryWithMetadata(timestamp, line, metadata) => {
+                    (timestamp, line, metadata)
+                }
+            };
+            let timestamp: i128 = timestamp
+                .parse()
+                .map_err(|_| anyhow!("Invalid timestamp {}", timestamp))?;
+            rows.push(to_row(&labels, metadata, timestamp, line)?);
+        }
+    }
+
+    Ok(rows)
+}
+
+// Every entry becomes a row with labels and structured metadata as columns
+// { "job": "varlogs", "timestamp": "2024-01-12T02:33:00.451Z", "message": "log line" }
+fn to_row(
+    labels: &[(String, String)],
+    metadata: impl IntoIterator<Item = (String, String)>,
+    timestamp_nanos: i128,
+    line: String,
+) -> Result<BTreeMap<String, Value>, anyhow::Error> {
+    let mut row: BTreeMap<String, Value> = labels
+        .iter()
+        .map(|(key, value)| (key.to_owned(), Value::String(value.to_owned())))
+        .collect();
+    row.extend(
+        metadata
+            .into_iter()
+            .map(|(key, value)| (key, Value::String(value))),
+    );
+
+    let secs = timestamp_nanos.div_euclid(1_000_000_000) as i64;
+    let nsecs = timestamp_nanos.rem_euclid(1_000_000_000) as u32;
+    let timestamp = NaiveDateTime::from_timestamp_opt(secs, nsecs)
+        .ok_or_else(|| anyhow!("Timestamp {} is out of range", timestamp_nanos))?
+        .and_utc();
+    row.insert(
+        TIMESTAMP_KEY.to_owned(),
+        Value::String(timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+    );
+    row.insert(LINE_KEY.to_owned(), Value::String(line));
+
+    Ok(row)
+}
+
+// Parse label set in prometheus text format
+// {job="varlogs", filename="/var/log/\"quoted\".log"}
+fn parse_labels(labels: &str) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let invalid = || anyhow!("Invalid label set {}", labels);
+    let inner = labels
+        .trim()
+        .strip_prefix('{')
+        .and_then(|labels| labels.strip_suffix('}'))
+        .ok_or_else(invalid)?;
+
+    let mut res = Vec::new();
+    let mut chars = inner.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+            name.push(c);
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if name.is_empty() || chars.next() != Some('=') {
+            return Err(invalid());
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next() != Some('"') {
+            return Err(invalid());
+        }
+
+        let mut value = String::new();
+        loop {
+            match chars.next().ok_or_else(invalid)? {
+                '"' => break,
+                '\\' => match chars.next().ok_or_else(invalid)? {
+                    'n' => value.push('\n'),
+                    c => value.push(c),
+                },
+                c => value.push(c),
+            }
+        }
+
+        res.push((name, value));
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use chrono::{TimeZone, Utc};
+    use prost::Message;
+    use serde_json::json;
+
+    use super::{
+        flatten_json_push, flatten_protobuf_push, parse_labels, EntryAdapter, LabelPairAdapter,
+        PushRequest, StreamAdapter, Timestamp,
+    };
+
+    #[test]
+    fn labels_are_parsed() {
+        let labels =
+            parse_labels(r#"{job="varlogs", path="/var/log/\"a\".log",empty=""}"#).unwrap();
+        assert_eq!(
+            labels,
+            vec![
+                ("job".to_owned(), "varlogs".to_owned()),
+                ("path".to_owned(), "/var/log/\"a\".log".to_owned()),
+                ("empty".to_owned(), "".to_owned()),
+            ]
+        );
+        assert!(parse_labels("{}").unwrap().is_empty());
+        assert!(parse_labels(r#"{job="varlogs""#).is_err());
+        assert!(parse_labels(r#"{job=varlogs}"#).is_err());
+    }
+
+    #[test]
+    fn json_push_into_rows() {
+        let body = json!({
+            "streams": [{
+                "stream": { "job": "varlogs", "host": "node-1" },
+                "values": [
+                    ["1705026780451000000", "first line"],
+                    ["1705026780452000000", "second line", { "trace_id": "abc" }]
+                ]
+            }]
+        });
+
+        let rows = flatten_json_push(&Bytes::from(serde_json::to_vec(&body).unwrap())).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["job"], json!("varlogs"));
+        assert_eq!(rows[0]["host"], json!("node-1"));
+        assert_eq!(rows[0]["message"], json!("first line"));
+        assert_eq!(rows[0]["timestamp"], json!("2024-01-12T02:33:00.451Z"));
+        assert_eq!(rows[1]["trace_id"], json!("abc"));
+        assert_eq!(rows[1]["timestamp"], json!("2024-01-12T02:33:00.452Z"));
+    }
+
+    #[test]
+    fn snappy_protobuf_push_into_rows() {
+        let request = PushRequest {
+            streams: vec![StreamAdapter {
+                labels: r#"{job="docker", container="web"}"#.to_owned(),
+                entries: vec![
+                    EntryAdapter {
+                        timestamp: Some(Timestamp {
+                            seconds: 1705026780,
+                            nanos: 451_000_000,
+                        }),
+                        line: "GET /index.html".to_owned(),
+                        structured_metadata: vec![LabelPairAdapter {
+                            name: "pod".to_owned(),
+                            value: "web-0".to_owned(),
+                        }],
+                    },
+                    EntryAdapter {
+                        timestamp: None,
+                        line: "GET /about.html".to_owned(),
+                        structured_metadata: vec![],
+                    },
+                ],
+                hash: 0,
+            }],
+        };
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&request.encode_to_vec())
+            .unwrap();
+
+        let received_at = Utc.with_ymd_and_hms(2024, 1, 12, 2, 34, 0).unwrap();
+        let rows = flatten_protobuf_push(&Bytes::from(body), received_at).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["job"], json!("docker"));
+        assert_eq!(rows[0]["container"], json!("web"));
+        assert_eq!(rows[0]["pod"], json!("web-0"));
+        assert_eq!(rows[0]["message"], json!("GET /index.html"));
+        assert_eq!(rows[0]["timestamp"], json!("2024-01-12T02:33:00.451Z"));
+        assert_eq!(rows[1]["timestamp"], json!("2024-01-12T02:34:00Z"));
+    }
+
+    #[test]
+    fn uncompressed_protobuf_is_err() {
+        assert!(flatten_protobuf_push(&Bytes::from_static(b"\x0a\x02{}"), Utc::now()).is_err());
+    }
+}
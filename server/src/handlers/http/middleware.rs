@@ -21,15 +21,17 @@ use std::future::{ready, Ready};
 
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    error::{ErrorBadRequest, ErrorForbidden, ErrorUnauthorized},
+    error::{ErrorBadRequest, ErrorForbidden, ErrorUnauthorized, InternalError},
     http::header::{self, HeaderName},
-    Error, Route,
+    Error, HttpResponse, Route,
 };
+use base64::{engine::general_purpose::STANDARD, Engine as _};
 use futures_util::future::LocalBoxFuture;
 
+use super::kinesis::FirehoseResponse;
 use crate::handlers::{
-    AUTHORIZATION_KEY, KINESIS_COMMON_ATTRIBUTES_KEY, LOG_SOURCE_KEY, LOG_SOURCE_KINESIS,
-    STREAM_NAME_HEADER_KEY,
+    AUTHORIZATION_KEY, KINESIS_ACCESS_KEY, KINESIS_COMMON_ATTRIBUTES_KEY, KINESIS_REQUEST_ID_KEY,
+    LOG_SOURCE_KEY, LOG_SOURCE_KINESIS, STREAM_NAME_HEADER_KEY,
 };
 use crate::{
     option::CONFIG,
@@ -49,9 +51,9 @@ struct Message {
 #[derive(Serialize, Deserialize, Debug)]
 struct CommonAttributes {
     #[serde(rename = "Authorization")]
-    authorization: String,
+    authorization: Option<String>,
     #[serde(rename = "X-P-Stream")]
-    x_p_stream: String,
+    x_p_stream: Option<String>,
 }
 
 pub trait RouteExt {
@@ -130,53 +132,109 @@ where
 
     fn call(&self, mut req: ServiceRequest) -> Self::Future {
         /*Below section is added to extract the Authorization and X-P-Stream headers from x-amz-firehose-common-attributes custom header
-        when request is made from Kinesis Firehose.
+        and the access key when request is made from Kinesis Firehose.
         For requests made from other clients, no change.
 
          ## Section start */
-        if let Some((_, kinesis_common_attributes)) = req
-            .request()
+        let firehose_request_id = req
             .headers()
-            .iter()
-            .find(|&(key, _)| key == KINESIS_COMMON_ATTRIBUTES_KEY)
+            .get(KINESIS_REQUEST_ID_KEY)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let firehose_result = if firehose_request_id.is_some()
+            || req.headers().contains_key(KINESIS_COMMON_ATTRIBUTES_KEY)
         {
-            let attribute_value: &str = kinesis_common_attributes.to_str().unwrap();
-            let message: Message = serde_json::from_str(attribute_value).unwrap();
-            req.headers_mut().insert(
-                HeaderName::from_static(AUTHORIZATION_KEY),
-                header::HeaderValue::from_str(&message.common_attributes.authorization.clone())
-                    .unwrap(),
-            );
-            req.headers_mut().insert(
-                HeaderName::from_static(STREAM_NAME_HEADER_KEY),
-                header::HeaderValue::from_str(&message.common_attributes.x_p_stream.clone())
-                    .unwrap(),
-            );
-            req.headers_mut().insert(
-                HeaderName::from_static(LOG_SOURCE_KEY),
-                header::HeaderValue::from_static(LOG_SOURCE_KINESIS),
-            );
-        }
+            set_firehose_headers(&mut req)
+        } else {
+            Ok(())
+        };
 
         /* ## Section end */
 
-        let auth_result: Result<_, Error> = (self.auth_method)(&mut req, self.action);
+        let auth_result: Result<_, Error> =
+            firehose_result.and_then(|_| (self.auth_method)(&mut req, self.action));
         let fut = self.service.call(req);
         Box::pin(async move {
-            match auth_result? {
-                rbac::Response::UnAuthorized => return Err(
+            let authorized = match auth_result {
+                Ok(rbac::Response::UnAuthorized) => Err(
                     ErrorForbidden("You don't have permission to access this resource. Please contact your administrator for assistance.")
                 ),
-                rbac::Response::ReloadRequired => return Err(
+                Ok(rbac::Response::ReloadRequired) => Err(
                     ErrorUnauthorized("Your session has expired or is no longer valid. Please re-authenticate to access this resource.")
                 ),
-                _ => {}
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = authorized {
+                // Firehose expects its error payload on failures as well
+                return Err(match firehose_request_id {
+                    Some(request_id) => firehose_error(&request_id, err),
+                    None => err,
+                });
             }
             fut.await
         })
     }
 }
 
+// The Authorization header is taken from the common attributes if set there,
+// else from the access key, which holds either base64 encoded or plain username:password credentials
+fn set_firehose_headers(req: &mut ServiceRequest) -> Result<(), Error> {
+    let attributes = match req.headers().get(KINESIS_COMMON_ATTRIBUTES_KEY) {
+        Some(value) => {
+            let message: Message = value
+                .to_str()
+                .ok()
+                .and_then(|value| serde_json::from_str(value).ok())
+                .ok_or_else(|| {
+                    ErrorBadRequest(format!("Invalid {} header", KINESIS_COMMON_ATTRIBUTES_KEY))
+                })?;
+            Some(message.common_attributes)
+        }
+        None => None,
+    };
+    let (authorization, stream) = match attributes {
+        Some(attributes) => (attributes.authorization, attributes.x_p_stream),
+        None => (None, None),
+    };
+
+    let authorization = authorization.or_else(|| {
+        let access_key = req.headers().get(KINESIS_ACCESS_KEY)?.to_str().ok()?;
+        if access_key.contains(':') {
+            Some(format!("Basic {}", STANDARD.encode(access_key)))
+        } else {
+            Some(format!("Basic {}", access_key))
+        }
+    });
+
+    let invalid = |key: &str| ErrorBadRequest(format!("Invalid {} value", key));
+    if let Some(authorization) = authorization {
+        req.headers_mut().insert(
+            HeaderName::from_static(AUTHORIZATION_KEY),
+            header::HeaderValue::from_str(&authorization)
+                .map_err(|_| invalid(AUTHORIZATION_KEY))?,
+        );
+    }
+    if let Some(stream) = stream {
+        req.headers_mut().insert(
+            HeaderName::from_static(STREAM_NAME_HEADER_KEY),
+            header::HeaderValue::from_str(&stream).map_err(|_| invalid(STREAM_NAME_HEADER_KEY))?,
+        );
+    }
+    req.headers_mut().insert(
+        HeaderName::from_static(LOG_SOURCE_KEY),
+        header::HeaderValue::from_static(LOG_SOURCE_KINESIS),
+    );
+    Ok(())
+}
+
+fn firehose_error(request_id: &str, err: Error) -> Error {
+    let message = err.to_string();
+    let response = HttpResponse::build(err.as_response_error().status_code())
+        .json(FirehoseResponse::error(request_id, message.clone()));
+    InternalError::from_response(message, response).into()
+}
+
 pub fn auth_no_context(req: &mut ServiceRequest, action: Action) -> Result<rbac::Response, Error> {
     let creds = extract_session_key(req);
     creds.map(|key| Users.authorize(key, action, None, None))
@@ -0,0 +1,354 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use prost::Message;
+use serde_json::{Map, Number, Value};
+use std::collections::BTreeMap;
+
+pub mod proto;
+
+use self::proto::{any_value, AnyValue, ExportLogsServiceRequest, KeyValue};
+
+// field holding the time of the log record, streams are created with a time partition on it
+pub const TIME_KEY: &str = "time_unix_nano";
+
+pub fn decode_protobuf(body: &Bytes) -> Result<ExportLogsServiceRequest, prost::DecodeError> {
+    ExportLogsServiceRequest::decode(body.clone())
+}
+
+pub fn decode_json(body: &Bytes) -> Result<ExportLogsServiceRequest, serde_json::Error> {
+    serde_json::from_slice(body)
+}
+
+// Flatten OTEL logs is used to flatten an OTLP export request into one JSON row per log record.
+// OTLP logs are nested as resourceLogs -> scopeLogs -> logRecords, every log record
+// is emitted as its own row with the attributes of its resource and scope hoisted into it.
+// {
+//     "resourceLogs": [{
+//         "resource": { "attributes": [{ "key": "service.name", "value": { "stringValue": "checkout" } }] },
+//         "scopeLogs": [{
+//             "scope": { "name": "app.logger" },
+//             "logRecords": [{
+//                 "timeUnixNano": "1705026780451000000",
+//                 "severityNumber": 9,
+//                 "severityText": "INFO",
+//                 "traceId": "5b8efff798038103d269b633813fc60c",
+//                 "body": { "stringValue": "order placed" }
+//             }]
+//         }]
+//     }]
+// }
+// is flattened to
+// {
+//     "service.name": "checkout",
+//     "scope_name": "app.logger",
+//     "time_unix_nano": "2024-01-12T02:33:00.451Z",
+//     "severity_number": 9,
+//     "severity_text": "INFO",
+//     "trace_id": "5b8efff798038103d269b633813fc60c",
+//     "body": "order placed"
+// }
+// Attributes of the log record take precedence over scope attributes,
+// which in turn take precedence over resource attributes.
+// Records without a time are given their observed time as the spec suggests,
+// or the time the request was received when neither is known.
+pub fn flatten_otel_logs(
+    request: ExportLogsServiceRequest,
+    received_at: DateTime<Utc>,
+) -> Vec<BTreeMap<String, Value>> {
+    let received_at = received_at.timestamp_nanos_opt().unwrap_or_default() as u64;
+
+    let mut vec_otel_json: Vec<BTreeMap<String, Value>> = Vec::new();
+
+    for resource_logs in request.resource_logs {
+        let mut resource_json: BTreeMap<String, Value> = BTreeMap::new();
+        if let Some(resource) = &resource_logs.resource {
+            insert_attributes(&mut resource_json, &resource.attributes);
+            insert_count(
+                &mut resource_json,
+                "resource_dropped_attributes_count",
+                resource.dropped_attributes_count,
+            );
+        }
+        insert_string(
+            &mut resource_json,
+            "resource_schema_url",
+            resource_logs.schema_url,
+        );
+
+        for scope_logs in resource_logs.scope_logs {
+            let mut scope_json = resource_json.clone();
+            if let Some(scope) = scope_logs.scope {
+                insert_attributes(&mut scope_json, &scope.attributes);
+                insert_string(&mut scope_json, "scope_name", scope.name);
+                insert_string(&mut scope_json, "scope_version", scope.version);
+                insert_count(
+                    &mut scope_json,
+                    "scope_dropped_attributes_count",
+                    scope.dropped_attributes_count,
+                );
+            }
+            insert_string(&mut scope_json, "scope_schema_url", scope_logs.schema_url);
+
+            for log_record in scope_logs.log_records {
+                let mut log_json = scope_json.clone();
+                insert_attributes(&mut log_json, &log_record.attributes);
+
+                let time = [
+                    log_record.time_unix_nano,
+                    log_record.observed_time_unix_nano,
+                ]
+                .into_iter()
+                .find(|&nanos| nanos != 0)
+                .unwrap_or(received_at);
+                if let Some(time) = format_unix_nano(time) {
+                    log_json.insert(TIME_KEY.to_owned(), time);
+                }
+                if let Some(time) = format_unix_nano(log_record.observed_time_unix_nano) {
+                    log_json.insert("observed_time_unix_nano".to_owned(), time);
+                }
+                if log_record.severity_number != 0 {
+                    log_json.insert(
+                        "severity_number".to_owned(),
+                        Value::Number(log_record.severity_number.into()),
+                    );
+                }
+                insert_string(&mut log_json, "severity_text", log_record.severity_text);
+                if let Some(body) = log_record.body.as_ref().and_then(any_value_to_json) {
+                    log_json.insert("body".to_owned(), body);
+                }
+                insert_count(
+                    &mut log_json,
+                    "dropped_attributes_count",
+                    log_record.dropped_attributes_count,
+                );
+                insert_count(&mut log_json, "flags", log_record.flags);
+                if !log_record.trace_id.is_empty() {
+                    log_json.insert(
+                        "trace_id".to_owned(),
+                        Value::String(hex::encode(&log_record.trace_id)),
+                    );
+                }
+                if !log_record.span_id.is_empty() {
+                    log_json.insert(
+                        "span_id".to_owned(),
+                        Value::String(hex::encode(&log_record.span_id)),
+                    );
+                }
+
+                vec_otel_json.push(log_json);
+            }
+        }
+    }
+
+    vec_otel_json
+}
+
+fn insert_attributes(map: &mut BTreeMap<String, Value>, attributes: &[KeyValue]) {
+    for attribute in attributes {
+        if let Some(value) = attribute.value.as_ref().and_then(any_value_to_json) {
+            map.insert(attribute.key.clone(), value);
+        }
+    }
+}
+
+fn insert_string(map: &mut BTreeMap<String, Value>, key: &str, value: String) {
+    if !value.is_empty() {
+        map.insert(key.to_owned(), Value::String(value));
+    }
+}
+
+fn insert_count(map: &mut BTreeMap<String, Value>, key: &str, value: u32) {
+    if value != 0 {
+        map.insert(key.to_owned(), Value::Number(value.into()));
+    }
+}
+
+// OTLP uses 0 to denote an unknown time
+fn format_unix_nano(nanos: u64) -> Option<Value> {
+    if nanos == 0 {
+        return None;
+    }
+    let secs = (nanos / 1_000_000_000) as i64;
+    let nsecs = (nanos % 1_000_000_000) as u32;
+    let time = NaiveDateTime::from_timestamp_opt(secs, nsecs)?.and_utc();
+    Some(Value::String(
+        time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    ))
+}
+
+fn any_value_to_json(value: &AnyValue) -> Option<Value> {
+    let value = match value.value.as_ref()? {
+        any_value::Value::StringValue(s) => Value::String(s.clone()),
+        any_value::Value::BoolValue(b) => Value::Bool(*b),
+        any_value::Value::IntValue(i) => Value::Number((*i).into()),
+        any_value::Value::DoubleValue(f) => Value::Number(Number::from_f64(*f)?),
+        any_value::Value::ArrayValue(arr) => {
+            Value::Array(arr.values.iter().filter_map(any_value_to_json).collect())
+        }
+        any_value::Value::KvlistValue(kvlist) => {
+            let map: Map<String, Value> = kvlist
+                .values
+                .iter()
+                .filter_map(|kv| Some((kv.key.clone(), any_value_to_json(kv.value.as_ref()?)?)))
+                .collect();
+            Value::Object(map)
+        }
+        any_value::Value::BytesValue(bytes) => Value::String(STANDARD.encode(bytes)),
+    };
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use chrono::{TimeZone, Utc};
+    use prost::Message;
+    use serde_json::{json, Value};
+
+    use super::proto::{
+        any_value, AnyValue, ExportLogsServiceRequest, InstrumentationScope, KeyValue, LogRecord,
+        Resource, ResourceLogs, ScopeLogs,
+    };
+    use super::{decode_json, decode_protobuf, flatten_otel_logs};
+
+    fn string_value(s: &str) -> Option<AnyValue> {
+        Some(AnyValue {
+            value: Some(any_value::Value::StringValue(s.to_owned())),
+        })
+    }
+
+    #[test]
+    fn json_request_is_split_into_rows() {
+        let body = json!({
+            "resourceLogs": [{
+                "resource": {
+                    "attributes": [{ "key": "service.name", "value": { "stringValue": "checkout" } }]
+                },
+                "scopeLogs": [{
+                    "scope": { "name": "app.logger", "version": "1.0" },
+                    "logRecords": [
+                        {
+                            "timeUnixNano": "1705026780451000000",
+                            "severityNumber": 9,
+                            "severityText": "INFO",
+                            "traceId": "5b8efff798038103d269b633813fc60c",
+                            "spanId": "eee19b7ec3c1b174",
+                            "body": { "stringValue": "order placed" },
+                            "attributes": [
+                                { "key": "order.id", "value": { "intValue": "42" } },
+                                { "key": "retry", "value": { "boolValue": false } }
+                            ]
+                        },
+                        {
+                            "observedTimeUnixNano": "1705026790000000000",
+                            "body": { "kvlistValue": { "values": [{ "key": "a", "value": { "doubleValue": 1.5 } }] } }
+                        }
+                    ]
+                }]
+            }]
+        });
+
+        let request = decode_json(&Bytes::from(serde_json::to_vec(&body).unwrap())).unwrap();
+        let rows = flatten_otel_logs(request, Utc::now());
+
+        assert_eq!(rows.len(), 2);
+        let first = &rows[0];
+        assert_eq!(first["service.name"], json!("checkout"));
+        assert_eq!(first["scope_name"], json!("app.logger"));
+        assert_eq!(first["scope_version"], json!("1.0"));
+        assert_eq!(first["time_unix_nano"], json!("2024-01-12T02:33:00.451Z"));
+        assert_eq!(first["severity_number"], json!(9));
+        assert_eq!(first["severity_text"], json!("INFO"));
+        assert_eq!(first["trace_id"], json!("5b8efff798038103d269b633813fc60c"));
+        assert_eq!(first["span_id"], json!("eee19b7ec3c1b174"));
+        assert_eq!(first["body"], json!("order placed"));
+        assert_eq!(first["order.id"], json!(42));
+        assert_eq!(first["retry"], json!(false));
+
+        let second = &rows[1];
+        assert_eq!(second["service.name"], json!("checkout"));
+        assert_eq!(second["body"], json!({ "a": 1.5 }));
+        assert_eq!(second["time_unix_nano"], json!("2024-01-12T02:33:10Z"));
+        assert_eq!(
+            second["observed_time_unix_nano"],
+            json!("2024-01-12T02:33:10Z")
+        );
+        assert!(!second.contains_key("trace_id"));
+    }
+
+    #[test]
+    fn protobuf_request_is_split_into_rows() {
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![KeyValue {
+                        key: "host.name".to_owned(),
+                        value: string_value("node-1"),
+                    }],
+                    dropped_attributes_count: 0,
+                }),
+                scope_logs: vec![ScopeLogs {
+                    scope: Some(InstrumentationScope {
+                        name: "app.logger".to_owned(),
+                        ..Default::default()
+                    }),
+                    log_records: vec![
+                        LogRecord {
+                            time_unix_nano: 1_705_026_780_451_000_000,
+                            severity_number: 17,
+                            severity_text: "ERROR".to_owned(),
+                            body: string_value("payment failed"),
+                            trace_id: vec![0xab; 16],
+                            ..Default::default()
+                        },
+                        LogRecord {
+                            body: string_value("no time"),
+                            ..Default::default()
+                        },
+                    ],
+                    schema_url: String::default(),
+                }],
+                schema_url: String::default(),
+            }],
+        };
+
+        let body = Bytes::from(request.encode_to_vec());
+        let received_at = Utc.with_ymd_and_hms(2024, 1, 12, 2, 34, 0).unwrap();
+        let rows = flatten_otel_logs(decode_protobuf(&body).unwrap(), received_at);
+
+        assert_eq!(rows.len(), 2);
+        let row = &rows[0];
+        assert_eq!(row["host.name"], json!("node-1"));
+        assert_eq!(row["scope_name"], json!("app.logger"));
+        assert_eq!(row["time_unix_nano"], json!("2024-01-12T02:33:00.451Z"));
+        assert_eq!(row["severity_number"], json!(17));
+        assert_eq!(row["body"], json!("payment failed"));
+        assert_eq!(row["trace_id"], Value::String("ab".repeat(16)));
+        assert_eq!(rows[1]["time_unix_nano"], json!("2024-01-12T02:34:00Z"));
+        assert!(!rows[1].contains_key("observed_time_unix_nano"));
+    }
+
+    #[test]
+    fn invalid_protobuf_is_err() {
+        assert!(decode_protobuf(&Bytes::from_static(b"\xff\xff\xff")).is_err());
+    }
+}
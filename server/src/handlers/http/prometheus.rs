@@ -0,0 +1,202 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use chrono::{NaiveDateTime, SecondsFormat};
+use prost::Message;
+use serde_json::{Number, Value};
+use std::collections::BTreeMap;
+
+const METRIC_NAME_LABEL: &str = "__name__";
+const METRIC_NAME_KEY: &str = "metric_name";
+const VALUE_KEY: &str = "value";
+const TIMESTAMP_KEY: &str = "timestamp";
+// labels that clash with the columns above are renamed the way prometheus does on scrape
+const EXPORTED_PREFIX: &str = "exported_";
+
+// Prometheus remote write request (prometheus.WriteRequest, remote write 1.0).
+// The protobuf payload is snappy (block format) compressed.
+// Metadata, exemplars and native histograms are not read.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TimeSeries {
+    // sorted by name, the metric name is the __name__ label
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    // milliseconds since epoch
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+pub fn flatten_write_request(body: &Bytes) -> Result<Vec<BTreeMap<String, Value>>, anyhow::Error> {
+    let body = snap::raw::Decoder::new()
+        .decompress_vec(body)
+        .map_err(|err| anyhow!("Could not decompress snappy payload, {}", err))?;
+    let request = WriteRequest::decode(body.as_slice())?;
+
+    let mut rows = Vec::new();
+    for series in request.timeseries {
+        let labels = series_labels(series.labels);
+        for sample in series.samples {
+            rows.push(to_row(&labels, sample)?);
+        }
+    }
+
+    Ok(rows)
+}
+
+// Labels of a series as columns, with the metric name in its own column
+// { "metric_name": "http_requests_total", "job": "api", "exported_value": "x" }
+fn series_labels(labels: Vec<Label>) -> BTreeMap<String, Value> {
+    labels
+        .into_iter()
+        .map(|label| {
+            let name = match label.name.as_str() {
+                METRIC_NAME_LABEL => METRIC_NAME_KEY.to_owned(),
+                METRIC_NAME_KEY | VALUE_KEY | TIMESTAMP_KEY => {
+                    format!("{EXPORTED_PREFIX}{}", label.name)
+                }
+                _ => label.name,
+            };
+            (name, Value::String(label.value))
+        })
+        .collect()
+}
+
+// Every sample becomes a row with the labels of its series
+// { "metric_name": "up", "job": "api", "value": 1.0, "timestamp": "2024-01-12T02:33:00.451Z" }
+fn to_row(
+    labels: &BTreeMap<String, Value>,
+    sample: Sample,
+) -> Result<BTreeMap<String, Value>, anyhow::Error> {
+    let mut row = labels.clone();
+
+    let timestamp = NaiveDateTime::from_timestamp_millis(sample.timestamp)
+        .ok_or_else(|| anyhow!("Timestamp {} is out of range", sample.timestamp))?
+        .and_utc();
+    row.insert(
+        TIMESTAMP_KEY.to_owned(),
+        Value::String(timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+    );
+    // NaN (which includes staleness markers) and infinities have no JSON
+    // representation, they are stored as null
+    row.insert(
+        VALUE_KEY.to_owned(),
+        Number::from_f64(sample.value).map_or(Value::Null, Value::Number),
+    );
+
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use prost::Message;
+    use serde_json::json;
+
+    use super::{flatten_write_request, Label, Sample, TimeSeries, WriteRequest};
+
+    fn label(name: &str, value: &str) -> Label {
+        Label {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn write_request_into_rows() {
+        let request = WriteRequest {
+            timeseries: vec![
+                TimeSeries {
+                    labels: vec![
+                        label("__name__", "http_requests_total"),
+                        label("job", "api"),
+                        label("value", "clash"),
+                    ],
+                    samples: vec![
+                        Sample {
+                            value: 10.0,
+                            timestamp: 1705026780451,
+                        },
+                        Sample {
+                            value: 12.5,
+                            timestamp: 1705026795451,
+                        },
+                    ],
+                },
+                TimeSeries {
+                    labels: vec![label("__name__", "up"), label("instance", "node-1")],
+                    samples: vec![Sample {
+                        value: f64::NAN,
+                        timestamp: 1705026780000,
+                    }],
+                },
+            ],
+        };
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&request.encode_to_vec())
+            .unwrap();
+
+        let rows = flatten_write_request(&Bytes::from(body)).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0]["metric_name"], json!("http_requests_total"));
+        assert_eq!(rows[0]["job"], json!("api"));
+        assert_eq!(rows[0]["exported_value"], json!("clash"));
+        assert_eq!(rows[0]["value"], json!(10.0));
+        assert_eq!(rows[0]["timestamp"], json!("2024-01-12T02:33:00.451Z"));
+        assert_eq!(rows[1]["value"], json!(12.5));
+        assert_eq!(rows[1]["timestamp"], json!("2024-01-12T02:33:15.451Z"));
+        assert_eq!(rows[2]["metric_name"], json!("up"));
+        assert_eq!(rows[2]["instance"], json!("node-1"));
+        assert_eq!(rows[2]["value"], json!(null));
+        assert!(!rows[2].contains_key("job"));
+    }
+
+    #[test]
+    fn uncompressed_write_request_is_err() {
+        let request = WriteRequest {
+            timeseries: vec![TimeSeries {
+                labels: vec![label("__name__", "up")],
+                samples: vec![],
+            }],
+        };
+        assert!(flatten_write_request(&Bytes::from(request.encode_to_vec())).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use futures::StreamExt;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, ConsumerContext, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::statistics::Statistics;
+use rdkafka::{ClientContext, Offset};
+use serde_json::Value;
+
+use crate::event::dead_letter;
+use crate::metrics::KAFKA_CONSUMER_LAG;
+use crate::option::CONFIG;
+
+use super::http::ingest::{push_dead_letters, push_records};
+
+// messages that are ready are ingested together, up to this many at a time
+const MAX_BATCH_MESSAGES: usize = 1000;
+// interval at which librdkafka reports statistics, consumer lag is taken from them
+const STATISTICS_INTERVAL_MS: &str = "10000";
+// wait before a batch that failed to be written is consumed again
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// Kafka consumer, started when --kafka-brokers is set.
+// Every topic of --kafka-topics is consumed into a stream, the value of each message
+// is a JSON object or an array of objects.
+// Offsets are stored only once the events of a message are processed and written
+// to the local staging, librdkafka then commits them in the background.
+// A single node Redpanda works for trying it out locally
+//     rpk container start
+//     parseable local-store --kafka-brokers 127.0.0.1:<port> --kafka-topics orders,payments:billing
+pub async fn consumer() {
+    let Some(brokers) = CONFIG.parseable.kafka_brokers.clone() else {
+        return;
+    };
+    let streams = topic_streams(&CONFIG.parseable.kafka_topics);
+
+    let consumer: StreamConsumer<LagContext> = match ClientConfig::new()
+        .set("bootstrap.servers", &brokers)
+        .set("group.id", &CONFIG.parseable.kafka_group)
+        .set("enable.auto.commit", "true")
+        .set("enable.auto.offset.store", "false")
+        .set("auto.offset.reset", "earliest")
+        .set("statistics.interval.ms", STATISTICS_INTERVAL_MS)
+        .create_with_context(LagContext)
+    {
+        Ok(consumer) => consumer,
+        Err(err) => {
+            log::error!("could not create kafka consumer for {}. {:?}", brokers, err);
+            return;
+        }
+    };
+
+    let topics: Vec<&str> = streams.keys().map(String::as_str).collect();
+    if let Err(err) = consumer.subscribe(&topics) {
+        log::error!(
+            "could not subscribe to kafka topics {:?}. {:?}",
+            topics,
+            err
+        );
+        return;
+    }
+    log::info!("kafka consumer subscribed to {:?} on {}", topics, brokers);
+
+    let mut messages = consumer.stream().ready_chunks(MAX_BATCH_MESSAGES);
+    while let Some(chunk) = messages.next().await {
+        let mut batches: BTreeMap<String, Batch> = BTreeMap::new();
+        for message in chunk {
+            let message = match message {
+                Ok(message) => message,
+                Err(err) => {
+                    log::warn!("failed to consume kafka message. {:?}", err);
+                    continue;
+                }
+            };
+            let batch = batches.entry(message.topic().to_owned()).or_default();
+            batch.track(message.partition(), message.offset());
+            let Some(payload) = message.payload() else {
+                continue;
+            };
+            match decode_value(payload) {
+                Ok(records) => {
+                    batch.size += message.payload_len();
+                    batch.records.extend(records);
+                }
+                Err(reason) => {
+                    log::warn!(
+                        "skipping kafka message {} of {}/{}, {}",
+                        message.offset(),
+                        message.topic(),
+                        message.partition(),
+                        reason
+                    );
+                    batch.undecoded.push((payload.to_vec(), reason));
+                }
+            }
+        }
+
+        for (topic, batch) in batches {
+            let Some(stream_name) = streams.get(&topic) else {
+                continue;
+            };
+            let processed = if batch.records.is_empty() {
+                true
+            } else {
+                let source = format!("kafka topic {}", topic);
+                match push_records(stream_name, batch.records, batch.size, &source).await {
+                    Ok(_) => true,
+                    // the events will not be accepted on a retry either
+                    Err(err) if err.is_rejection() => {
+                        log::warn!(
+                            "dropping kafka messages of {} not accepted by {}. {}",
+                            topic,
+                            stream_name,
+                            err
+                        );
+                        true
+                    }
+                    Err(err) => {
+                        log::error!(
+                            "failed to ingest kafka messages of {} into {}, retrying. {}",
+                            topic,
+                            stream_name,
+                            err
+                        );
+                        false
+                    }
+                }
+            };
+
+            for (partition, (first, last)) in batch.offsets {
+                let result = if processed {
+                    consumer.store_offset(&topic, partition, last)
+                } else {
+                    consumer.seek(&topic, partition, Offset::Offset(first), RETRY_DELAY)
+                };
+                if let Err(err) = result {
+                    log::warn!(
+                        "failed to update offset of kafka partition {}/{}. {:?}",
+                        topic,
+                        partition,
+                        err
+                    );
+                }
+            }
+            if processed {
+                // messages that are read again after a retry are rejected again, so only once
+                let headers = dead_letter::source_headers(format!("kafka topic {}", topic));
+                push_dead_letters(stream_name, &headers, batch.undecoded).await;
+            } else {
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+// messages of a topic that are ingested together
+#[derive(Default)]
+struct Batch {
+    records: Vec<BTreeMap<String, Value>>,
+    size: usize,
+    // payloads of the messages that could not be decoded, with the reason
+    undecoded: Vec<(Vec<u8>, String)>,
+    // first and last offset of every partition in the batch
+    offsets: HashMap<i32, (i64, i64)>,
+}
+
+impl Batch {
+    fn track(&mut self, partition: i32, offset: i64) {
+        let offsets = self.offsets.entry(partition).or_insert((offset, offset));
+        offsets.0 = offsets.0.min(offset);
+        offsets.1 = offsets.1.max(offset);
+    }
+}
+
+// maps every topic to its stream, topic:stream or topic for a stream of the same name
+fn topic_streams(topics: &[String]) -> HashMap<String, String> {
+    topics
+        .iter()
+        .map(|topic| match topic.split_once(':') {
+            Some((topic, stream)) => (topic.to_owned(), stream.to_owned()),
+            None => (topic.to_owned(), topic.to_owned()),
+        })
+        .collect()
+}
+
+fn decode_value(payload: &[u8]) -> Result<Vec<BTreeMap<String, Value>>, String> {
+    let value: Value =
+        serde_json::from_slice(payload).map_err(|err| format!("value is not JSON, {}", err))?;
+    let values = match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::Object(map) => Ok(map.into_iter().collect()),
+            _ => Err("value is not a JSON object or an array of objects".to_owned()),
+        })
+        .collect()
+}
+
+// sets the lag of every assigned partition from the statistics of the consumer
+struct LagContext;
+
+impl ClientContext for LagContext {
+    fn stats(&self, statistics: Statistics) {
+        for topic in statistics.topics.values() {
+            for (partition, stats) in &topic.partitions {
+                // -1 is the internal unassigned partition, lag is -1 if not known yet
+                if *partition < 0 || stats.consumer_lag < 0 {
+                    continue;
+                }
+                KAFKA_CONSUMER_LAG
+                    .with_label_values(&[&topic.topic, &partition.to_string()])
+                    .set(stats.consumer_lag);
+            }
+        }
+    }
+}
+
+impl ConsumerContext for LagContext {}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{decode_value, topic_streams};
+
+    #[test]
+    fn topics_map_to_streams() {
+        let streams = topic_streams(&["orders".to_owned(), "payments:billing".to_owned()]);
+        assert_eq!(streams["orders"], "orders");
+        assert_eq!(streams["payments"], "billing");
+    }
+
+    #[test]
+    fn values_are_decoded() {
+        let records = decode_value(br#"[{"a": 1}, {"b": {"c": true}}]"#).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["b"], json!({"c": true}));
+        assert_eq!(decode_value(br#"{"a": 1}"#).unwrap().len(), 1);
+
+        assert!(decode_value(b"not json").is_err());
+        assert!(decode_value(b"[1, 2]").is_err());
+    }
+}
@@ -18,11 +18,15 @@
 
 use std::net::SocketAddr;
 
+use actix_web::ResponseError;
 use arrow_array::RecordBatch;
+use arrow_flight::decode::FlightRecordBatchStream;
 use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
 use cookie::Cookie;
 use futures::stream::BoxStream;
 use futures_util::{Future, StreamExt, TryFutureExt, TryStreamExt};
+use http::StatusCode;
 use http_auth_basic::Credentials;
 use rand::distributions::{Alphanumeric, DistString};
 use tonic::metadata::MetadataMap;
@@ -37,6 +41,7 @@ use arrow_flight::{
 use tonic_web::GrpcWebLayer;
 use tower_http::cors::CorsLayer;
 
+use crate::handlers::http::ingest::{push_arrow_batch, session_credential, PostError};
 use crate::livetail::{Message, LIVETAIL};
 use crate::metadata::STREAM_INFO;
 use crate::option::CONFIG;
@@ -95,17 +100,7 @@ impl FlightService for FlightServiceImpl {
             .map_err(|err| Status::internal(err.to_string()))?;
         let stream = extract_stream(&ticket)?;
         log::info!("livetail requested for stream {}", stream);
-        match Users.authorize(key, rbac::role::Action::Query, Some(stream), None) {
-            rbac::Response::Authorized => (),
-            rbac::Response::UnAuthorized => {
-                return Err(Status::permission_denied(
-                    "user is not authenticated to access this resource",
-                ))
-            }
-            rbac::Response::ReloadRequired => {
-                return Err(Status::unauthenticated("reload required"))
-            }
-        }
+        authorize(key, rbac::role::Action::Query, stream)?;
 
         let schema = STREAM_INFO
             .schema(stream)
@@ -135,9 +130,42 @@ impl FlightService for FlightServiceImpl {
 
     async fn do_put(
         &self,
-        _request: Request<Streaming<FlightData>>,
+        req: Request<Streaming<FlightData>>,
     ) -> Result<Response<Self::DoPutStream>, Status> {
-        Err(Status::unimplemented("Implement do_put"))
+        let key = extract_session_key(req.metadata())?;
+        let mut flight_data = req.into_inner();
+        // the first message carries the descriptor naming the stream along with the schema
+        let first = flight_data
+            .message()
+            .await?
+            .ok_or(Status::invalid_argument("no data in do_put request"))?;
+        let stream = extract_put_stream(first.flight_descriptor.as_ref())?;
+        log::info!("do_put requested for stream {}", stream);
+        let credential = session_credential(key.clone());
+        authorize(key, rbac::role::Action::Ingest, &stream)?;
+
+        let flight_data = futures::stream::once(async { Ok(first) })
+            .chain(flight_data)
+            .map_err(FlightError::from);
+        let mut batches = FlightRecordBatchStream::new_from_flight_data(flight_data);
+
+        let mut results = Vec::new();
+        while let Some(rb) = batches
+            .try_next()
+            .await
+            .map_err(|err| Status::invalid_argument(err.to_string()))?
+        {
+            if rb.num_rows() == 0 {
+                continue;
+            }
+            let size = rb.get_array_memory_size();
+            push_arrow_batch(&stream, Some(&credential), rb, size)
+                .await
+                .map_err(post_error_to_status)?;
+            results.push(Ok(PutResult::default()));
+        }
+
+        Ok(Response::new(Box::pin(futures::stream::iter(results))))
     }
 
     async fn do_action(
@@ -232,6 +260,39 @@ fn extract_stream(body: &serde_json::Value) -> Result<&str, Status> {
         .ok_or(Status::invalid_argument("stream key value is invalid"))
 }
 
+// stream to put data into is either the first element of the descriptor path
+// or a command of the form {"stream": "name"}
+fn extract_put_stream(descriptor: Option<&FlightDescriptor>) -> Result<String, Status> {
+    let descriptor = descriptor.ok_or(Status::invalid_argument(
+        "flight descriptor is not provided",
+    ))?;
+    if let Some(stream) = descriptor.path.first() {
+        return Ok(stream.to_owned());
+    }
+    let cmd: serde_json::Value = serde_json::from_slice(&descriptor.cmd)
+        .map_err(|err| Status::invalid_argument(err.to_string()))?;
+    extract_stream(&cmd).map(str::to_owned)
+}
+
+fn authorize(key: SessionKey, action: rbac::role::Action, stream: &str) -> Result<(), Status> {
+    match Users.authorize(key, action, Some(stream), None) {
+        rbac::Response::Authorized => Ok(()),
+        rbac::Response::UnAuthorized => Err(Status::permission_denied(
+            "user is not authenticated to access this resource",
+        )),
+        rbac::Response::ReloadRequired => Err(Status::unauthenticated("reload required")),
+    }
+}
+
+fn post_error_to_status(err: PostError) -> Status {
+    match err.status_code() {
+        StatusCode::NOT_FOUND => Status::not_found(err.to_string()),
+        StatusCode::TOO_MANY_REQUESTS => Status::resource_exhausted(err.to_string()),
+        code if code.is_client_error() => Status::invalid_argument(err.to_string()),
+        _ => Status::internal(err.to_string()),
+    }
+}
+
 fn extract_session_key(headers: &MetadataMap) -> Result<SessionKey, Status> {
     // Extract username and password from the request using basic auth extractor.
     let basic = extract_basic_auth(headers).map(|creds| SessionKey::BasicAuth {
@@ -0,0 +1,482 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use bytes::{Buf, Bytes, BytesMut};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+use crate::option::CONFIG;
+
+use super::http::ingest::push_records;
+
+// upper bound for a single syslog message, larger frames close the connection
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+// octet counting prefix is at most this many digits followed by a space
+const MAX_FRAME_LEN_DIGITS: usize = 10;
+
+const NILVALUE: &str = "-";
+
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+// Syslog listener, accepts messages over TCP (octet counting or newline framed)
+// and UDP on the same address and ingests them into the configured stream
+pub async fn server() {
+    let Some(address) = CONFIG.parseable.syslog_address.clone() else {
+        return;
+    };
+    let stream_name = CONFIG.parseable.syslog_stream.clone();
+
+    let (tcp, udp) = match tokio::try_join!(TcpListener::bind(&address), UdpSocket::bind(&address))
+    {
+        Ok(sockets) => sockets,
+        Err(err) => {
+            log::error!("could not start syslog listener on {}. {:?}", address, err);
+            return;
+        }
+    };
+    log::info!("syslog listener started on {}", address);
+
+    tokio::join!(serve_tcp(tcp, &stream_name), serve_udp(udp, &stream_name));
+}
+
+async fn serve_tcp(listener: TcpListener, stream_name: &str) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, peer)) => {
+                let stream_name = stream_name.to_owned();
+                actix_web::rt::spawn(async move {
+                    if let Err(err) = handle_connection(socket, &stream_name).await {
+                        log::warn!("syslog connection from {} closed. {}", peer, err);
+                    }
+                });
+            }
+            Err(err) => log::warn!("failed to accept syslog connection. {:?}", err),
+        }
+    }
+}
+
+async fn handle_connection(mut socket: TcpStream, stream_name: &str) -> anyhow::Result<()> {
+    let mut buf = BytesMut::with_capacity(8 * 1024);
+    loop {
+        let read = socket.read_buf(&mut buf).await?;
+        let mut frames = Vec::new();
+        while let Some(frame) = next_frame(&mut buf)? {
+            frames.push(frame);
+        }
+        // peer closed the connection, whatever is left is the last message
+        if read == 0 {
+            frames.push(buf.split().freeze());
+        }
+        ingest_frames(stream_name, frames).await;
+        if read == 0 {
+            return Ok(());
+        }
+    }
+}
+
+async fn serve_udp(socket: UdpSocket, stream_name: &str) {
+    let mut buf = vec![0u8; MAX_FRAME_SIZE];
+    loop {
+        match socket.recv_from(&mut buf).await {
+            // every datagram carries exactly one message
+            Ok((len, _)) => {
+                ingest_frames(stream_name, vec![Bytes::copy_from_slice(&buf[..len])]).await
+            }
+            Err(err) => log::warn!("failed to receive syslog datagram. {:?}", err),
+        }
+    }
+}
+
+async fn ingest_frames(stream_name: &str, frames: Vec<Bytes>) {
+    let mut size = 0;
+    let mut records = Vec::new();
+    for frame in frames {
+        size += frame.len();
+        let message = String::from_utf8_lossy(&frame);
+        let message = message.trim_end_matches(['\r', '\n', '\0']);
+        if !message.trim().is_empty() {
+            records.push(parse_message(message));
+        }
+    }
+
+    if records.is_empty() {
+        return;
+    }
+    if let Err(err) = push_records(stream_name, records, size, "syslog").await {
+        log::warn!(
+            "failed to ingest syslog messages into {}. {}",
+            stream_name,
+            err
+        );
+    }
+}
+
+// Split the next complete message off the buffer.
+// RFC 6587 octet counting frames start with the message length ("27 <34>1 ...")
+// while non transparent framing terminates every message with a newline.
+fn next_frame(buf: &mut BytesMut) -> anyhow::Result<Option<Bytes>> {
+    while buf.first().map_or(false, |b| b.is_ascii_whitespace()) {
+        buf.advance(1);
+    }
+    if buf.is_empty() {
+        return Ok(None);
+    }
+
+    if buf[0].is_ascii_digit() {
+        let Some(space) = buf
+            .iter()
+            .take(MAX_FRAME_LEN_DIGITS + 1)
+            .position(|&b| b == b' ')
+        else {
+            if buf.len() > MAX_FRAME_LEN_DIGITS {
+                anyhow::bail!("invalid octet counting frame");
+            }
+            return Ok(None);
+        };
+        let len: usize = std::str::from_utf8(&buf[..space])?.parse()?;
+        if len > MAX_FRAME_SIZE {
+            anyhow::bail!("frame of {} bytes exceeds the limit", len);
+        }
+        if buf.len() < space + 1 + len {
+            return Ok(None);
+        }
+        buf.advance(space + 1);
+        return Ok(Some(buf.split_to(len).freeze()));
+    }
+
+    match buf.iter().position(|&b| b == b'\n') {
+        Some(newline) => {
+            let frame = buf.split_to(newline).freeze();
+            buf.advance(1);
+            Ok(Some(frame))
+        }
+        None if buf.len() > MAX_FRAME_SIZE => anyhow::bail!("message exceeds the frame limit"),
+        None => Ok(None),
+    }
+}
+
+// Parse a syslog message in either RFC 5424 or RFC 3164 (BSD) format.
+// Messages that can not be parsed are ingested with the raw text as message.
+// <165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3"] An application event
+// is parsed to
+// {
+//     "facility": "local4",
+//     "severity": "notice",
+//     "version": 1,
+//     "timestamp": "2003-10-11T22:14:15.003Z",
+//     "hostname": "mymachine.example.com",
+//     "app_name": "evntslog",
+//     "msgid": "ID47",
+//     "structured_data": { "exampleSDID@32473": { "iut": "3" } },
+//     "message": "An application event"
+// }
+pub fn parse_message(message: &str) -> BTreeMap<String, Value> {
+    let mut record = BTreeMap::new();
+
+    let Some((pri, rest)) = parse_pri(message) else {
+        insert_string(&mut record, "message", message);
+        return record;
+    };
+    insert_string(&mut record, "facility", FACILITIES[(pri >> 3) as usize]);
+    insert_string(&mut record, "severity", SEVERITIES[(pri & 7) as usize]);
+
+    let parsed = match rest.split_once(' ') {
+        Some((version, rest))
+            if !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            parse_rfc5424(&mut record, version, rest)
+        }
+        _ => parse_rfc3164(&mut record, rest, Utc::now()),
+    };
+    if parsed.is_none() {
+        insert_string(&mut record, "message", rest);
+    }
+
+    record
+}
+
+fn parse_pri(message: &str) -> Option<(u8, &str)> {
+    let (pri, rest) = message.strip_prefix('<')?.split_once('>')?;
+    if pri.is_empty() || pri.len() > 3 || !pri.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let pri: u8 = pri.parse().ok()?;
+    (pri <= 191).then_some((pri, rest))
+}
+
+fn parse_rfc5424(record: &mut BTreeMap<String, Value>, version: &str, rest: &str) -> Option<()> {
+    let mut rest = rest;
+    let mut fields = Vec::with_capacity(5);
+    for _ in 0..5 {
+        let (field, remaining) = rest.split_once(' ')?;
+        fields.push(field);
+        rest = remaining;
+    }
+    let (structured_data, rest) = parse_structured_data(rest)?;
+
+    record.insert(
+        "version".to_owned(),
+        Value::Number(version.parse::<u64>().ok()?.into()),
+    );
+    for (key, field) in ["timestamp", "hostname", "app_name", "procid", "msgid"]
+        .into_iter()
+        .zip(fields)
+    {
+        if field != NILVALUE {
+            insert_string(record, key, field);
+        }
+    }
+    if !structured_data.is_empty() {
+        record.insert("structured_data".to_owned(), Value::Object(structured_data));
+    }
+    let message = rest.strip_prefix(' ').unwrap_or(rest);
+    insert_string(record, "message", message.trim_start_matches('\u{feff}'));
+
+    Some(())
+}
+
+// [exampleSDID@32473 iut="3" eventSource="Application"][examplePriority@32473 class="high"]
+fn parse_structured_data(data: &str) -> Option<(Map<String, Value>, &str)> {
+    let mut elements = Map::new();
+    if let Some(rest) = data.strip_prefix(NILVALUE) {
+        return Some((elements, rest));
+    }
+
+    let mut rest = data;
+    while let Some(element) = rest.strip_prefix('[') {
+        let end = element.find([' ', ']'])?;
+        let id = &element[..end];
+        let mut params = Map::new();
+        rest = &element[end..];
+
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if let Some(remaining) = rest.strip_prefix(']') {
+                rest = remaining;
+                break;
+            }
+            let (name, remaining) = rest.split_once("=\"")?;
+            let mut value = String::new();
+            let mut chars = remaining.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (idx, '"') => break idx,
+                    (_, '\\') => {
+                        let (_, c) = chars.next()?;
+                        if !matches!(c, '"' | '\\' | ']') {
+                            value.push('\\');
+                        }
+                        value.push(c);
+                    }
+                    (_, c) => value.push(c),
+                }
+            };
+            params.insert(name.to_owned(), Value::String(value));
+            rest = &remaining[end + 1..];
+        }
+
+        elements.insert(id.to_owned(), Value::Object(params));
+    }
+
+    (!elements.is_empty()).then_some((elements, rest))
+}
+
+// <34>Oct 11 22:14:15 mymachine su[230]: 'su root' failed for lonvick on /dev/pts/8
+fn parse_rfc3164(
+    record: &mut BTreeMap<String, Value>,
+    rest: &str,
+    now: DateTime<Utc>,
+) -> Option<()> {
+    // timestamp is always 15 characters, days are space padded
+    let timestamp = rest.get(..15)?;
+    let rest = rest.get(15..)?.strip_prefix(' ')?;
+    let timestamp = parse_bsd_timestamp(timestamp, now)?;
+    insert_string(
+        record,
+        "timestamp",
+        &timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    );
+
+    let (hostname, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    insert_string(record, "hostname", hostname);
+
+    // tag is the program name optionally followed by its pid, terminated by a colon
+    let message = match rest.split_once(": ") {
+        Some((tag, message)) if !tag.is_empty() && !tag.contains(' ') => {
+            match tag.strip_suffix(']').and_then(|tag| tag.split_once('[')) {
+                Some((app_name, procid)) => {
+                    insert_string(record, "app_name", app_name);
+                    insert_string(record, "procid", procid);
+                }
+                None => insert_string(record, "app_name", tag),
+            }
+            message
+        }
+        _ => rest,
+    };
+    insert_string(record, "message", message);
+
+    Some(())
+}
+
+// BSD timestamps carry no year or timezone, they are assumed to be UTC
+// in the current year unless that would put them in the future
+fn parse_bsd_timestamp(timestamp: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let parse = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{year} {timestamp}"), "%Y %b %e %H:%M:%S")
+            .ok()
+            .map(|time| time.and_utc())
+    };
+    let timestamp = parse(now.year())?;
+    if timestamp - now > Duration::days(1) {
+        return parse(now.year() - 1);
+    }
+    Some(timestamp)
+}
+
+fn insert_string(record: &mut BTreeMap<String, Value>, key: &str, value: &str) {
+    if !value.is_empty() {
+        record.insert(key.to_owned(), Value::String(value.to_owned()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    use super::{next_frame, parse_message, parse_rfc3164};
+
+    #[test]
+    fn rfc5424_message() {
+        let record = parse_message(concat!(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 ",
+            "[exampleSDID@32473 iut=\"3\" eventSource=\"App\\\"lication\"][examplePriority@32473 class=\"high\"] ",
+            "\u{feff}An application event"
+        ));
+
+        assert_eq!(record["facility"], json!("local4"));
+        assert_eq!(record["severity"], json!("notice"));
+        assert_eq!(record["version"], json!(1));
+        assert_eq!(record["timestamp"], json!("2003-10-11T22:14:15.003Z"));
+        assert_eq!(record["hostname"], json!("mymachine.example.com"));
+        assert_eq!(record["app_name"], json!("evntslog"));
+        assert_eq!(record["msgid"], json!("ID47"));
+        assert!(!record.contains_key("procid"));
+        assert_eq!(
+            record["structured_data"],
+            json!({
+                "exampleSDID@32473": { "iut": "3", "eventSource": "App\"lication" },
+                "examplePriority@32473": { "class": "high" }
+            })
+        );
+        assert_eq!(record["message"], json!("An application event"));
+    }
+
+    #[test]
+    fn rfc5424_message_without_structured_data() {
+        let record = parse_message("<34>1 - host app 42 - - failed login");
+        assert_eq!(record["facility"], json!("auth"));
+        assert_eq!(record["severity"], json!("crit"));
+        assert!(!record.contains_key("timestamp"));
+        assert_eq!(record["procid"], json!("42"));
+        assert!(!record.contains_key("structured_data"));
+        assert_eq!(record["message"], json!("failed login"));
+    }
+
+    #[test]
+    fn rfc3164_message() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        let mut record = Default::default();
+        parse_rfc3164(
+            &mut record,
+            "Oct  1 22:14:15 mymachine su[230]: 'su root' failed",
+            now,
+        )
+        .unwrap();
+
+        // a date later than now is from the previous year
+        assert_eq!(record["timestamp"], json!("2023-10-01T22:14:15Z"));
+        assert_eq!(record["hostname"], json!("mymachine"));
+        assert_eq!(record["app_name"], json!("su"));
+        assert_eq!(record["procid"], json!("230"));
+        assert_eq!(record["message"], json!("'su root' failed"));
+
+        let record = parse_message("<13>Feb  5 17:32:18 10.0.0.99 Use the BFG!");
+        assert_eq!(record["facility"], json!("user"));
+        assert_eq!(record["hostname"], json!("10.0.0.99"));
+        assert!(!record.contains_key("app_name"));
+        assert_eq!(record["message"], json!("Use the BFG!"));
+    }
+
+    #[test]
+    fn unparsable_message_is_kept() {
+        let record = parse_message("just some text");
+        assert_eq!(record.len(), 1);
+        assert_eq!(record["message"], json!("just some text"));
+
+        let record = parse_message("<14>not a timestamp");
+        assert_eq!(record["severity"], json!("info"));
+        assert_eq!(record["message"], json!("not a timestamp"));
+    }
+
+    #[test]
+    fn frames_are_split() {
+        let mut buf = BytesMut::from("11 <14>1 - - -\n<13>hello\r\n<13>partial");
+        assert_eq!(&next_frame(&mut buf).unwrap().unwrap()[..], b"<14>1 - - -");
+        assert_eq!(&next_frame(&mut buf).unwrap().unwrap()[..], b"<13>hello\r");
+        assert!(next_frame(&mut buf).unwrap().is_none());
+        assert_eq!(&buf[..], b"<13>partial");
+
+        let mut buf = BytesMut::from("99999999999 <14>");
+        assert!(next_frame(&mut buf).is_err());
+    }
+}
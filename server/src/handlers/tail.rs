@@ -0,0 +1,566 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::event::text_parser::MESSAGE_KEY;
+use crate::metadata::STREAM_INFO;
+use crate::option::CONFIG;
+
+use super::http::ingest::push_records;
+
+// read offsets of the tailed files, kept in the staging directory
+const CHECKPOINT_FILE_NAME: &str = ".tail.json";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// wait before lines that failed to be written are read again
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// most bytes of a file that are read and ingested at once
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+// column with the path of the file a line was read from
+const FILENAME_KEY: &str = "filename";
+// most bytes at the start of a file that its fingerprint is taken of
+const FINGERPRINT_SIZE: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TailFormat {
+    // lines are parsed with the text parser of the stream, see PUT /logstream/{name}/parser
+    #[default]
+    Text,
+    // every line is a JSON object, lines that are not are kept as text
+    Json,
+}
+
+impl TailFormat {
+    pub fn from_format(format: &str) -> Option<Self> {
+        match format {
+            "text" => Some(TailFormat::Text),
+            "json" => Some(TailFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+// identity of a file, that stays the same when the file is renamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct FileId {
+    dev: u64,
+    inode: u64,
+}
+
+// size and hash of the first bytes of a file, tells it apart from a later file that
+// is given the same inode once it is deleted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Fingerprint {
+    size: u64,
+    hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Position {
+    #[serde(flatten)]
+    id: FileId,
+    path: PathBuf,
+    offset: u64,
+    // missing in checkpoints of older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<Fingerprint>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    files: Vec<Position>,
+}
+
+struct TailedFile {
+    path: PathBuf,
+    // bytes of the file that are ingested
+    offset: u64,
+    fingerprint: Option<Fingerprint>,
+    // kept open so that a file that is rotated away is still read to its end
+    file: Option<Arc<File>>,
+}
+
+// File tailer, started when --tail-paths is set.
+// The files matching the globs are read line by line into --tail-stream.
+// Rotation by renaming is followed as files are tracked by inode, a file that is
+// no longer matched is read to its end before it is let go. A file that shrinks
+// is taken to be truncated and is read again from its start. Files renamed while
+// the server is down are looked up by inode in the directory they were in.
+// Offsets are checkpointed only once the lines are written to the local staging,
+// so lines are neither skipped nor read again across restarts.
+pub async fn tailer() {
+    if CONFIG.parseable.tail_paths.is_empty() {
+        return;
+    }
+    let stream_name = CONFIG.parseable.tail_stream.clone();
+    let format = CONFIG.parseable.tail_format;
+    let mut tailer = Tailer::load(CONFIG.staging_dir().join(CHECKPOINT_FILE_NAME));
+    log::info!(
+        "tailing files {:?} into {}",
+        CONFIG.parseable.tail_paths,
+        stream_name
+    );
+
+    loop {
+        let delay = match tailer.poll(&stream_name, format).await {
+            Ok(_) => POLL_INTERVAL,
+            Err(err) => {
+                log::error!(
+                    "failed to tail files into {}, retrying. {}",
+                    stream_name,
+                    err
+                );
+                RETRY_DELAY
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+struct Tailer {
+    checkpoint_path: PathBuf,
+    files: HashMap<FileId, TailedFile>,
+}
+
+impl Tailer {
+    fn load(checkpoint_path: PathBuf) -> Self {
+        let checkpoint = match fs::read(&checkpoint_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+                log::warn!("ignoring invalid tail checkpoint. {:?}", err);
+                Checkpoint::default()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Checkpoint::default(),
+            Err(err) => {
+                log::warn!("could not read tail checkpoint. {:?}", err);
+                Checkpoint::default()
+            }
+        };
+        let files = checkpoint
+            .files
+            .into_iter()
+            .map(|position| (position.id, resume(position)))
+            .collect();
+        Self {
+            checkpoint_path,
+            files,
+        }
+    }
+
+    async fn poll(&mut self, stream_name: &str, format: TailFormat) -> anyhow::Result<()> {
+        let patterns = CONFIG.parseable.tail_paths.clone();
+        let matched = tokio::task::spawn_blocking(move || matched_files(&patterns)).await?;
+
+        // files that were rotated away are read to their end, if they are still open
+        let gone: Vec<FileId> = self
+            .files
+            .keys()
+            .filter(|id| !matched.contains_key(id))
+            .copied()
+            .collect();
+        for id in gone {
+            self.read(id, stream_name, format, true).await?;
+            self.files.remove(&id);
+            self.save()?;
+        }
+
+        for (id, (path, len)) in matched {
+            let tailed = self.files.entry(id).or_insert_with(|| TailedFile {
+                path: path.clone(),
+                offset: 0,
+                fingerprint: None,
+                file: None,
+            });
+            tailed.path = path;
+            if len < tailed.offset {
+                log::warn!(
+                    "{:?} is shorter than what was read of it, reading it from the start",
+                    tailed.path
+                );
+                tailed.offset = 0;
+                tailed.fingerprint = None;
+            }
+            self.read(id, stream_name, format, false).await?;
+        }
+        Ok(())
+    }
+
+    // ingests the lines of the file that are not ingested yet,
+    // up to the last complete line unless the file is read to its end
+    async fn read(
+        &mut self,
+        id: FileId,
+        stream_name: &str,
+        format: TailFormat,
+        to_end: bool,
+    ) -> anyhow::Result<()> {
+        loop {
+            let Some(tailed) = self.files.get_mut(&id) else {
+                return Ok(());
+            };
+            let file = match &tailed.file {
+                Some(file) => file.clone(),
+                None => match open(&tailed.path, id) {
+                    Some(file) => tailed.file.insert(Arc::new(file)).clone(),
+                    None => return Ok(()),
+                },
+            };
+            let offset = tailed.offset;
+            let refresh = tailed
+                .fingerprint
+                .as_ref()
+                .map_or(true, |fingerprint| fingerprint.size < FINGERPRINT_SIZE);
+            let (chunk, fingerprint) = tokio::task::spawn_blocking(move || {
+                let chunk = read_lines(&file, offset, to_end)?;
+                let fingerprint = if refresh && !chunk.is_empty() {
+                    Some(fingerprint(&file, offset + chunk.len() as u64)?)
+                } else {
+                    None
+                };
+                io::Result::Ok((chunk, fingerprint))
+            })
+            .await??;
+            if chunk.is_empty() {
+                return Ok(());
+            }
+
+            let records = match format {
+                TailFormat::Text => text_records(stream_name, &chunk),
+                TailFormat::Json => json_records(&chunk),
+            };
+            let path = tailed.path.to_string_lossy().into_owned();
+            let records = records
+                .into_iter()
+                .map(|mut record| {
+                    record
+                        .entry(FILENAME_KEY.to_owned())
+                        .or_insert_with(|| Value::String(path.clone()));
+                    record
+                })
+                .collect::<Vec<_>>();
+
+            if !records.is_empty() {
+                match push_records(stream_name, records, chunk.len(), &format!("tail {}", path))
+                    .await
+                {
+                    Ok(_) => {}
+                    // the lines will not be accepted on a retry either
+                    Err(err) if err.is_rejection() => log::warn!(
+                        "dropping lines of {} not accepted by {}. {}",
+                        path,
+                        stream_name,
+                        err
+                    ),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+
+            if let Some(tailed) = self.files.get_mut(&id) {
+                tailed.offset = offset + chunk.len() as u64;
+                if fingerprint.is_some() {
+                    tailed.fingerprint = fingerprint;
+                }
+            }
+            self.save()?;
+        }
+    }
+
+    // writes the checkpoint to a temporary file first, so that it is never left half written
+    fn save(&self) -> io::Result<()> {
+        let checkpoint = Checkpoint {
+            files: self
+                .files
+                .iter()
+                .map(|(id, tailed)| Position {
+                    id: *id,
+                    path: tailed.path.clone(),
+                    offset: tailed.offset,
+                    fingerprint: tailed.fingerprint.clone(),
+                })
+                .collect(),
+        };
+        let temp_path = self.checkpoint_path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_vec(&checkpoint)?)?;
+        fs::rename(temp_path, &self.checkpoint_path)
+    }
+}
+
+// regular files matching any of the globs along with their size
+fn matched_files(patterns: &[String]) -> HashMap<FileId, (PathBuf, u64)> {
+    let mut files = HashMap::new();
+    for pattern in patterns {
+        let paths = match glob::glob(pattern) {
+            Ok(paths) => paths,
+            Err(err) => {
+                log::warn!("invalid tail glob {}. {}", pattern, err);
+                continue;
+            }
+        };
+        for path in paths.flatten() {
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            if metadata.is_file() {
+                files
+                    .entry(file_id(&metadata))
+                    .or_insert((path, metadata.len()));
+            }
+        }
+    }
+    files
+}
+
+// opens the file at the path, if it is still the file that was tailed
+fn open(path: &Path, id: FileId) -> Option<File> {
+    let file = File::open(path).ok()?;
+    let metadata = file.metadata().ok()?;
+    (file_id(&metadata) == id).then_some(file)
+}
+
+// Tailed file of a checkpointed position. A file that was renamed while the server was
+// down, such as app.log rotated to app.log.1, is found by its inode in the same directory.
+// A file with a different fingerprint only reuses the inode and is read from its start
+fn resume(position: Position) -> TailedFile {
+    let mut tailed = TailedFile {
+        path: position.path,
+        offset: position.offset,
+        fingerprint: position.fingerprint,
+        file: None,
+    };
+    let Some((path, file)) = locate(&tailed.path, position.id) else {
+        return tailed;
+    };
+    if path != tailed.path {
+        log::info!(
+            "{:?} was renamed to {:?}, tailing it there",
+            tailed.path,
+            path
+        );
+        tailed.path = path;
+    }
+    if let Some(expected) = &tailed.fingerprint {
+        if fingerprint(&file, expected.size).ok().as_ref() != Some(expected) {
+            log::warn!(
+                "{:?} is not the file that was tailed before, reading it from the start",
+                tailed.path
+            );
+            tailed.offset = 0;
+            tailed.fingerprint = None;
+        }
+    }
+    tailed.file = Some(Arc::new(file));
+    tailed
+}
+
+fn locate(path: &Path, id: FileId) -> Option<(PathBuf, File)> {
+    if let Some(file) = open(path, id) {
+        return Some((path.to_owned(), file));
+    }
+    path.parent()?
+        .read_dir()
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| fs::metadata(path).map_or(false, |metadata| file_id(&metadata) == id))
+        .find_map(|path| open(&path, id).map(|file| (path, file)))
+}
+
+// fingerprint of the first bytes of the file, up to the given size
+fn fingerprint(file: &File, size: u64) -> io::Result<Fingerprint> {
+    let mut file = file;
+    file.seek(SeekFrom::Start(0))?;
+    let mut buf = Vec::new();
+    file.take(size.min(FINGERPRINT_SIZE))
+        .read_to_end(&mut buf)?;
+    Ok(Fingerprint {
+        size: buf.len() as u64,
+        hash: hex::encode(Sha256::digest(&buf)),
+    })
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> FileId {
+    use std::os::unix::fs::MetadataExt;
+    FileId {
+        dev: metadata.dev(),
+        inode: metadata.ino(),
+    }
+}
+
+// without inodes, files at the same path are told apart by their creation time
+#[cfg(not(unix))]
+fn file_id(metadata: &fs::Metadata) -> FileId {
+    let created = metadata
+        .created()
+        .ok()
+        .and_then(|created| created.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |created| created.as_nanos() as u64);
+    FileId {
+        dev: 0,
+        inode: created,
+    }
+}
+
+// Reads up to a chunk of the file from the offset, ending at the last complete line.
+// A line longer than a chunk is split, while a partial line at the end of the file
+// is left for when the rest of it is written, unless the file is read to its end.
+fn read_lines(file: &File, offset: u64, to_end: bool) -> io::Result<Vec<u8>> {
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::new();
+    file.take(MAX_CHUNK_SIZE as u64).read_to_end(&mut buf)?;
+
+    match buf.iter().rposition(|b| *b == b'\n') {
+        _ if to_end => {}
+        Some(newline) => buf.truncate(newline + 1),
+        None if buf.len() == MAX_CHUNK_SIZE => {}
+        None => buf.clear(),
+    }
+    Ok(buf)
+}
+
+fn text_records(stream_name: &str, chunk: &[u8]) -> Vec<BTreeMap<String, Value>> {
+    let text_parser = STREAM_INFO
+        .text_parser(stream_name)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    text_parser
+        .parse(&String::from_utf8_lossy(chunk))
+        .into_iter()
+        .filter_map(|record| match record {
+            Value::Object(record) => Some(record.into_iter().collect()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn json_records(chunk: &[u8]) -> Vec<BTreeMap<String, Value>> {
+    String::from_utf8_lossy(chunk)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(
+            |line| match serde_json::from_str::<Map<String, Value>>(line) {
+                Ok(record) => record.into_iter().collect(),
+                Err(_) => {
+                    BTreeMap::from([(MESSAGE_KEY.to_owned(), Value::String(line.to_owned()))])
+                }
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File, OpenOptions};
+    use std::io::Write;
+
+    use serde_json::json;
+
+    use super::{
+        file_id, fingerprint, json_records, matched_files, read_lines, resume, Position,
+        MAX_CHUNK_SIZE,
+    };
+
+    #[test]
+    fn only_complete_lines_are_read() {
+        let dir = std::env::temp_dir().join(format!("parseable-tail-{}", ulid::Ulid::new()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        fs::write(&path, "first\nsecond\nthi").unwrap();
+
+        let file = File::open(&path).unwrap();
+        assert_eq!(read_lines(&file, 0, false).unwrap(), b"first\nsecond\n");
+        assert_eq!(read_lines(&file, 13, false).unwrap(), b"");
+        assert_eq!(read_lines(&file, 13, true).unwrap(), b"thi");
+
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"rd\n")
+            .unwrap();
+        assert_eq!(read_lines(&file, 13, false).unwrap(), b"third\n");
+
+        // lines longer than a chunk are split
+        fs::write(&path, vec![b'a'; MAX_CHUNK_SIZE + 10]).unwrap();
+        assert_eq!(read_lines(&file, 0, false).unwrap().len(), MAX_CHUNK_SIZE);
+
+        let matched = matched_files(&[format!("{}/*.log", dir.display())]);
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched.values().next().unwrap().0, path);
+        assert!(matched_files(&[format!("{}/*.txt", dir.display())]).is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn files_are_resumed_after_a_restart() {
+        let dir = std::env::temp_dir().join(format!("parseable-tail-{}", ulid::Ulid::new()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        fs::write(&path, "first\nsecond\n").unwrap();
+        let file = File::open(&path).unwrap();
+        let position = Position {
+            id: file_id(&file.metadata().unwrap()),
+            path: path.clone(),
+            offset: 6,
+            fingerprint: Some(fingerprint(&file, 6).unwrap()),
+        };
+
+        // renamed while the server was down
+        let rotated = dir.join("app.log.1");
+        fs::rename(&path, &rotated).unwrap();
+        let tailed = resume(position.clone());
+        assert_eq!(tailed.path, rotated);
+        assert_eq!(tailed.offset, 6);
+        assert!(tailed.file.is_some());
+
+        // same inode with other contents
+        fs::write(&rotated, "other\nlines\n").unwrap();
+        let tailed = resume(position.clone());
+        assert_eq!(tailed.offset, 0);
+        assert!(tailed.fingerprint.is_none());
+
+        // deleted
+        fs::remove_file(&rotated).unwrap();
+        let tailed = resume(position);
+        assert_eq!(tailed.path, path);
+        assert!(tailed.file.is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn json_lines_into_records() {
+        let records = json_records(b"{\"a\": 1}\n\nnot json\n{\"b\": \"x\"}\n");
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["a"], json!(1));
+        assert_eq!(records[1]["message"], json!("not json"));
+        assert_eq!(records[2]["b"], json!("x"));
+    }
+}
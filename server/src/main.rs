@@ -40,6 +40,7 @@ mod migration;
 mod oidc;
 mod option;
 mod query;
+mod rate_limit;
 mod rbac;
 mod response;
 mod stats;
@@ -91,6 +92,20 @@ async fn main() -> anyhow::Result<()> {
     }
 
     tokio::spawn(handlers::livetail::server());
+    tokio::spawn(storage::watermark::monitor());
+    if CONFIG.parseable.syslog_address.is_some() {
+        tokio::spawn(handlers::syslog::server());
+    }
+    if CONFIG.parseable.forward_address.is_some() {
+        tokio::spawn(handlers::forward::server());
+    }
+    if !CONFIG.parseable.tail_paths.is_empty() {
+        tokio::spawn(handlers::tail::tailer());
+    }
+    #[cfg(feature = "kafka")]
+    if CONFIG.parseable.kafka_brokers.is_some() {
+        tokio::spawn(handlers::kafka::consumer());
+    }
 
     let app = handlers::http::run_http(prometheus, CONFIG.parseable.openid.clone());
     tokio::pin!(app);
@@ -24,7 +24,17 @@ use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
 use crate::alerts::Alerts;
+use crate::event::dead_letter::DeadLetter;
+use crate::event::dedup::Dedup;
+use crate::event::durability::Durability;
+use crate::event::evolution::merge_schemas;
+use crate::event::nested::NestedObjects;
+use crate::event::processor::Processor;
+use crate::event::static_schema::SchemaPolicy;
+use crate::event::text_parser::TextParser;
+use crate::event::time_partition::TimePartition;
 use crate::metrics::{EVENTS_INGESTED, EVENTS_INGESTED_SIZE};
+use crate::rate_limit::RateLimit;
 use crate::storage::{ObjectStorage, StorageDir};
 use crate::utils::arrow::MergedRecordReader;
 
@@ -43,6 +53,17 @@ pub struct LogStreamMetadata {
     pub schema: HashMap<String, Arc<Field>>,
     pub alerts: Alerts,
     pub cache_enabled: bool,
+    pub time_partition: Option<TimePartition>,
+    // set when the stream has a static schema, events are then checked
+    // against the schema instead of extending it
+    pub static_schema_policy: Option<SchemaPolicy>,
+    pub processors: Vec<Processor>,
+    pub text_parser: Option<TextParser>,
+    pub rate_limit: Option<RateLimit>,
+    pub dedup: Option<Dedup>,
+    pub durability: Option<Durability>,
+    pub nested_objects: Option<NestedObjects>,
+    pub dead_letter: Option<DeadLetter>,
 }
 
 // It is very unlikely that panic will occur when dealing with metadata.
@@ -97,6 +118,155 @@ impl StreamInfo {
         Ok(())
     }
 
+    pub fn processors(&self, stream_name: &str) -> Result<Vec<Processor>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.processors.clone())
+    }
+
+    pub fn set_processors(
+        &self,
+        stream_name: &str,
+        processors: Vec<Processor>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.processors = processors;
+        Ok(())
+    }
+
+    pub fn text_parser(&self, stream_name: &str) -> Result<Option<TextParser>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.text_parser.clone())
+    }
+
+    pub fn set_text_parser(
+        &self,
+        stream_name: &str,
+        text_parser: Option<TextParser>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.text_parser = text_parser;
+        Ok(())
+    }
+
+    pub fn rate_limit(&self, stream_name: &str) -> Result<Option<RateLimit>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.rate_limit.clone())
+    }
+
+    pub fn set_rate_limit(
+        &self,
+        stream_name: &str,
+        rate_limit: Option<RateLimit>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.rate_limit = rate_limit;
+        Ok(())
+    }
+
+    pub fn dedup(&self, stream_name: &str) -> Result<Option<Dedup>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.dedup.clone())
+    }
+
+    pub fn set_dedup(&self, stream_name: &str, dedup: Option<Dedup>) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.dedup = dedup;
+        Ok(())
+    }
+
+    pub fn durability(&self, stream_name: &str) -> Result<Option<Durability>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.durability)
+    }
+
+    pub fn set_durability(
+        &self,
+        stream_name: &str,
+        durability: Option<Durability>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.durability = durability;
+        Ok(())
+    }
+
+    pub fn dead_letter(&self, stream_name: &str) -> Result<Option<DeadLetter>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.dead_letter.clone())
+    }
+
+    pub fn set_dead_letter(
+        &self,
+        stream_name: &str,
+        dead_letter: Option<DeadLetter>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.dead_letter = dead_letter;
+        Ok(())
+    }
+
+    pub fn nested_objects(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<NestedObjects>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.nested_objects.clone())
+    }
+
+    pub fn set_nested_objects(
+        &self,
+        stream_name: &str,
+        nested_objects: Option<NestedObjects>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.nested_objects = nested_objects;
+        Ok(())
+    }
+
+    pub fn time_partition(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<TimePartition>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.time_partition.clone())
+    }
+
     pub fn schema(&self, stream_name: &str) -> Result<Arc<Schema>, MetadataError> {
         let map = self.read().expect(LOCK_EXPECT);
         let schema = map
@@ -126,9 +296,22 @@ impl StreamInfo {
             })
     }
 
-    pub fn add_stream(&self, stream_name: String) {
+    pub fn add_stream(
+        &self,
+        stream_name: String,
+        schema: &Schema,
+        time_partition: Option<TimePartition>,
+        static_schema_policy: Option<SchemaPolicy>,
+    ) {
         let mut map = self.write().expect(LOCK_EXPECT);
         let metadata = LogStreamMetadata {
+            schema: schema
+                .fields()
+                .iter()
+                .map(|field| (field.name().to_owned(), field.clone()))
+                .collect(),
+            time_partition,
+            static_schema_policy,
             ..Default::default()
         };
         map.insert(stream_name, metadata);
@@ -162,6 +345,15 @@ impl StreamInfo {
                 schema,
                 alerts,
                 cache_enabled: meta.cache_enabled,
+                time_partition: meta.time_partition,
+                static_schema_policy: meta.static_schema_policy,
+                processors: meta.processors,
+                text_parser: meta.text_parser,
+                rate_limit: meta.rate_limit,
+                dedup: meta.dedup,
+                durability: meta.durability,
+                nested_objects: meta.nested_objects,
+                dead_letter: meta.dead_letter,
             };
 
             let mut map = self.write().expect(LOCK_EXPECT);
@@ -203,7 +395,7 @@ fn update_schema_from_staging(stream_name: &str, current_schema: Schema) -> Sche
         .unwrap()
         .merged_schema();
 
-    Schema::try_merge(vec![schema, current_schema]).unwrap()
+    merge_schemas(vec![schema, current_schema]).unwrap()
 }
 
 pub mod error {
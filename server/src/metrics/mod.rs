@@ -20,7 +20,9 @@ pub mod storage;
 
 use actix_web_prometheus::{PrometheusMetrics, PrometheusMetricsBuilder};
 use once_cell::sync::Lazy;
-use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+};
 
 use crate::{handlers::http::metrics_path, metadata::STREAM_INFO, option::CONFIG};
 
@@ -83,6 +85,36 @@ pub static ALERTS_STATES: Lazy<IntCounterVec> = Lazy::new(|| {
     .expect("metric can be created")
 });
 
+pub static STAGING_DISK_USAGE: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::with_opts(
+        Opts::new(
+            "staging_disk_usage",
+            "Used space of the staging disk in percent",
+        )
+        .namespace(METRICS_NAMESPACE),
+    )
+    .expect("metric can be created")
+});
+
+pub static THROTTLED_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("throttled_requests", "Requests rejected by rate limits")
+            .namespace(METRICS_NAMESPACE),
+        &["stream", "limit"],
+    )
+    .expect("metric can be created")
+});
+
+#[cfg(feature = "kafka")]
+pub static KAFKA_CONSUMER_LAG: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new("kafka_consumer_lag", "Kafka consumer lag in messages")
+            .namespace(METRICS_NAMESPACE),
+        &["topic", "partition"],
+    )
+    .expect("metric can be created")
+});
+
 fn custom_metrics(registry: &Registry) {
     registry
         .register(Box::new(EVENTS_INGESTED.clone()))
@@ -105,6 +137,16 @@ fn custom_metrics(registry: &Registry) {
     registry
         .register(Box::new(ALERTS_STATES.clone()))
         .expect("metric can be registered");
+    registry
+        .register(Box::new(STAGING_DISK_USAGE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(THROTTLED_REQUESTS.clone()))
+        .expect("metric can be registered");
+    #[cfg(feature = "kafka")]
+    registry
+        .register(Box::new(KAFKA_CONSUMER_LAG.clone()))
+        .expect("metric can be registered");
 }
 
 pub fn build_metrics_handler() -> PrometheusMetrics {
@@ -24,8 +24,11 @@ use parquet::basic::{BrotliLevel, GzipLevel, ZstdLevel};
 use std::env;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
 
+use crate::event::durability::Durability;
+use crate::handlers::tail::TailFormat;
 use crate::oidc::{self, OpenidConfig};
 use crate::storage::{FSConfig, ObjectStorageProvider, S3Config};
 use crate::utils::validate_path_is_writeable;
@@ -229,6 +232,61 @@ pub struct Server {
 
     /// Parquet compression algorithm
     pub parquet_compression: Compression,
+
+    /// When the events of a request count as persisted, for streams without a level of their own
+    pub durability: Durability,
+
+    /// Interval of group commits for streams without an interval of their own
+    pub group_commit_interval: Duration,
+
+    /// Used percent of the staging disk above which events are rejected, off when not set
+    pub staging_soft_watermark: Option<u8>,
+
+    /// Used percent of the staging disk above which events are rejected and,
+    /// with staging_drop_oldest, the oldest staged data is dropped. Off when not set
+    pub staging_hard_watermark: Option<u8>,
+
+    /// Whether staged data not yet uploaded is dropped above the hard watermark
+    pub staging_drop_oldest: bool,
+
+    /// Seconds for which idempotency keys and dedup field values are remembered
+    pub dedup_window: u64,
+
+    /// Most idempotency keys and dedup field values remembered per stream
+    pub dedup_capacity: usize,
+
+    /// Address for the syslog listener, disabled when not set
+    pub syslog_address: Option<String>,
+
+    /// Stream that syslog messages are ingested into
+    pub syslog_stream: String,
+
+    /// Address for the Fluentd Forward protocol listener, disabled when not set
+    pub forward_address: Option<String>,
+
+    /// Globs of the local files to tail, tailing is disabled when not set
+    pub tail_paths: Vec<String>,
+
+    /// Stream that lines of tailed files are ingested into
+    pub tail_stream: String,
+
+    /// Whether lines of tailed files are JSON objects or plain text
+    pub tail_format: TailFormat,
+
+    /// Directory that parquet files are imported from, import is disabled when not set
+    pub import_dir: Option<PathBuf>,
+
+    /// Kafka bootstrap servers, the consumer is disabled when not set
+    #[cfg(feature = "kafka")]
+    pub kafka_brokers: Option<String>,
+
+    /// Kafka topics to consume, as topic or topic:stream
+    #[cfg(feature = "kafka")]
+    pub kafka_topics: Vec<String>,
+
+    /// Kafka consumer group id
+    #[cfg(feature = "kafka")]
+    pub kafka_group: String,
 }
 
 impl FromArgMatches for Server {
@@ -297,6 +355,74 @@ impl FromArgMatches for Server {
             .get_one::<usize>(Self::ROW_GROUP_SIZE)
             .cloned()
             .expect("default for row_group size");
+        self.staging_soft_watermark = m.get_one::<u8>(Self::STAGING_SOFT_WATERMARK).cloned();
+        self.staging_hard_watermark = m.get_one::<u8>(Self::STAGING_HARD_WATERMARK).cloned();
+        self.staging_drop_oldest = m
+            .get_one::<bool>(Self::STAGING_DROP_OLDEST)
+            .cloned()
+            .expect("default for staging drop oldest");
+        if let (Some(soft), Some(hard)) = (self.staging_soft_watermark, self.staging_hard_watermark)
+        {
+            if soft > hard {
+                return Err(clap::Error::raw(
+                    ErrorKind::ArgumentConflict,
+                    "staging soft watermark can not be above the hard watermark\n",
+                ));
+            }
+        }
+        if self.staging_drop_oldest && self.staging_hard_watermark.is_none() {
+            return Err(clap::Error::raw(
+                ErrorKind::ArgumentConflict,
+                "staging data can only be dropped above a staging hard watermark\n",
+            ));
+        }
+        self.durability = m
+            .get_one::<String>(Self::DURABILITY)
+            .and_then(|level| Durability::from_level(level))
+            .expect("default for durability");
+        self.group_commit_interval = m
+            .get_one::<u64>(Self::GROUP_COMMIT_INTERVAL)
+            .map(|ms| Duration::from_millis(*ms))
+            .expect("default for group commit interval");
+        self.dedup_window = m
+            .get_one::<u64>(Self::DEDUP_WINDOW)
+            .cloned()
+            .expect("default for dedup window");
+        self.dedup_capacity = m
+            .get_one::<usize>(Self::DEDUP_CAPACITY)
+            .cloned()
+            .expect("default for dedup capacity");
+        self.syslog_address = m.get_one::<String>(Self::SYSLOG_ADDRESS).cloned();
+        self.syslog_stream = m
+            .get_one::<String>(Self::SYSLOG_STREAM)
+            .cloned()
+            .expect("default for syslog stream");
+        self.forward_address = m.get_one::<String>(Self::FORWARD_ADDRESS).cloned();
+        self.tail_paths = m
+            .get_many::<String>(Self::TAIL_PATHS)
+            .map(|paths| paths.cloned().collect())
+            .unwrap_or_default();
+        self.tail_stream = m
+            .get_one::<String>(Self::TAIL_STREAM)
+            .cloned()
+            .expect("default for tail stream");
+        self.tail_format = m
+            .get_one::<String>(Self::TAIL_FORMAT)
+            .and_then(|format| TailFormat::from_format(format))
+            .expect("default for tail format");
+        self.import_dir = m.get_one::<PathBuf>(Self::IMPORT_DIR).cloned();
+        #[cfg(feature = "kafka")]
+        {
+            self.kafka_brokers = m.get_one::<String>(Self::KAFKA_BROKERS).cloned();
+            self.kafka_topics = m
+                .get_many::<String>(Self::KAFKA_TOPICS)
+                .map(|topics| topics.cloned().collect())
+                .unwrap_or_default();
+            self.kafka_group = m
+                .get_one::<String>(Self::KAFKA_GROUP)
+                .cloned()
+                .expect("default for kafka group");
+        }
         self.parquet_compression = match m
             .get_one::<String>(Self::PARQUET_COMPRESSION_ALGO)
             .expect("default for compression algo")
@@ -360,6 +486,26 @@ impl Server {
     pub const QUERY_MEM_POOL_SIZE: &'static str = "query-mempool-size";
     pub const ROW_GROUP_SIZE: &'static str = "row-group-size";
     pub const PARQUET_COMPRESSION_ALGO: &'static str = "compression-algo";
+    pub const STAGING_SOFT_WATERMARK: &'static str = "staging-soft-watermark";
+    pub const STAGING_HARD_WATERMARK: &'static str = "staging-hard-watermark";
+    pub const STAGING_DROP_OLDEST: &'static str = "staging-drop-oldest";
+    pub const DURABILITY: &'static str = "durability";
+    pub const GROUP_COMMIT_INTERVAL: &'static str = "group-commit-interval";
+    pub const DEDUP_WINDOW: &'static str = "dedup-window";
+    pub const DEDUP_CAPACITY: &'static str = "dedup-capacity";
+    pub const SYSLOG_ADDRESS: &'static str = "syslog-addr";
+    pub const SYSLOG_STREAM: &'static str = "syslog-stream";
+    pub const FORWARD_ADDRESS: &'static str = "forward-addr";
+    pub const TAIL_PATHS: &'static str = "tail-paths";
+    pub const TAIL_STREAM: &'static str = "tail-stream";
+    pub const TAIL_FORMAT: &'static str = "tail-format";
+    pub const IMPORT_DIR: &'static str = "import-dir";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_BROKERS: &'static str = "kafka-brokers";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_TOPICS: &'static str = "kafka-topics";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_GROUP: &'static str = "kafka-group";
     pub const DEFAULT_USERNAME: &'static str = "admin";
     pub const DEFAULT_PASSWORD: &'static str = "admin";
 
@@ -573,12 +719,179 @@ impl Server {
                         "lz4",
                         "zstd"])
                     .help("Parquet compression algorithm"),
+            )
+            .arg(
+                Arg::new(Self::STAGING_SOFT_WATERMARK)
+                    .long(Self::STAGING_SOFT_WATERMARK)
+                    .env("P_STAGING_SOFT_WATERMARK")
+                    .value_name("PERCENT")
+                    .required(false)
+                    .value_parser(value_parser!(u8).range(1..=100))
+                    .help("Used percent of the staging disk above which new events are rejected, off when not set"),
+            )
+            .arg(
+                Arg::new(Self::STAGING_HARD_WATERMARK)
+                    .long(Self::STAGING_HARD_WATERMARK)
+                    .env("P_STAGING_HARD_WATERMARK")
+                    .value_name("PERCENT")
+                    .required(false)
+                    .value_parser(value_parser!(u8).range(1..=100))
+                    .help("Used percent of the staging disk above which new events are rejected and, with --staging-drop-oldest, staged data is dropped. Off when not set"),
+            )
+            .arg(
+                Arg::new(Self::STAGING_DROP_OLDEST)
+                    .long(Self::STAGING_DROP_OLDEST)
+                    .env("P_STAGING_DROP_OLDEST")
+                    .value_name("BOOL")
+                    .default_value("false")
+                    .required(false)
+                    .value_parser(value_parser!(bool))
+                    .help("Drop the oldest staged data not yet uploaded, across all streams, when the staging disk is above the hard watermark"),
+            )
+            .arg(
+                Arg::new(Self::DURABILITY)
+                    .long(Self::DURABILITY)
+                    .env("P_DURABILITY")
+                    .value_name("LEVEL")
+                    .default_value("none")
+                    .required(false)
+                    .value_parser(["none", "fsync-per-request", "group-commit"])
+                    .help("When ingested events are acknowledged: once written (none), once synced to disk (fsync-per-request) or once synced by the next group commit (group-commit)"),
+            )
+            .arg(
+                Arg::new(Self::GROUP_COMMIT_INTERVAL)
+                    .long(Self::GROUP_COMMIT_INTERVAL)
+                    .env("P_GROUP_COMMIT_INTERVAL")
+                    .value_name("MILLISECONDS")
+                    .default_value("10")
+                    .required(false)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Interval at which the staging files are synced to disk for group-commit durability"),
+            )
+            .arg(
+                Arg::new(Self::DEDUP_WINDOW)
+                    .long(Self::DEDUP_WINDOW)
+                    .env("P_DEDUP_WINDOW")
+                    .value_name("SECONDS")
+                    .default_value("600")
+                    .required(false)
+                    .value_parser(value_parser!(u64))
+                    .help("Seconds for which idempotency keys and dedup field values are remembered"),
+            )
+            .arg(
+                Arg::new(Self::DEDUP_CAPACITY)
+                    .long(Self::DEDUP_CAPACITY)
+                    .env("P_DEDUP_CAPACITY")
+                    .value_name("NUMBER")
+                    .default_value("100000")
+                    .required(false)
+                    .value_parser(validation::dedup_capacity)
+                    .help("Most idempotency keys and dedup field values remembered per stream"),
+            )
+            .arg(
+                Arg::new(Self::SYSLOG_ADDRESS)
+                    .long(Self::SYSLOG_ADDRESS)
+                    .env("P_SYSLOG_ADDR")
+                    .value_name("ADDR:PORT")
+                    .required(false)
+                    .value_parser(validation::socket_addr)
+                    .help("Address and port to listen for syslog messages on, over both TCP and UDP"),
+            )
+            .arg(
+                Arg::new(Self::SYSLOG_STREAM)
+                    .long(Self::SYSLOG_STREAM)
+                    .env("P_SYSLOG_STREAM")
+                    .value_name("STRING")
+                    .default_value("syslog")
+                    .required(false)
+                    .help("Stream to ingest syslog messages into"),
+            )
+            .arg(
+                Arg::new(Self::FORWARD_ADDRESS)
+                    .long(Self::FORWARD_ADDRESS)
+                    .env("P_FORWARD_ADDR")
+                    .value_name("ADDR:PORT")
+                    .required(false)
+                    .value_parser(validation::socket_addr)
+                    .help("Address and port to listen for Fluentd / Fluent Bit forward messages on"),
+            )
+            .arg(
+                Arg::new(Self::TAIL_PATHS)
+                    .long(Self::TAIL_PATHS)
+                    .env("P_TAIL_PATHS")
+                    .value_name("GLOB,...")
+                    .required(false)
+                    .value_delimiter(',')
+                    .value_parser(validation::glob_pattern)
+                    .help("Local files to tail and ingest line by line, e.g. /var/log/app/*.log"),
+            )
+            .arg(
+                Arg::new(Self::TAIL_STREAM)
+                    .long(Self::TAIL_STREAM)
+                    .env("P_TAIL_STREAM")
+                    .value_name("STRING")
+                    .default_value("files")
+                    .required(false)
+                    .help("Stream to ingest the lines of tailed files into"),
+            )
+            .arg(
+                Arg::new(Self::TAIL_FORMAT)
+                    .long(Self::TAIL_FORMAT)
+                    .env("P_TAIL_FORMAT")
+                    .value_name("FORMAT")
+                    .default_value("text")
+                    .required(false)
+                    .value_parser(["text", "json"])
+                    .help("Format of the lines of tailed files, plain text parsed with the parser of the stream or a JSON object per line"),
+            )
+            .arg(
+                Arg::new(Self::IMPORT_DIR)
+                    .long(Self::IMPORT_DIR)
+                    .env("P_IMPORT_DIR")
+                    .value_name("DIR")
+                    .required(false)
+                    .value_parser(validation::canonicalize_path)
+                    .help("Local path on this device that parquet files can be imported into streams from, import is disabled when not set"),
             ).group(
                 ArgGroup::new("oidc")
                     .args([Self::OPENID_CLIENT_ID, Self::OPENID_CLIENT_SECRET, Self::OPENID_ISSUER])
                     .requires_all([Self::OPENID_CLIENT_ID, Self::OPENID_CLIENT_SECRET, Self::OPENID_ISSUER])
                     .multiple(true)
         )
+        .args(Self::kafka_args())
+    }
+
+    #[cfg(feature = "kafka")]
+    fn kafka_args() -> Vec<Arg> {
+        vec![
+            Arg::new(Self::KAFKA_BROKERS)
+                .long(Self::KAFKA_BROKERS)
+                .env("P_KAFKA_BROKERS")
+                .value_name("HOST:PORT,...")
+                .required(false)
+                .requires(Self::KAFKA_TOPICS)
+                .help("Kafka bootstrap servers to consume topics from"),
+            Arg::new(Self::KAFKA_TOPICS)
+                .long(Self::KAFKA_TOPICS)
+                .env("P_KAFKA_TOPICS")
+                .value_name("TOPIC[:STREAM],...")
+                .required(false)
+                .value_delimiter(',')
+                .value_parser(validation::kafka_topic)
+                .help("Kafka topics to consume, each into the stream of the same name unless one is given"),
+            Arg::new(Self::KAFKA_GROUP)
+                .long(Self::KAFKA_GROUP)
+                .env("P_KAFKA_GROUP")
+                .value_name("STRING")
+                .default_value("parseable")
+                .required(false)
+                .help("Kafka consumer group of this server"),
+        ]
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    fn kafka_args() -> Vec<Arg> {
+        Vec::new()
     }
 }
 
@@ -661,6 +974,21 @@ pub mod validation {
             .ok_or_else(|| "Socket Address for server is invalid".to_string())
     }
 
+    #[cfg(feature = "kafka")]
+    pub fn kafka_topic(s: &str) -> Result<String, String> {
+        let (topic, stream) = s.split_once(':').unwrap_or((s, s));
+        if topic.is_empty() || stream.is_empty() {
+            return Err("Kafka topic and stream names can not be empty".to_string());
+        }
+        Ok(s.to_string())
+    }
+
+    pub fn glob_pattern(s: &str) -> Result<String, String> {
+        glob::Pattern::new(s)
+            .map(|_| s.to_string())
+            .map_err(|err| format!("Invalid glob {s}, {err}"))
+    }
+
     pub fn url(s: &str) -> Result<url::Url, String> {
         url::Url::parse(s).map_err(|_| "Invalid URL provided".to_string())
     }
@@ -708,4 +1036,12 @@ pub mod validation {
         }
         Ok(u)
     }
+
+    pub fn dedup_capacity(s: &str) -> Result<usize, String> {
+        match s.parse::<usize>() {
+            Ok(0) => Err("dedup capacity must be 1 or more".to_string()),
+            Ok(capacity) => Ok(capacity),
+            Err(_) => Err("invalid dedup capacity".to_string()),
+        }
+    }
 }
@@ -17,11 +17,13 @@
  */
 
 mod filter_optimizer;
+pub mod functions;
 mod listing_table_builder;
 mod stream_schema_provider;
 
 use chrono::{DateTime, Utc};
 use chrono::{NaiveDateTime, TimeZone};
+use datafusion::arrow::datatypes::{DataType, Schema};
 use datafusion::arrow::record_batch::RecordBatch;
 
 use datafusion::common::tree_node::{Transformed, TreeNode, TreeNodeVisitor, VisitRecursion};
@@ -99,7 +101,10 @@ impl Query {
             )
             .unwrap();
 
-        SessionContext::new_with_state(state)
+        let ctx = SessionContext::new_with_state(state);
+        ctx.register_udf(functions::map_get());
+        ctx.register_udf(functions::map_contains());
+        ctx
     }
 
     pub async fn execute(&self) -> Result<(Vec<RecordBatch>, Vec<String>), ExecuteError> {
@@ -121,7 +126,7 @@ impl Query {
 
     /// return logical plan with all time filters applied through
     fn final_logical_plan(&self) -> LogicalPlan {
-        let filters = self.filter_tag.clone().and_then(tag_filter);
+        let filters = self.filter_tag.clone();
         // see https://github.com/apache/arrow-datafusion/pull/8400
         // this can be eliminated in later version of datafusion but with slight caveat
         // transform cannot modify stringified plans by itself
@@ -180,12 +185,29 @@ impl TreeNodeVisitor for TableScanVisitor {
     }
 }
 
-fn tag_filter(filters: Vec<String>) -> Option<Expr> {
+// Tags of a reader privilege match exactly, env=prod matches the tag env with the
+// value prod. A tag without = matches a tag with that key or that value, so that
+// privileges such as prod, made when tags were matched by substring, keep their
+// access to the events tagged env=prod. Streams that still store tags as key=value
+// strings are matched by substring.
+fn tag_filter(filters: &[String], schema: &Schema) -> Option<Expr> {
+    let tags = Expr::Column(Column::from_name(event::DEFAULT_TAGS_KEY));
+    let tags_as_string = schema
+        .field_with_name(event::DEFAULT_TAGS_KEY)
+        .is_ok_and(|field| field.data_type() == &DataType::Utf8);
+
     filters
         .iter()
-        .map(|literal| {
-            Expr::Column(Column::from_name(event::DEFAULT_TAGS_KEY))
-                .like(lit(format!("%{}%", literal)))
+        .map(|tag| {
+            if tags_as_string {
+                return tags.clone().like(lit(format!("%{}%", tag)));
+            }
+            match tag.split_once('=') {
+                Some((key, value)) => functions::map_get()
+                    .call(vec![tags.clone(), lit(key)])
+                    .eq(lit(value)),
+                None => functions::map_contains().call(vec![tags.clone(), lit(tag.as_str())]),
+            }
         })
         .reduce(or)
 }
@@ -194,7 +216,7 @@ fn transform(
     plan: LogicalPlan,
     start_time: NaiveDateTime,
     end_time: NaiveDateTime,
-    filters: Option<Expr>,
+    filters: Option<Vec<String>>,
 ) -> LogicalPlan {
     plan.transform(&|plan| match plan {
         LogicalPlan::TableScan(table) => {
@@ -216,7 +238,10 @@ fn transform(
                 new_filters.push(end_time_filter);
             }
 
-            if let Some(tag_filters) = filters.clone() {
+            if let Some(tag_filters) = filters
+                .as_ref()
+                .and_then(|filters| tag_filter(filters, &table.source.schema()))
+            {
                 new_filters.push(tag_filters)
             }
 
@@ -305,8 +330,79 @@ pub mod error {
 
 #[cfg(test)]
 mod tests {
-    use super::time_from_path;
+    use super::{tag_filter, time_from_path};
     use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use arrow_array::builder::{MapBuilder, StringBuilder};
+    use arrow_array::{Array, Int64Array, RecordBatch};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::prelude::SessionContext;
+
+    use crate::event;
+
+    // ids of the rows of a stream with tags stored as a map that the tags of a privilege match
+    async fn matched(tags: &[&str]) -> Vec<i64> {
+        let mut builder = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+        for row in [vec![("env", "prod")], vec![("env", "preprod")], vec![]] {
+            for (key, value) in row {
+                builder.keys().append_value(key);
+                builder.values().append_value(value);
+            }
+            builder.append(true).unwrap();
+        }
+        let tags_column = builder.finish();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new(
+                event::DEFAULT_TAGS_KEY,
+                tags_column.data_type().clone(),
+                true,
+            ),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2, 3])),
+                Arc::new(tags_column),
+            ],
+        )
+        .unwrap();
+
+        let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+        let filter = tag_filter(&tags, &schema).unwrap();
+        let ctx = SessionContext::new();
+        let batches = ctx
+            .read_batch(batch)
+            .unwrap()
+            .filter(filter)
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn tags_of_privileges_match_map_tags() {
+        assert_eq!(matched(&["env=prod"]).await, [1]);
+        assert_eq!(matched(&["env"]).await, [1, 2]);
+        // privileges made when tags were matched by substring keep their access
+        assert_eq!(matched(&["prod"]).await, [1]);
+        assert_eq!(matched(&["preprod", "env=prod"]).await, [1, 2]);
+        assert!(matched(&["team"]).await.is_empty());
+    }
 
     #[test]
     fn test_time_from_parquet_path() {
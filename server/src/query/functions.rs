@@ -0,0 +1,212 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::{Array, ArrayRef, BooleanArray, StringArray};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::logical_expr::{
+    ColumnarValue, ReturnTypeFunction, ScalarFunctionImplementation, ScalarUDF, Signature,
+    Volatility,
+};
+use datafusion::scalar::ScalarValue;
+
+pub const MAP_GET: &str = "map_get";
+pub const MAP_CONTAINS: &str = "map_contains";
+
+// map_get(map, key) is the value of the key in a Map<Utf8, Utf8> column, such
+// as p_tags and p_metadata, or null if the map does not have the key
+// select * from app where map_get(p_tags, 'env') = 'prod'
+pub fn map_get() -> ScalarUDF {
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Utf8)));
+    let fun: ScalarFunctionImplementation = Arc::new(map_get_values);
+    ScalarUDF::new(
+        MAP_GET,
+        &Signature::any(2, Volatility::Immutable),
+        &return_type,
+        &fun,
+    )
+}
+
+// map_contains(map, text) is true if the text is one of the keys or the values of a
+// Map<Utf8, Utf8> column, such as p_tags
+// select * from app where map_contains(p_tags, 'prod')
+pub fn map_contains() -> ScalarUDF {
+    let return_type: ReturnTypeFunction = Arc::new(|_| Ok(Arc::new(DataType::Boolean)));
+    let fun: ScalarFunctionImplementation = Arc::new(map_contains_values);
+    ScalarUDF::new(
+        MAP_CONTAINS,
+        &Signature::any(2, Volatility::Immutable),
+        &return_type,
+        &fun,
+    )
+}
+
+fn map_get_values(args: &[ColumnarValue]) -> Result<ColumnarValue> {
+    let (maps, strings, all_scalars) = map_args(args);
+    let values: StringArray = map_rows(MAP_GET, &maps, &strings, |keys, values, entries, key| {
+        entries
+            .into_iter()
+            .find(|&entry| keys.value(entry) == key)
+            .filter(|&entry| values.is_valid(entry))
+            .map(|entry| values.value(entry))
+    })?
+    .into_iter()
+    .collect();
+    into_columnar(Arc::new(values), all_scalars)
+}
+
+fn map_contains_values(args: &[ColumnarValue]) -> Result<ColumnarValue> {
+    let (maps, strings, all_scalars) = map_args(args);
+    let values: BooleanArray = map_rows(
+        MAP_CONTAINS,
+        &maps,
+        &strings,
+        |keys, values, mut entries, text| {
+            Some(entries.any(|entry| {
+                keys.value(entry) == text || (values.is_valid(entry) && values.value(entry) == text)
+            }))
+        },
+    )?
+    .into_iter()
+    .collect();
+    into_columnar(Arc::new(values), all_scalars)
+}
+
+// the map and string arguments as arrays, along with whether both are scalars
+fn map_args(args: &[ColumnarValue]) -> (ArrayRef, ArrayRef, bool) {
+    let num_rows = args
+        .iter()
+        .find_map(|arg| match arg {
+            ColumnarValue::Array(array) => Some(array.len()),
+            ColumnarValue::Scalar(_) => None,
+        })
+        .unwrap_or(1);
+    let all_scalars = args
+        .iter()
+        .all(|arg| matches!(arg, ColumnarValue::Scalar(_)));
+    (
+        args[0].clone().into_array(num_rows),
+        args[1].clone().into_array(num_rows),
+        all_scalars,
+    )
+}
+
+// Calls the function with the keys and values of the entries of the map and the
+// string of every row, rows where either of them is null are null
+fn map_rows<'a, T>(
+    name: &str,
+    maps: &'a ArrayRef,
+    strings: &'a ArrayRef,
+    fun: impl Fn(&'a StringArray, &'a StringArray, Range<usize>, &'a str) -> Option<T>,
+) -> Result<Vec<Option<T>>> {
+    let maps = maps.as_map_opt().ok_or_else(|| {
+        DataFusionError::Execution(format!("{name} expects a map as the first argument"))
+    })?;
+    let strings = strings.as_string_opt::<i32>().ok_or_else(|| {
+        DataFusionError::Execution(format!("{name} expects a string as the second argument"))
+    })?;
+    let (Some(entry_keys), Some(entry_values)) = (
+        maps.keys().as_string_opt::<i32>(),
+        maps.values().as_string_opt::<i32>(),
+    ) else {
+        return Err(DataFusionError::Execution(format!(
+            "{name} expects a map of strings"
+        )));
+    };
+
+    let offsets = maps.value_offsets();
+    Ok((0..strings.len())
+        .map(|row| {
+            if maps.is_null(row) || strings.is_null(row) {
+                return None;
+            }
+            let entries = offsets[row] as usize..offsets[row + 1] as usize;
+            fun(entry_keys, entry_values, entries, strings.value(row))
+        })
+        .collect())
+}
+
+fn into_columnar(values: ArrayRef, all_scalars: bool) -> Result<ColumnarValue> {
+    if all_scalars {
+        return Ok(ColumnarValue::Scalar(ScalarValue::try_from_array(
+            &values, 0,
+        )?));
+    }
+    Ok(ColumnarValue::Array(values))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::builder::{MapBuilder, StringBuilder};
+    use arrow_array::cast::AsArray;
+    use arrow_array::{BooleanArray, StringArray};
+    use datafusion::logical_expr::ColumnarValue;
+    use datafusion::scalar::ScalarValue;
+
+    use super::{map_contains_values, map_get_values};
+
+    #[test]
+    fn values_of_keys() {
+        let mut builder = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+        builder.keys().append_value("env");
+        builder.values().append_value("prod");
+        builder.keys().append_value("team");
+        builder.values().append_value("api");
+        builder.append(true).unwrap();
+        builder.keys().append_value("env");
+        builder.values().append_value("preprod");
+        builder.append(true).unwrap();
+        builder.append(true).unwrap();
+        builder.append(false).unwrap();
+        let maps = Arc::new(builder.finish());
+
+        let values = map_get_values(&[
+            ColumnarValue::Array(maps.clone()),
+            ColumnarValue::Scalar(ScalarValue::Utf8(Some("env".to_owned()))),
+        ])
+        .unwrap()
+        .into_array(4);
+        assert_eq!(
+            values.as_string::<i32>(),
+            &StringArray::from(vec![Some("prod"), Some("preprod"), None, None])
+        );
+
+        let contains = map_contains_values(&[
+            ColumnarValue::Array(maps.clone()),
+            ColumnarValue::Scalar(ScalarValue::Utf8(Some("prod".to_owned()))),
+        ])
+        .unwrap()
+        .into_array(4);
+        assert_eq!(
+            contains.as_boolean(),
+            &BooleanArray::from(vec![Some(true), Some(false), Some(false), None])
+        );
+
+        assert!(map_get_values(&[
+            ColumnarValue::Scalar(ScalarValue::Utf8(Some("env".to_owned()))),
+            ColumnarValue::Array(maps),
+        ])
+        .is_err());
+    }
+}
@@ -228,7 +228,7 @@ fn partitioned_files(
                 .entry(col.name)
                 .and_modify(|x| {
                     if let Some((stats, col_stats)) = x.as_ref().cloned().zip(col.stats.clone()) {
-                        *x = Some(stats.update(col_stats));
+                        *x = stats.update(col_stats);
                     }
                 })
                 .or_insert_with(|| col.stats.as_ref().cloned());
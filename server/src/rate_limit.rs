@@ -0,0 +1,365 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::THROTTLED_REQUESTS;
+
+pub static RATE_LIMITER: Lazy<RateLimiter> = Lazy::new(RateLimiter::default);
+
+// buckets that are not used for this long, and are full again, are dropped. These are
+// mostly of sessions, which get a bucket of their own and are not used again once they end
+const IDLE_BUCKET_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Ingestion limits of a stream, set with PUT /logstream/{name}/ratelimit
+// {
+//     "stream": { "events_per_sec": 10000, "bytes_per_sec": 10485760 },
+//     "credential": { "events_per_sec": 1000 }
+// }
+// The stream limit is shared by all requests to the stream, while the credential limit
+// applies to the requests of every user (or session) to the stream on its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<Limit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<Limit>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events_per_sec: Option<NonZeroU64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_per_sec: Option<NonZeroU64>,
+}
+
+// A request that went over a limit and how long to wait before sending it again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttled {
+    // stream or credential
+    pub limit: &'static str,
+    pub retry_after_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Unit {
+    Events,
+    Bytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BucketKey {
+    stream: String,
+    // None for the bucket of the stream limit
+    credential: Option<String>,
+    unit: Unit,
+}
+
+// Token bucket that holds up to a second worth of its rate.
+// A request larger than that is let through when the bucket is full and leaves it in debt,
+// so that it is throttled for as long as the request would take at the rate.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    rate: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    // a bucket that has been refilled since it was last used is no different from a new one
+    fn is_idle(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated);
+        elapsed >= IDLE_BUCKET_TIMEOUT
+            && self.tokens + elapsed.as_secs_f64() * self.rate >= self.rate
+    }
+
+    fn refill(&mut self, rate: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.updated = now;
+    }
+
+    // seconds until the amount can be taken from the bucket
+    fn wait(&self, rate: f64, amount: f64) -> f64 {
+        let needed = amount.min(rate);
+        if self.tokens >= needed {
+            0.0
+        } else {
+            (needed - self.tokens) / rate
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<BucketKey, Bucket>,
+    // last time idle buckets were dropped
+    evicted: Option<Instant>,
+}
+
+impl Buckets {
+    fn evict_idle(&mut self, now: Instant) {
+        if self.evicted.map_or(false, |evicted| {
+            now.saturating_duration_since(evicted) < IDLE_BUCKET_TIMEOUT
+        }) {
+            return;
+        }
+        self.buckets.retain(|_, bucket| !bucket.is_idle(now));
+        self.evicted = Some(now);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter(Mutex<Buckets>);
+
+impl RateLimiter {
+    // takes the events and bytes of a request from the buckets of the stream and the credential.
+    // Nothing is taken if any of the buckets is short, the request is throttled instead.
+    pub fn acquire(
+        &self,
+        stream_name: &str,
+        credential: Option<&str>,
+        rate_limit: &RateLimit,
+        events: u64,
+        bytes: u64,
+    ) -> Result<(), Throttled> {
+        self.acquire_at(
+            stream_name,
+            credential,
+            rate_limit,
+            events,
+            bytes,
+            Instant::now(),
+        )
+    }
+
+    fn acquire_at(
+        &self,
+        stream_name: &str,
+        credential: Option<&str>,
+        rate_limit: &RateLimit,
+        events: u64,
+        bytes: u64,
+        now: Instant,
+    ) -> Result<(), Throttled> {
+        let mut limits = Vec::with_capacity(4);
+        for (limit_name, limit, credential) in [
+            ("stream", rate_limit.stream, None),
+            ("credential", rate_limit.credential, credential),
+        ] {
+            let Some(limit) = limit else {
+                continue;
+            };
+            // requests without credentials only count towards the stream limit
+            if limit_name == "credential" && credential.is_none() {
+                continue;
+            }
+            for (unit, rate, amount) in [
+                (Unit::Events, limit.events_per_sec, events),
+                (Unit::Bytes, limit.bytes_per_sec, bytes),
+            ] {
+                if let Some(rate) = rate {
+                    let key = BucketKey {
+                        stream: stream_name.to_owned(),
+                        credential: credential.map(str::to_owned),
+                        unit,
+                    };
+                    limits.push((limit_name, key, rate.get() as f64, amount as f64));
+                }
+            }
+        }
+
+        let mut buckets = self.0.lock().unwrap();
+        buckets.evict_idle(now);
+        let buckets = &mut buckets.buckets;
+        let mut throttled: Option<(&'static str, f64)> = None;
+        for (limit_name, key, rate, amount) in &limits {
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: *rate,
+                rate: *rate,
+                updated: now,
+            });
+            bucket.refill(*rate, now);
+            let wait = bucket.wait(*rate, *amount);
+            if wait > throttled.map_or(0.0, |(_, wait)| wait) {
+                throttled = Some((limit_name, wait));
+            }
+        }
+
+        if let Some((limit, wait)) = throttled {
+            THROTTLED_REQUESTS
+                .with_label_values(&[stream_name, limit])
+                .inc();
+            return Err(Throttled {
+                limit,
+                retry_after_secs: (wait.ceil() as u64).max(1),
+            });
+        }
+
+        for (_, key, _, amount) in limits {
+            if let Some(bucket) = buckets.get_mut(&key) {
+                bucket.tokens -= amount;
+            }
+        }
+        Ok(())
+    }
+
+    // drops the buckets of a stream, for when its limits change or it is deleted
+    pub fn reset(&self, stream_name: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .buckets
+            .retain(|key, _| key.stream != stream_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use serde_json::json;
+
+    use super::{RateLimit, RateLimiter, Throttled};
+
+    fn rate_limit(value: serde_json::Value) -> RateLimit {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn stream_limit_refills_over_time() {
+        let limiter = RateLimiter::default();
+        let limit =
+            rate_limit(json!({ "stream": { "events_per_sec": 100, "bytes_per_sec": 1000 } }));
+        let start = Instant::now();
+
+        assert!(limiter
+            .acquire_at("app", None, &limit, 60, 100, start)
+            .is_ok());
+        assert_eq!(
+            limiter.acquire_at("app", None, &limit, 60, 100, start),
+            Err(Throttled {
+                limit: "stream",
+                retry_after_secs: 1
+            })
+        );
+        // a throttled request takes nothing from the buckets
+        assert!(limiter
+            .acquire_at("app", None, &limit, 40, 900, start)
+            .is_ok());
+        assert!(limiter
+            .acquire_at("app", None, &limit, 60, 100, start + Duration::from_secs(1))
+            .is_ok());
+        // other streams have buckets of their own
+        assert!(limiter
+            .acquire_at("other", None, &limit, 100, 1000, start)
+            .is_ok());
+    }
+
+    #[test]
+    fn credential_limit_is_per_credential() {
+        let limiter = RateLimiter::default();
+        let limit = rate_limit(json!({ "credential": { "events_per_sec": 10 } }));
+        let now = Instant::now();
+
+        assert!(limiter
+            .acquire_at("app", Some("alice"), &limit, 10, 0, now)
+            .is_ok());
+        assert_eq!(
+            limiter
+                .acquire_at("app", Some("alice"), &limit, 1, 0, now)
+                .unwrap_err()
+                .limit,
+            "credential"
+        );
+        assert!(limiter
+            .acquire_at("app", Some("bob"), &limit, 10, 0, now)
+            .is_ok());
+        assert!(limiter.acquire_at("app", None, &limit, 100, 0, now).is_ok());
+
+        limiter.reset("app");
+        assert!(limiter
+            .acquire_at("app", Some("alice"), &limit, 10, 0, now)
+            .is_ok());
+    }
+
+    #[test]
+    fn large_request_goes_into_debt() {
+        let limiter = RateLimiter::default();
+        let limit = rate_limit(json!({ "stream": { "bytes_per_sec": 1000 } }));
+        let now = Instant::now();
+
+        assert!(limiter
+            .acquire_at("app", None, &limit, 1, 3000, now)
+            .is_ok());
+        assert_eq!(
+            limiter
+                .acquire_at("app", None, &limit, 1, 10, now)
+                .unwrap_err()
+                .retry_after_secs,
+            3
+        );
+    }
+
+    #[test]
+    fn idle_buckets_are_dropped() {
+        let limiter = RateLimiter::default();
+        let limit = rate_limit(json!({ "credential": { "bytes_per_sec": 1000 } }));
+        let start = Instant::now();
+
+        assert!(limiter
+            .acquire_at("app", Some("session"), &limit, 1, 1000, start)
+            .is_ok());
+        assert!(limiter
+            .acquire_at("app", Some("debtor"), &limit, 1, 20000, start)
+            .is_ok());
+        let buckets = |limiter: &RateLimiter| limiter.0.lock().unwrap().buckets.len();
+        assert_eq!(buckets(&limiter), 2);
+
+        // buckets still in debt are kept until they are refilled
+        let later = start + Duration::from_secs(6);
+        assert!(limiter
+            .acquire_at("app", Some("alice"), &limit, 1, 10, later)
+            .is_ok());
+        assert_eq!(buckets(&limiter), 2);
+        assert!(limiter
+            .acquire_at("app", Some("debtor"), &limit, 1, 10, later)
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_limits_are_err() {
+        for value in [
+            json!({ "stream": { "events_per_sec": 0 } }),
+            json!({ "stream": { "events_per_sec": -1 } }),
+            json!({ "user": { "events_per_sec": 10 } }),
+            json!({ "stream": { "events": 10 } }),
+        ] {
+            assert!(serde_json::from_value::<RateLimit>(value).is_err());
+        }
+    }
+}
@@ -31,6 +31,21 @@ pub enum Action {
     PutRetention,
     GetCacheEnabled,
     PutCacheEnabled,
+    GetProcessors,
+    PutProcessors,
+    GetTextParser,
+    PutTextParser,
+    GetRateLimit,
+    PutRateLimit,
+    GetDedup,
+    PutDedup,
+    GetDurability,
+    PutDurability,
+    GetNestedObjects,
+    PutNestedObjects,
+    GetDeadLetter,
+    PutDeadLetter,
+    Import,
     PutAlert,
     GetAlert,
     PutUser,
@@ -105,6 +120,21 @@ impl RoleBuilder {
                 | Action::PutRetention
                 | Action::GetCacheEnabled
                 | Action::PutCacheEnabled
+                | Action::GetProcessors
+                | Action::PutProcessors
+                | Action::GetTextParser
+                | Action::PutTextParser
+                | Action::GetRateLimit
+                | Action::PutRateLimit
+                | Action::GetDedup
+                | Action::PutDedup
+                | Action::GetDurability
+                | Action::PutDurability
+                | Action::GetNestedObjects
+                | Action::PutNestedObjects
+                | Action::GetDeadLetter
+                | Action::PutDeadLetter
+                | Action::Import
                 | Action::PutAlert
                 | Action::GetAlert
                 | Action::All => Permission::Stream(action, self.stream.clone().unwrap()),
@@ -129,6 +159,8 @@ pub mod model {
         Editor,
         Writer { stream: String },
         Ingester { stream: String },
+        // tag limits access to the events with a tag, env=prod or a key or value such as prod,
+        // see query::tag_filter
         Reader { stream: String, tag: Option<String> },
     }
 
@@ -175,6 +207,20 @@ pub mod model {
                 Action::PutRetention,
                 Action::PutCacheEnabled,
                 Action::GetCacheEnabled,
+                Action::GetProcessors,
+                Action::PutProcessors,
+                Action::GetTextParser,
+                Action::PutTextParser,
+                Action::GetRateLimit,
+                Action::PutRateLimit,
+                Action::GetDedup,
+                Action::PutDedup,
+                Action::GetDurability,
+                Action::PutDurability,
+                Action::GetNestedObjects,
+                Action::PutNestedObjects,
+                Action::GetDeadLetter,
+                Action::PutDeadLetter,
                 Action::PutAlert,
                 Action::GetAlert,
                 Action::GetAbout,
@@ -194,6 +240,13 @@ pub mod model {
                 Action::GetSchema,
                 Action::GetStats,
                 Action::GetRetention,
+                Action::GetProcessors,
+                Action::GetTextParser,
+                Action::GetRateLimit,
+                Action::GetDedup,
+                Action::GetDurability,
+                Action::GetNestedObjects,
+                Action::GetDeadLetter,
                 Action::PutAlert,
                 Action::GetAlert,
                 Action::GetAbout,
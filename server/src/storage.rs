@@ -16,12 +16,22 @@
  *
  */
 
-use crate::{catalog::snapshot::Snapshot, stats::Stats};
+use crate::{
+    catalog::snapshot::Snapshot,
+    event::{
+        dead_letter::DeadLetter, dedup::Dedup, durability::Durability, nested::NestedObjects,
+        processor::Processor, static_schema::SchemaPolicy, text_parser::TextParser,
+        time_partition::TimePartition,
+    },
+    rate_limit::RateLimit,
+    stats::Stats,
+};
 
 use chrono::Local;
 
 use std::fmt::Debug;
 
+pub mod import;
 mod localfs;
 mod metrics_layer;
 mod object_storage;
@@ -29,6 +39,7 @@ pub mod retention;
 mod s3;
 pub mod staging;
 mod store_metadata;
+pub mod watermark;
 
 pub use localfs::FSConfig;
 pub use object_storage::{ObjectStorage, ObjectStorageProvider};
@@ -76,6 +87,24 @@ pub struct ObjectStoreFormat {
     pub snapshot: Snapshot,
     #[serde(default)]
     pub cache_enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_partition: Option<TimePartition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub static_schema_policy: Option<SchemaPolicy>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub processors: Vec<Processor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_parser: Option<TextParser>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup: Option<Dedup>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub durability: Option<Durability>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nested_objects: Option<NestedObjects>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_letter: Option<DeadLetter>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -118,6 +147,15 @@ impl Default for ObjectStoreFormat {
             stats: Stats::default(),
             snapshot: Snapshot::default(),
             cache_enabled: false,
+            time_partition: None,
+            static_schema_policy: None,
+            processors: Vec::new(),
+            text_parser: None,
+            rate_limit: None,
+            dedup: None,
+            durability: None,
+            nested_objects: None,
+            dead_letter: None,
         }
     }
 }
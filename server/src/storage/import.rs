@@ -0,0 +1,663 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{Field, Schema};
+use chrono::{DateTime, NaiveDateTime, Timelike, Utc};
+use once_cell::sync::Lazy;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use relative_path::RelativePath;
+use serde::{Deserialize, Serialize};
+
+use crate::catalog;
+use crate::event::format::{self, EventFormat};
+use crate::event::static_schema::{self, SchemaPolicy};
+use crate::event::time_partition::TimePartition;
+use crate::event::{self, DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY};
+use crate::metadata::STREAM_INFO;
+use crate::metrics::STORAGE_SIZE;
+use crate::option::CONFIG;
+use crate::{stats, utils};
+
+use super::object_storage::commit_schema_to_storage;
+use super::staging::parquet_writer_props;
+use super::OBJECT_STORE_DATA_GRANULARITY;
+
+// directory of the staging where files are rewritten before they are uploaded
+const IMPORT_DIR: &str = ".import";
+// a source file is rewritten into a file per minute, at most this many of
+// them are written at once, further minutes start new files
+const MAX_OPEN_FILES: usize = 64;
+// finished jobs that are kept around for their status to be read
+const MAX_FINISHED_JOBS: usize = 100;
+
+// import jobs by id, ids are ulids so that jobs are in the order they were started
+static JOBS: Lazy<Mutex<BTreeMap<String, ImportJob>>> = Lazy::new(Mutex::default);
+
+// Import of parquet files on the disk of the server into a stream, with
+// POST /logstream/{name}/import
+// {
+//     "paths": ["archive/2023/*.parquet"],
+//     "time_field": "timestamp",
+//     "time_format": "%d/%b/%Y:%H:%M:%S %z"
+// }
+// Only files under the import directory of the server (--import-dir) can be imported.
+// The import runs in the background, its progress is read with
+// GET /logstream/{name}/import/{id} with the id in the response.
+// The time of every row is taken from the time field, from the time partition
+// of the stream when not set, or from p_timestamp for files exported from a stream.
+// Files are checked against the schema of the stream like events are, then rewritten
+// into the date=/hour=/minute= layout and added to the manifests of the stream.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportRequest {
+    // globs of parquet files, relative to the import directory
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub time_field: Option<String>,
+    #[serde(default)]
+    pub time_format: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub imported_files: usize,
+    pub imported_rows: u64,
+    // matches of the globs outside of the import directory, they are never imported
+    pub rejected_files: usize,
+    pub failed: Vec<FailedImport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedImport {
+    // relative to the import directory
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Finished,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportJob {
+    pub id: String,
+    pub stream: String,
+    pub status: JobStatus,
+    pub started_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    // files imported so far while the job is running
+    pub report: ImportReport,
+}
+
+impl ImportRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.paths.is_empty() {
+            return Err("import paths can not be empty".to_owned());
+        }
+        for path in &self.paths {
+            glob::Pattern::new(path).map_err(|err| format!("invalid glob {path}, {err}"))?;
+        }
+        match &self.time_field {
+            Some(field) => {
+                TimePartition::new(field.to_owned(), self.time_format.clone()).map(|_| ())
+            }
+            None if self.time_format.is_some() => {
+                Err("time format is set without a time field".to_owned())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn time_partition(&self, stream_partition: Option<TimePartition>) -> TimePartition {
+        match &self.time_field {
+            Some(field) => TimePartition {
+                field: field.to_owned(),
+                format: self.time_format.clone(),
+            },
+            None => stream_partition.unwrap_or(TimePartition {
+                field: DEFAULT_TIMESTAMP_KEY.to_owned(),
+                format: None,
+            }),
+        }
+    }
+
+    // Files matching any of the globs, in order. Globs are relative to the root,
+    // matches that resolve to a path outside of it are counted but left out
+    fn files(&self, root: &Path) -> (BTreeSet<PathBuf>, usize) {
+        let mut files = BTreeSet::new();
+        let mut rejected = 0;
+        let paths = self
+            .paths
+            .iter()
+            .filter_map(|pattern| glob::glob(&root.join(pattern).to_string_lossy()).ok())
+            .flat_map(|paths| paths.flatten());
+        for path in paths {
+            match fs::canonicalize(&path) {
+                Ok(path) if path.starts_with(root) => {
+                    if path.is_file() {
+                        files.insert(path);
+                    }
+                }
+                _ => {
+                    log::warn!(
+                        "not importing {:?}, it is outside of the import directory",
+                        path
+                    );
+                    rejected += 1;
+                }
+            }
+        }
+        (files, rejected)
+    }
+}
+
+// the import directory of the server, None when import is disabled
+pub fn import_dir() -> Option<PathBuf> {
+    let dir = CONFIG.parseable.import_dir.as_ref()?;
+    match fs::canonicalize(dir) {
+        Ok(dir) => Some(dir),
+        Err(err) => {
+            log::warn!("import directory {:?} is not accessible. {}", dir, err);
+            None
+        }
+    }
+}
+
+// starts an import job in the background, returns the id of the job
+pub fn start(stream_name: &str, root: PathBuf, request: ImportRequest) -> String {
+    let id = ulid::Ulid::new().to_string().to_lowercase();
+    JOBS.lock().unwrap().insert(
+        id.clone(),
+        ImportJob {
+            id: id.clone(),
+            stream: stream_name.to_owned(),
+            status: JobStatus::Running,
+            started_at: Utc::now(),
+            finished_at: None,
+            report: ImportReport::default(),
+        },
+    );
+
+    let job_id = id.clone();
+    let stream_name = stream_name.to_owned();
+    tokio::spawn(async move {
+        import(&job_id, &stream_name, &root, request).await;
+        let mut jobs = JOBS.lock().unwrap();
+        if let Some(job) = jobs.get_mut(&job_id) {
+            job.status = JobStatus::Finished;
+            job.finished_at = Some(Utc::now());
+        }
+        let finished: Vec<String> = jobs
+            .values()
+            .filter(|job| job.status == JobStatus::Finished)
+            .map(|job| job.id.clone())
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(MAX_FINISHED_JOBS))
+        {
+            jobs.remove(id);
+        }
+    });
+    id
+}
+
+// an import job into the stream
+pub fn job(stream_name: &str, id: &str) -> Option<ImportJob> {
+    JOBS.lock()
+        .unwrap()
+        .get(id)
+        .filter(|job| job.stream == stream_name)
+        .cloned()
+}
+
+fn update_report(id: &str, f: impl FnOnce(&mut ImportReport)) {
+    if let Some(job) = JOBS.lock().unwrap().get_mut(id) {
+        f(&mut job.report);
+    }
+}
+
+// Imports the files one by one, a file that fails is reported and skipped.
+// Files are imported as a whole unless uploading them fails midway.
+async fn import(id: &str, stream_name: &str, root: &Path, request: ImportRequest) {
+    let out_dir = CONFIG.staging_dir().join(IMPORT_DIR).join(id);
+
+    let (files, rejected) = request.files(root);
+    update_report(id, |report| report.rejected_files = rejected);
+    let mut imported = false;
+    for path in files {
+        match import_file(stream_name, &request, &path, &out_dir).await {
+            Ok(rows) => {
+                imported = true;
+                update_report(id, |report| {
+                    report.imported_files += 1;
+                    report.imported_rows += rows;
+                });
+            }
+            Err(err) => {
+                log::warn!("failed to import {:?} into {}. {}", path, stream_name, err);
+                let path = path.strip_prefix(root).unwrap_or(&path);
+                update_report(id, |report| {
+                    report.failed.push(FailedImport {
+                        path: path.to_string_lossy().into_owned(),
+                        reason: err.to_string(),
+                    })
+                });
+            }
+        }
+    }
+    let _ = fs::remove_dir_all(&out_dir);
+
+    if imported {
+        if let Some(stats) = stats::get_current_stats(stream_name, "json") {
+            let storage = CONFIG.storage().get_object_store();
+            if let Err(err) = storage.put_stats(stream_name, &stats).await {
+                log::warn!("Error updating stats to objectstore due to error [{}]", err);
+            }
+        }
+    }
+}
+
+async fn import_file(
+    stream_name: &str,
+    request: &ImportRequest,
+    path: &Path,
+    out_dir: &Path,
+) -> anyhow::Result<u64> {
+    let (schema, static_schema_policy, time_partition) = {
+        let hash_map = STREAM_INFO.read().unwrap();
+        let stream = hash_map
+            .get(stream_name)
+            .ok_or_else(|| anyhow!("stream {} not found", stream_name))?;
+        // processors run on json records, files would be imported without them
+        if !stream.processors.is_empty() {
+            return Err(anyhow!(
+                "stream {} has processors, which are not applied to imported files",
+                stream_name
+            ));
+        }
+        (
+            stream.schema.clone(),
+            stream.static_schema_policy,
+            request.time_partition(stream.time_partition.clone()),
+        )
+    };
+
+    let source = path.to_owned();
+    let target = out_dir.to_owned();
+    // rows are not sorted by time across the batches of a file
+    let props = parquet_writer_props().set_sorting_columns(None).build();
+    let rewritten = tokio::task::spawn_blocking(move || {
+        rewrite(
+            &source,
+            schema,
+            static_schema_policy,
+            &time_partition,
+            &target,
+            props,
+        )
+    })
+    .await??;
+
+    // the schema is extended before the files show up in the manifests
+    if let Some(schema) = rewritten.new_schema {
+        event::commit_schema(stream_name, schema.clone())?;
+        commit_schema_to_storage(stream_name, schema.as_ref().clone()).await?;
+    }
+
+    let storage = CONFIG.storage().get_object_store();
+    for file in rewritten.files {
+        let filename = file
+            .file_name()
+            .and_then(|filename| filename.to_str())
+            .expect("rewritten files have a valid name");
+        let file_suffix = str::replacen(filename, ".", "/", 3);
+        let stream_relative_path = format!("{stream_name}/{file_suffix}");
+        storage.upload_file(&stream_relative_path, &file).await?;
+        let absolute_path = storage
+            .absolute_url(RelativePath::from_path(&stream_relative_path)?)
+            .to_string();
+        let manifest = catalog::create_from_parquet_file(absolute_path, &file)?;
+        STORAGE_SIZE
+            .with_label_values(&["data", stream_name, "parquet"])
+            .add(manifest.file_size as i64);
+        catalog::update_snapshot(storage.clone(), stream_name, manifest).await?;
+        fs::remove_file(file)?;
+    }
+
+    STREAM_INFO.update_stats(stream_name, "json", rewritten.size, rewritten.rows)?;
+    Ok(rewritten.rows)
+}
+
+struct Rewritten {
+    files: Vec<PathBuf>,
+    rows: u64,
+    // size of the source file
+    size: u64,
+    // schema of the rewritten files, if they add fields to the stream
+    new_schema: Option<Arc<Schema>>,
+}
+
+// Rewrites a parquet file into files of a minute each, with the columns of
+// an event of the stream and p_timestamp set from the time field
+fn rewrite(
+    path: &Path,
+    mut schema: HashMap<String, Arc<Field>>,
+    static_schema_policy: Option<SchemaPolicy>,
+    time_partition: &TimePartition,
+    out_dir: &Path,
+    props: WriterProperties,
+) -> anyhow::Result<Rewritten> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+    fs::create_dir_all(out_dir)?;
+
+    let mut writers: BTreeMap<NaiveDateTime, (PathBuf, ArrowWriter<File>)> = BTreeMap::new();
+    let mut rewritten = Rewritten {
+        files: Vec::new(),
+        rows: 0,
+        size,
+        new_schema: None,
+    };
+
+    for batch in reader {
+        let batch = batch?;
+        if batch.num_rows() == 0 {
+            continue;
+        }
+        let column = batch
+            .column_by_name(&time_partition.field)
+            .ok_or_else(|| anyhow!("time field {} is not in the file", time_partition.field))?;
+        let timestamps = time_partition.timestamps(column)?;
+        let tags = batch.column_by_name(DEFAULT_TAGS_KEY).cloned();
+        let metadata = batch.column_by_name(DEFAULT_METADATA_KEY).cloned();
+
+        let batch = without_reserved_fields(batch)?;
+        let batch = match static_schema_policy {
+            Some(policy) => static_schema::enforce_batch(policy, &schema, batch)
+                .map_err(|reason| anyhow!(reason))?,
+            None => batch,
+        };
+        let event = format::arrow::Event {
+            rb: batch,
+            tags: BTreeMap::default(),
+            metadata: BTreeMap::default(),
+        };
+        let (rb, is_first) = event.into_recordbatch(schema.clone())?;
+        let rb = with_labels(rb, tags, metadata)?;
+        if is_first {
+            let rb_schema = rb.schema();
+            schema.extend(
+                rb_schema
+                    .fields()
+                    .iter()
+                    .map(|field| (field.name().to_owned(), field.clone())),
+            );
+            rewritten.new_schema = Some(rb_schema);
+        }
+
+        rewritten.rows += rb.num_rows() as u64;
+        let (_, batches) = time_partition.split_batch(rb, timestamps)?;
+        for (time, rb) in batches {
+            if !writers.contains_key(&time) && writers.len() >= MAX_OPEN_FILES {
+                close_all(&mut writers, &mut rewritten.files)?;
+            }
+            let (_, writer) = match writers.entry(time) {
+                std::collections::btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::btree_map::Entry::Vacant(entry) => {
+                    let path = out_dir.join(file_name(time));
+                    let writer = ArrowWriter::try_new(
+                        File::create(&path)?,
+                        rb.schema(),
+                        Some(props.clone()),
+                    )?;
+                    entry.insert((path, writer))
+                }
+            };
+            writer.write(&rb)?;
+        }
+    }
+
+    close_all(&mut writers, &mut rewritten.files)?;
+    Ok(rewritten)
+}
+
+fn close_all(
+    writers: &mut BTreeMap<NaiveDateTime, (PathBuf, ArrowWriter<File>)>,
+    files: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    for (_, (path, writer)) in std::mem::take(writers) {
+        writer.close()?;
+        files.push(path);
+    }
+    Ok(())
+}
+
+// name of a rewritten file, the time prefix with dots in place of slashes as for staged files
+// date=2024-01-12.hour=02.minute=33.import.01hm0y3v8ndbk1rs8jzz0szjhv.data.parquet
+fn file_name(time: NaiveDateTime) -> String {
+    let prefix = utils::date_to_prefix(time.date())
+        + &utils::hour_to_prefix(time.hour())
+        + &utils::minute_to_prefix(time.minute(), OBJECT_STORE_DATA_GRANULARITY)
+            .expect("minute is valid");
+    format!(
+        "{}import.{}.data.parquet",
+        prefix.replace('/', "."),
+        ulid::Ulid::new().to_string().to_lowercase()
+    )
+}
+
+// p_timestamp, p_tags and p_metadata are set by the stream
+fn without_reserved_fields(batch: RecordBatch) -> anyhow::Result<RecordBatch> {
+    let indices: Vec<usize> = batch
+        .schema()
+        .fields()
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| {
+            ![
+                DEFAULT_TIMESTAMP_KEY,
+                DEFAULT_TAGS_KEY,
+                DEFAULT_METADATA_KEY,
+            ]
+            .contains(&field.name().as_str())
+        })
+        .map(|(idx, _)| idx)
+        .collect();
+    Ok(batch.project(&indices)?)
+}
+
+// keeps the tags and metadata of files exported from a stream
+fn with_labels(
+    rb: RecordBatch,
+    tags: Option<ArrayRef>,
+    metadata: Option<ArrayRef>,
+) -> anyhow::Result<RecordBatch> {
+    let mut indices = Vec::new();
+    let mut columns = Vec::new();
+    for (name, column) in [(DEFAULT_TAGS_KEY, tags), (DEFAULT_METADATA_KEY, metadata)] {
+        let index = rb.schema().index_of(name)?;
+        if let Some(column) =
+            column.filter(|column| column.data_type() == rb.schema().field(index).data_type())
+        {
+            indices.push(index);
+            columns.push(column);
+        }
+    }
+    if indices.is_empty() {
+        return Ok(rb);
+    }
+    Ok(utils::arrow::replace_columns(
+        rb.schema(),
+        &rb,
+        &indices,
+        &columns,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{self, File};
+    use std::sync::Arc;
+
+    use arrow_array::{Array, ArrayRef, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::DataType;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use parquet::arrow::ArrowWriter;
+    use parquet::file::properties::WriterProperties;
+    use serde_json::json;
+
+    use super::{rewrite, ImportRequest};
+
+    #[test]
+    fn file_is_rewritten_per_minute() {
+        let dir = std::env::temp_dir().join(format!("parseable-import-{}", ulid::Ulid::new()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.parquet");
+        let batch = RecordBatch::try_from_iter([
+            (
+                "ts",
+                Arc::new(StringArray::from(vec![
+                    "2024-01-12T02:33:00.451Z",
+                    "2024-01-12T02:34:10Z",
+                    "2024-01-12T02:33:59Z",
+                ])) as ArrayRef,
+            ),
+            (
+                "status",
+                Arc::new(Int64Array::from(vec![200, 500, 404])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        let mut writer =
+            ArrowWriter::try_new(File::create(&source).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let request: ImportRequest = serde_json::from_value(json!({
+            "paths": [source.to_string_lossy()],
+            "time_field": "ts"
+        }))
+        .unwrap();
+        request.validate().unwrap();
+        let time_partition = request.time_partition(None);
+
+        let rewritten = rewrite(
+            &source,
+            HashMap::default(),
+            None,
+            &time_partition,
+            &dir.join("out"),
+            WriterProperties::default(),
+        )
+        .unwrap();
+        assert_eq!(rewritten.rows, 3);
+        assert!(rewritten.new_schema.is_some());
+
+        let mut names: Vec<String> = rewritten
+            .files
+            .iter()
+            .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 2);
+        assert!(names[0].starts_with("date=2024-01-12.hour=02.minute=33.import."));
+        assert!(names[1].starts_with("date=2024-01-12.hour=02.minute=34.import."));
+
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&rewritten.files[0]).unwrap())
+                .unwrap()
+                .build()
+                .unwrap();
+        let rb = reader.map(Result::unwrap).next().unwrap();
+        assert_eq!(rb.num_rows(), 2);
+        assert_eq!(rb.schema().field(0).name(), "p_timestamp");
+        assert_eq!(rb.column(0).null_count(), 0);
+
+        // files have to match the types of the stream
+        let schema = HashMap::from([(
+            "status".to_owned(),
+            Arc::new(arrow_schema::Field::new("status", DataType::Utf8, true)),
+        )]);
+        assert!(rewrite(
+            &source,
+            schema,
+            None,
+            &time_partition,
+            &dir.join("out"),
+            WriterProperties::default()
+        )
+        .is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_are_confined_to_the_root() {
+        let dir = std::env::temp_dir().join(format!("parseable-import-{}", ulid::Ulid::new()));
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("2023")).unwrap();
+        let root = fs::canonicalize(root).unwrap();
+        File::create(root.join("2023/a.parquet")).unwrap();
+        File::create(dir.join("outside.parquet")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside.parquet"), root.join("link.parquet")).unwrap();
+
+        let request: ImportRequest = serde_json::from_value(json!({
+            "paths": ["2023/*.parquet", "*.parquet", "../*.parquet"]
+        }))
+        .unwrap();
+        let (files, rejected) = request.files(&root);
+        assert_eq!(
+            files.into_iter().collect::<Vec<_>>(),
+            [root.join("2023/a.parquet")]
+        );
+        // the symlink and the file outside of the root
+        assert_eq!(rejected, 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_requests_are_err() {
+        for value in [
+            json!({ "paths": [] }),
+            json!({ "paths": ["/data/[a.parquet"] }),
+            json!({ "paths": ["/data/*.parquet"], "time_format": "%Y" }),
+        ] {
+            let request: ImportRequest = serde_json::from_value(value).unwrap();
+            assert!(request.validate().is_err());
+        }
+        assert!(serde_json::from_value::<ImportRequest>(json!({ "path": "/data" })).is_err());
+    }
+}
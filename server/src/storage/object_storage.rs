@@ -17,13 +17,18 @@
  */
 
 use super::{
-    retention::Retention, staging::convert_disk_files_to_parquet, LogStream, ObjectStorageError,
-    ObjectStoreFormat, Permisssion, StorageDir, StorageMetadata,
+    retention::Retention, staging::convert_disk_files_to_parquet, watermark, LogStream,
+    ObjectStorageError, ObjectStoreFormat, Permisssion, StorageDir, StorageMetadata,
 };
 
 use crate::{
     alerts::Alerts,
     catalog::{self, manifest::Manifest, snapshot::Snapshot},
+    event::{
+        evolution::{self, SchemaVersion},
+        static_schema::SchemaPolicy,
+        time_partition::TimePartition,
+    },
     localcache::LocalCacheManager,
     metadata::STREAM_INFO,
     metrics::{storage::StorageMetrics, STORAGE_SIZE},
@@ -53,6 +58,7 @@ use std::{
 pub(super) const STREAM_METADATA_FILE_NAME: &str = ".stream.json";
 pub(super) const PARSEABLE_METADATA_FILE_NAME: &str = ".parseable.json";
 const SCHEMA_FILE_NAME: &str = ".schema";
+const SCHEMA_HISTORY_FILE_NAME: &str = ".schema_history.json";
 const ALERT_FILE_NAME: &str = ".alert.json";
 const MANIFEST_FILE: &str = "manifest.json";
 
@@ -105,15 +111,23 @@ pub trait ObjectStorage: Sync + 'static {
         Ok(())
     }
 
-    async fn create_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError> {
+    async fn create_stream(
+        &self,
+        stream_name: &str,
+        schema: &Schema,
+        time_partition: Option<TimePartition>,
+        static_schema_policy: Option<SchemaPolicy>,
+    ) -> Result<(), ObjectStorageError> {
         let mut format = ObjectStoreFormat::default();
         format.set_id(CONFIG.parseable.username.clone());
         let permission = Permisssion::new(CONFIG.parseable.username.clone());
         format.permissions = vec![permission];
+        format.time_partition = time_partition;
+        format.static_schema_policy = static_schema_policy;
 
         let format_json = to_bytes(&format);
 
-        self.put_object(&schema_path(stream_name), to_bytes(&Schema::empty()))
+        self.put_object(&schema_path(stream_name), to_bytes(schema))
             .await?;
 
         self.put_object(&stream_json_path(stream_name), format_json)
@@ -173,6 +187,27 @@ pub trait ObjectStorage: Sync + 'static {
         Ok(serde_json::from_slice(&schema_map)?)
     }
 
+    // versions of the schema of a stream, empty until a field of the stream is widened
+    async fn get_schema_history(
+        &self,
+        stream_name: &str,
+    ) -> Result<Vec<SchemaVersion>, ObjectStorageError> {
+        match self.get_object(&schema_history_path(stream_name)).await {
+            Ok(history) => Ok(serde_json::from_slice(&history)?),
+            Err(ObjectStorageError::NoSuchKey(_)) => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn put_schema_history(
+        &self,
+        stream_name: &str,
+        history: &[SchemaVersion],
+    ) -> Result<(), ObjectStorageError> {
+        self.put_object(&schema_history_path(stream_name), to_bytes(history))
+            .await
+    }
+
     async fn get_alerts(&self, stream_name: &str) -> Result<Alerts, ObjectStorageError> {
         match self.get_object(&alert_json_path(stream_name)).await {
             Ok(alerts) => {
@@ -320,6 +355,7 @@ pub trait ObjectStorage: Sync + 'static {
 
         let cache_manager = LocalCacheManager::global();
         let mut cache_updates: HashMap<&String, Vec<_>> = HashMap::new();
+        let mut claims = Vec::new();
 
         for stream in &streams {
             let cache_enabled = STREAM_INFO
@@ -333,7 +369,9 @@ pub trait ObjectStorage: Sync + 'static {
                 commit_schema_to_storage(stream, schema).await?;
             }
 
-            let parquet_files = dir.parquet_files();
+            // the files are not dropped by the staging watermark while they are uploaded
+            let claim = watermark::claim_for_upload(&dir);
+            let parquet_files = claim.files().to_vec();
             parquet_files.iter().for_each(|file| {
                 let compressed_size = file.metadata().map_or(0, |meta| meta.len());
                 stream_stats
@@ -367,6 +405,10 @@ pub trait ObjectStorage: Sync + 'static {
                     let _ = fs::remove_file(file);
                 }
             }
+            // files moved to the cache are kept until they are moved
+            if cache_updates.contains_key(stream) {
+                claims.push(claim);
+            }
         }
 
         for (stream, compressed_size) in stream_stats {
@@ -396,6 +438,7 @@ pub trait ObjectStorage: Sync + 'static {
                             .unwrap()
                     }
                 }
+                drop(claims);
             });
         }
 
@@ -403,13 +446,21 @@ pub trait ObjectStorage: Sync + 'static {
     }
 }
 
-async fn commit_schema_to_storage(
+pub(super) async fn commit_schema_to_storage(
     stream_name: &str,
     schema: Schema,
 ) -> Result<(), ObjectStorageError> {
     let storage = CONFIG.storage().get_object_store();
     let stream_schema = storage.get_schema(stream_name).await?;
-    let new_schema = Schema::try_merge(vec![schema, stream_schema]).unwrap();
+    let new_schema = evolution::merge_schemas(vec![schema, stream_schema.clone()])
+        .map_err(|err| ObjectStorageError::UnhandledError(Box::new(err)))?;
+
+    // a version is kept for every schema that widens fields
+    if !evolution::widened_fields(&stream_schema, &new_schema).is_empty() {
+        let mut history = storage.get_schema_history(stream_name).await?;
+        evolution::add_version(&mut history, &stream_schema, &new_schema);
+        storage.put_schema_history(stream_name, &history).await?;
+    }
     storage.put_schema(stream_name, &new_schema).await
 }
 
@@ -430,6 +481,11 @@ fn stream_json_path(stream_name: &str) -> RelativePathBuf {
     RelativePathBuf::from_iter([stream_name, STREAM_METADATA_FILE_NAME])
 }
 
+#[inline(always)]
+fn schema_history_path(stream_name: &str) -> RelativePathBuf {
+    RelativePathBuf::from_iter([stream_name, SCHEMA_HISTORY_FILE_NAME])
+}
+
 #[inline(always)]
 fn parseable_json_path() -> RelativePathBuf {
     RelativePathBuf::from(PARSEABLE_METADATA_FILE_NAME)
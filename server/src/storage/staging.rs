@@ -19,14 +19,14 @@
 
 use std::{
     collections::HashMap,
-    fs,
+    fs, io,
     path::{Path, PathBuf},
     process,
     sync::Arc,
 };
 
 use arrow_schema::{ArrowError, Schema};
-use chrono::{NaiveDateTime, Timelike, Utc};
+use chrono::{NaiveDateTime, Timelike};
 use parquet::{
     arrow::ArrowWriter,
     basic::Encoding,
@@ -37,7 +37,8 @@ use parquet::{
 };
 
 use crate::{
-    event::DEFAULT_TIMESTAMP_KEY,
+    event::{durability, evolution::merge_schemas, DEFAULT_TIMESTAMP_KEY, STREAM_WRITERS},
+    metadata::STREAM_INFO,
     metrics,
     option::CONFIG,
     storage::OBJECT_STORE_DATA_GRANULARITY,
@@ -68,7 +69,7 @@ impl StorageDir {
         format!("{local_uri}{hostname}.{extention}")
     }
 
-    fn filename_by_time(stream_hash: &str, time: NaiveDateTime) -> String {
+    pub fn filename_by_time(stream_hash: &str, time: NaiveDateTime) -> String {
         format!(
             "{}.{}",
             stream_hash,
@@ -76,16 +77,6 @@ impl StorageDir {
         )
     }
 
-    fn filename_by_current_time(stream_hash: &str) -> String {
-        let datetime = Utc::now();
-        Self::filename_by_time(stream_hash, datetime.naive_utc())
-    }
-
-    pub fn path_by_current_time(&self, stream_hash: &str) -> PathBuf {
-        self.data_path
-            .join(Self::filename_by_current_time(stream_hash))
-    }
-
     pub fn arrow_files(&self) -> Vec<PathBuf> {
         let Ok(dir) = self.data_path.read_dir() else {
             return vec![];
@@ -179,12 +170,36 @@ pub fn convert_disk_files_to_parquet(
     let mut schemas = Vec::new();
 
     let time = chrono::Utc::now().naive_utc();
-    let staging_files = dir.arrow_files_grouped_exclude_time(time);
+    let mut staging_files = dir.arrow_files_grouped_exclude_time(time);
+    // skip groups with files that are still being written to, with a time partition
+    // these are not necessarily the files of the current minute
+    let open_files = STREAM_WRITERS.open_files(stream);
+    staging_files.retain(|_, files| !files.iter().any(|file| open_files.contains(file)));
     if staging_files.is_empty() {
         metrics::STAGING_FILES.with_label_values(&[stream]).set(0);
     }
 
-    for (parquet_path, files) in staging_files {
+    // events of the same minute may arrive in more than one sync cycle when the
+    // stream is partitioned by event time, name every parquet file uniquely so
+    // that the previously uploaded file for this minute is not overwritten
+    let time_partitioned = STREAM_INFO
+        .time_partition(stream)
+        .map_or(false, |partition| partition.is_some());
+
+    for (mut parquet_path, files) in staging_files {
+        if time_partitioned {
+            let filename = parquet_path.file_name().unwrap().to_str().unwrap();
+            let filename = filename
+                .strip_suffix(PARQUET_FILE_EXTENSION)
+                .unwrap_or(filename);
+            parquet_path.set_file_name(format!(
+                "{}{}.{}",
+                filename,
+                ulid::Ulid::new().to_string().to_lowercase(),
+                PARQUET_FILE_EXTENSION
+            ));
+        }
+
         metrics::STAGING_FILES
             .with_label_values(&[stream])
             .set(files.len() as i64);
@@ -202,7 +217,13 @@ pub fn convert_disk_files_to_parquet(
 
         let parquet_file = fs::File::create(&parquet_path).map_err(|_| MoveDataError::Create)?;
 
-        let props = parquet_writer_props().build();
+        // p_timestamp of a time partitioned stream is the event time, which the rows are not
+        // ordered by as they are written in the order they arrive, so the file is not sorted
+        let props = if time_partitioned {
+            parquet_writer_props().set_sorting_columns(None).build()
+        } else {
+            parquet_writer_props().build()
+        };
         let merged_schema = record_reader.merged_schema();
         schemas.push(merged_schema.clone());
         let schema = Arc::new(merged_schema);
@@ -214,6 +235,15 @@ pub fn convert_disk_files_to_parquet(
 
         writer.close()?;
 
+        // the staging files are only removed once the parquet file that replaces them
+        // is on disk, their events may have been acknowledged as durable
+        if durability::is_durable(stream) {
+            fs::File::open(&parquet_path)?.sync_all()?;
+            if let Some(parent) = parquet_path.parent() {
+                sync_dir(parent)?;
+            }
+        }
+
         for file in files {
             if fs::remove_file(file).is_err() {
                 log::error!("Failed to delete file. Unstable state");
@@ -223,13 +253,24 @@ pub fn convert_disk_files_to_parquet(
     }
 
     if !schemas.is_empty() {
-        Ok(Some(Schema::try_merge(schemas).unwrap()))
+        Ok(Some(merge_schemas(schemas).unwrap()))
     } else {
         Ok(None)
     }
 }
 
-fn parquet_writer_props() -> WriterPropertiesBuilder {
+#[cfg(unix)]
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    fs::File::open(path)?.sync_all()
+}
+
+// directories can not be opened to be synced on other platforms
+#[cfg(not(unix))]
+pub fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+pub fn parquet_writer_props() -> WriterPropertiesBuilder {
     WriterProperties::builder()
         .set_max_row_group_size(CONFIG.parseable.row_group_size)
         .set_compression(CONFIG.parseable.parquet_compression.into())
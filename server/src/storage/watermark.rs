@@ -0,0 +1,278 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use once_cell::sync::Lazy;
+use sysinfo::{DiskExt, System, SystemExt};
+
+use crate::metadata::STREAM_INFO;
+use crate::metrics;
+use crate::option::CONFIG;
+
+use super::staging::StorageDir;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+static STATE: AtomicU8 = AtomicU8::new(DiskState::Ok as u8);
+
+// staged parquet files that are being uploaded, they are never dropped
+static UPLOADING: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(Mutex::default);
+
+// How full the disk of the staging directory is, as per --staging-soft-watermark
+// and --staging-hard-watermark, both are off unless set.
+// Above either watermark new events are rejected until the staged data is uploaded.
+// With --staging-drop-oldest, above the hard watermark the oldest staged data that
+// is not uploaded yet is also dropped, so that the most recent data still has room on the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum DiskState {
+    Ok = 0,
+    Soft = 1,
+    Hard = 2,
+}
+
+impl DiskState {
+    fn from_usage(used_percent: f64, soft: Option<u8>, hard: Option<u8>) -> Self {
+        let above = |watermark: Option<u8>| watermark.is_some_and(|w| used_percent >= w as f64);
+        if above(hard) {
+            DiskState::Hard
+        } else if above(soft) {
+            DiskState::Soft
+        } else {
+            DiskState::Ok
+        }
+    }
+}
+
+pub fn state() -> DiskState {
+    match STATE.load(Ordering::Relaxed) {
+        0 => DiskState::Ok,
+        1 => DiskState::Soft,
+        _ => DiskState::Hard,
+    }
+}
+
+// checks that there is room for new events on the staging disk
+pub fn check() -> Result<(), String> {
+    match state() {
+        DiskState::Ok => Ok(()),
+        state => {
+            let watermark = match state {
+                DiskState::Soft => CONFIG.parseable.staging_soft_watermark,
+                _ => CONFIG.parseable.staging_hard_watermark,
+            };
+            Err(format!(
+                "staging disk usage is above {}%, events are not accepted until staged data is uploaded",
+                watermark.unwrap_or_default()
+            ))
+        }
+    }
+}
+
+// checks the staging disk every few seconds, runs for as long as the server
+pub async fn monitor() {
+    loop {
+        if let Err(err) = tokio::task::spawn_blocking(refresh).await {
+            log::error!("staging disk check failed. {:?}", err);
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+fn refresh() {
+    let staging = CONFIG.staging_dir();
+    let Some((total, available)) = disk_space(staging) else {
+        log::warn!("could not find the disk of staging directory {:?}", staging);
+        return;
+    };
+    let used = total.saturating_sub(available);
+    let used_percent = used as f64 * 100.0 / total as f64;
+    metrics::STAGING_DISK_USAGE.set(used_percent.round() as i64);
+
+    let soft = CONFIG.parseable.staging_soft_watermark;
+    let hard = CONFIG.parseable.staging_hard_watermark;
+    let state = DiskState::from_usage(used_percent, soft, hard);
+    let previous = STATE.swap(state as u8, Ordering::Relaxed);
+    if previous != state as u8 {
+        log::warn!(
+            "staging disk usage is {:.1}%, disk state is now {:?}",
+            used_percent,
+            state
+        );
+    }
+
+    if let (DiskState::Hard, Some(hard), true) = (state, hard, CONFIG.parseable.staging_drop_oldest)
+    {
+        let target = total * hard as u64 / 100;
+        drop_oldest_staged(used.saturating_sub(target));
+    }
+}
+
+// Staged parquet files of a stream claimed for upload, they are not dropped
+// until the claim is dropped
+pub struct UploadClaim {
+    files: Vec<PathBuf>,
+}
+
+impl UploadClaim {
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+}
+
+impl Drop for UploadClaim {
+    fn drop(&mut self) {
+        let mut uploading = UPLOADING.lock().unwrap();
+        for file in &self.files {
+            uploading.remove(file);
+        }
+    }
+}
+
+// lists the staged parquet files of a stream and claims them for upload
+pub fn claim_for_upload(dir: &StorageDir) -> UploadClaim {
+    let mut uploading = UPLOADING.lock().unwrap();
+    let files = dir.parquet_files();
+    uploading.extend(files.iter().cloned());
+    UploadClaim { files }
+}
+
+// deletes the oldest parquet files waiting in staging to be uploaded, across all
+// streams, until at least the given number of bytes are freed
+fn drop_oldest_staged(mut bytes: u64) {
+    let mut files: Vec<(SystemTime, u64, String, PathBuf)> = STREAM_INFO
+        .list_streams()
+        .into_iter()
+        .flat_map(|stream| {
+            StorageDir::new(&stream)
+                .parquet_files()
+                .into_iter()
+                .map(move |file| (stream.clone(), file))
+        })
+        .filter_map(|(stream, file)| {
+            let metadata = file.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), stream, file))
+        })
+        .collect();
+    files.sort();
+
+    for (_, size, stream, file) in files {
+        if bytes == 0 {
+            break;
+        }
+        // files are checked and removed under the lock, a file is either claimed
+        // for upload before it is dropped here or is not listed for upload at all
+        let uploading = UPLOADING.lock().unwrap();
+        if uploading.contains(&file) {
+            continue;
+        }
+        match fs::remove_file(&file) {
+            Ok(_) => {
+                log::error!(
+                    "staging disk is above the hard watermark, dropped staged file {:?} of stream {} with {} bytes",
+                    file,
+                    stream,
+                    size
+                );
+                bytes = bytes.saturating_sub(size);
+            }
+            Err(err) => log::error!("failed to drop staged file {:?}. {:?}", file, err),
+        }
+    }
+}
+
+// total and available bytes of the disk the path is on
+fn disk_space(path: &Path) -> Option<(u64, u64)> {
+    let path = path.canonicalize().ok()?;
+    let mut system = System::new();
+    system.refresh_disks_list();
+    let disks = system
+        .disks()
+        .iter()
+        .map(|disk| {
+            (
+                disk.mount_point().to_path_buf(),
+                disk.total_space(),
+                disk.available_space(),
+            )
+        })
+        .collect::<Vec<_>>();
+    disk_of(&path, &disks).filter(|(total, _)| *total > 0)
+}
+
+// the disk with the longest mount point that the path is under
+fn disk_of(path: &Path, disks: &[(PathBuf, u64, u64)]) -> Option<(u64, u64)> {
+    disks
+        .iter()
+        .filter(|(mount_point, _, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _, _)| mount_point.components().count())
+        .map(|(_, total, available)| (*total, *available))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{disk_of, DiskState};
+
+    #[test]
+    fn usage_maps_to_state() {
+        assert_eq!(
+            DiskState::from_usage(50.0, Some(85), Some(95)),
+            DiskState::Ok
+        );
+        assert_eq!(
+            DiskState::from_usage(85.0, Some(85), Some(95)),
+            DiskState::Soft
+        );
+        assert_eq!(
+            DiskState::from_usage(99.5, Some(85), Some(95)),
+            DiskState::Hard
+        );
+        assert_eq!(
+            DiskState::from_usage(90.0, Some(90), Some(90)),
+            DiskState::Hard
+        );
+        // watermarks are off unless set
+        assert_eq!(DiskState::from_usage(99.5, None, None), DiskState::Ok);
+        assert_eq!(DiskState::from_usage(99.5, Some(85), None), DiskState::Soft);
+        assert_eq!(DiskState::from_usage(99.5, None, Some(95)), DiskState::Hard);
+    }
+
+    #[test]
+    fn path_is_on_the_innermost_disk() {
+        let disks = vec![
+            (PathBuf::from("/"), 100, 10),
+            (PathBuf::from("/var"), 200, 20),
+            (PathBuf::from("/var/lib/parseable"), 300, 30),
+        ];
+        assert_eq!(
+            disk_of(Path::new("/var/lib/parseable/staging"), &disks),
+            Some((300, 30))
+        );
+        assert_eq!(disk_of(Path::new("/var/log"), &disks), Some((200, 20)));
+        assert_eq!(disk_of(Path::new("/tmp"), &disks), Some((100, 10)));
+        assert_eq!(disk_of(Path::new("/tmp"), &disks[1..]), None);
+    }
+}
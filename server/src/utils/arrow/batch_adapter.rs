@@ -17,8 +17,9 @@
  */
 
 use datafusion::arrow::array::new_null_array;
-use datafusion::arrow::array::ArrayRef;
-use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::array::{Array, ArrayRef, ListArray, StructArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Schema};
 use datafusion::arrow::record_batch::RecordBatch;
 
 use std::sync::Arc;
@@ -30,6 +31,8 @@ use std::sync::Arc;
 // log stream schema.
 // This is necessary because all the record batches in a log
 // stream need to have all the fields.
+// Columns of fields that have since been widened are cast to the type of the stream,
+// struct columns, also those in lists, get nulls for the children added to the struct since.
 pub fn adapt_batch(table_schema: &Schema, batch: &RecordBatch) -> RecordBatch {
     let batch_schema = &*batch.schema();
     let batch_cols = batch.columns().to_vec();
@@ -37,7 +40,10 @@ pub fn adapt_batch(table_schema: &Schema, batch: &RecordBatch) -> RecordBatch {
     let mut cols: Vec<ArrayRef> = Vec::with_capacity(table_schema.fields().len());
     for table_field in table_schema.fields() {
         if let Some((batch_idx, _)) = batch_schema.column_with_name(table_field.name().as_str()) {
-            cols.push(Arc::clone(&batch_cols[batch_idx]));
+            cols.push(adapt_column(
+                &batch_cols[batch_idx],
+                table_field.data_type(),
+            ));
         } else {
             cols.push(new_null_array(table_field.data_type(), batch.num_rows()))
         }
@@ -46,3 +52,84 @@ pub fn adapt_batch(table_schema: &Schema, batch: &RecordBatch) -> RecordBatch {
     let merged_schema = Arc::new(table_schema.clone());
     RecordBatch::try_new(merged_schema, cols).unwrap()
 }
+
+fn adapt_column(col: &ArrayRef, data_type: &DataType) -> ArrayRef {
+    if col.data_type() == data_type {
+        return Arc::clone(col);
+    }
+    if let (DataType::Struct(fields), Some(array)) =
+        (data_type, col.as_any().downcast_ref::<StructArray>())
+    {
+        let children = fields
+            .iter()
+            .map(|field| match array.column_by_name(field.name()) {
+                Some(child) => adapt_column(child, field.data_type()),
+                None => new_null_array(field.data_type(), array.len()),
+            })
+            .collect();
+        if let Ok(array) = StructArray::try_new(fields.clone(), children, array.nulls().cloned()) {
+            return Arc::new(array);
+        }
+    }
+    if let (DataType::List(item), Some(array)) =
+        (data_type, col.as_any().downcast_ref::<ListArray>())
+    {
+        let values = adapt_column(array.values(), item.data_type());
+        if let Ok(array) = ListArray::try_new(
+            item.clone(),
+            array.offsets().clone(),
+            values,
+            array.nulls().cloned(),
+        ) {
+            return Arc::new(array);
+        }
+    }
+    cast(col, data_type).unwrap_or_else(|_| new_null_array(data_type, col.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::{Array, ArrayRef, Int64Array, StringArray, StructArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+
+    use super::adapt_batch;
+
+    #[test]
+    fn struct_children_added_since_are_null() {
+        let method = Field::new("method", DataType::Utf8, true);
+        let status = Field::new("status", DataType::Int64, true);
+        let old = StructArray::from(vec![(
+            Arc::new(method.clone()),
+            Arc::new(StringArray::from(vec!["GET", "PUT"])) as ArrayRef,
+        )]);
+        let old_schema = Schema::new(vec![Field::new("request", old.data_type().clone(), true)]);
+        let batch = RecordBatch::try_new(Arc::new(old_schema), vec![Arc::new(old)]).unwrap();
+
+        let request = DataType::Struct(vec![method, status].into());
+        let schema = Schema::new(vec![Field::new("request", request, true)]);
+        let adapted = adapt_batch(&schema, &batch);
+
+        let request = adapted
+            .column(0)
+            .as_any()
+            .downcast_ref::<StructArray>()
+            .unwrap();
+        let method = request
+            .column_by_name("method")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(method.value(1), "PUT");
+        let status = request
+            .column_by_name("status")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(status.null_count(), 2);
+    }
+}
@@ -17,20 +17,22 @@
  */
 
 const MAX_HEADERS_ALLOWED: usize = 10;
+use std::collections::BTreeMap;
+
 use actix_web::{HttpRequest, HttpResponse, ResponseError};
 
 pub fn collect_labelled_headers(
     req: &HttpRequest,
     prefix: &str,
     kv_separator: char,
-) -> Result<String, ParseHeaderError> {
+) -> Result<BTreeMap<String, String>, ParseHeaderError> {
     // filter out headers which has right prefix label and convert them into str;
     let headers = req.headers().iter().filter_map(|(key, value)| {
         let key = key.as_str().strip_prefix(prefix)?;
         Some((key, value))
     });
 
-    let mut labels: Vec<String> = Vec::new();
+    let mut labels = BTreeMap::new();
 
     for (key, value) in headers {
         let value = value.to_str().map_err(|_| ParseHeaderError::InvalidValue)?;
@@ -44,14 +46,14 @@ pub fn collect_labelled_headers(
             return Err(ParseHeaderError::SeperatorInValue(kv_separator));
         }
 
-        labels.push(format!("{key}={value}"));
+        labels.insert(key.to_owned(), value.to_owned());
     }
 
     if labels.len() > MAX_HEADERS_ALLOWED {
         return Err(ParseHeaderError::MaxHeadersLimitExceeded);
     }
 
-    Ok(labels.join(&kv_separator.to_string()))
+    Ok(labels)
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -21,10 +21,20 @@ use serde_json::Value;
 
 pub mod flatten;
 
+use crate::event::nested::NestedObjects;
+
 pub fn flatten_json_body(body: serde_json::Value) -> Result<Value, anyhow::Error> {
     flatten::flatten(body, "_")
 }
 
+// flattens the body except for the objects the stream keeps as structs or maps
+pub fn flatten_json_body_nested(
+    body: serde_json::Value,
+    nested: &NestedObjects,
+) -> Result<Value, anyhow::Error> {
+    flatten::flatten_nested(body, "_", nested)
+}
+
 pub fn convert_to_string(value: &Value) -> Value {
     match value {
         Value::Null => Value::String("null".to_owned()),
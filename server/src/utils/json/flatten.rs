@@ -21,11 +21,23 @@ use itertools::Itertools;
 use serde_json::map::Map;
 use serde_json::value::Value;
 
+use crate::event::nested::{self, NestedObjects, ObjectMode};
+
 pub fn flatten(nested_value: Value, separator: &str) -> Result<Value, anyhow::Error> {
+    flatten_nested(nested_value, separator, &NestedObjects::default())
+}
+
+// flattens the objects of the top level fields as per their mode, objects that are
+// kept as structs or maps are not flattened and neither are arrays of them
+pub fn flatten_nested(
+    nested_value: Value,
+    separator: &str,
+    nested: &NestedObjects,
+) -> Result<Value, anyhow::Error> {
     match nested_value {
         Value::Object(nested_dict) => {
             let mut map = Map::new();
-            flatten_object(&mut map, None, nested_dict, separator)?;
+            flatten_top_level(&mut map, nested_dict, separator, nested)?;
             Ok(Value::Object(map))
         }
         Value::Array(mut arr) => {
@@ -35,7 +47,7 @@ pub fn flatten(nested_value: Value, separator: &str) -> Result<Value, anyhow::Er
                 let Value::Object(obj) = value else {
                     return Err(anyhow!("Expected object in array of objects"));
                 };
-                flatten_object(&mut map, None, obj, separator)?;
+                flatten_top_level(&mut map, obj, separator, nested)?;
                 *_value = Value::Object(map);
             }
             Ok(Value::Array(arr))
@@ -44,6 +56,39 @@ pub fn flatten(nested_value: Value, separator: &str) -> Result<Value, anyhow::Er
     }
 }
 
+fn flatten_top_level(
+    map: &mut Map<String, Value>,
+    nested_dict: Map<String, Value>,
+    separator: &str,
+    nested: &NestedObjects,
+) -> Result<(), anyhow::Error> {
+    if nested.flatten_all() {
+        return flatten_object(map, None, nested_dict, separator);
+    }
+    for (key, value) in nested_dict {
+        let mode = nested.mode(&key);
+        match value {
+            Value::Object(obj) if mode != ObjectMode::Flatten => {
+                if let Some(value) = nested::keep_object(mode, obj) {
+                    map.insert(key, value);
+                }
+            }
+            Value::Array(values) if mode != ObjectMode::Flatten && nested::is_objects(&values) => {
+                let values = values
+                    .into_iter()
+                    .map(|value| match value {
+                        Value::Object(obj) => nested::keep_object(mode, obj).unwrap_or(Value::Null),
+                        value => value,
+                    })
+                    .collect();
+                map.insert(key, Value::Array(values));
+            }
+            value => flatten_object(map, None, Map::from_iter([(key, value)]), separator)?,
+        }
+    }
+    Ok(())
+}
+
 pub fn flatten_with_parent_prefix(
     nested_value: Value,
     prefix: &str,
@@ -152,7 +197,8 @@ pub fn flatten_array_objects(
 mod tests {
     use crate::utils::json::flatten::flatten_array_objects;
 
-    use super::flatten;
+    use super::{flatten, flatten_nested};
+    use crate::event::nested::NestedObjects;
     use serde_json::{json, Map, Value};
 
     #[test]
@@ -335,4 +381,25 @@ mod tests {
         assert_eq!(map.get("key.q.x").unwrap(), &json!([[1, 2], [1], null]));
         assert_eq!(map.get("key.r").unwrap(), &json!([null, 2, 3]));
     }
+
+    #[test]
+    fn arrays_of_objects_are_kept_for_structs_and_maps() {
+        let nested: NestedObjects = serde_json::from_value(json!({
+            "fields": { "items": "struct", "labels": "map" }
+        }))
+        .unwrap();
+        let obj = json!({
+            "items": [{"k": 1}, null, {"k": 2}],
+            "labels": [{"k": 1}, {"k": "x"}],
+            "other": [{"k": 1}, {"k": 2}]
+        });
+        assert_eq!(
+            flatten_nested(obj, "_", &nested).unwrap(),
+            json!({
+                "items": [{"k": 1}, null, {"k": 2}],
+                "labels": [{"k": "1"}, {"k": "x"}],
+                "other_k": [1, 2]
+            })
+        );
+    }
 }